@@ -1,12 +1,22 @@
 pub mod channels;
+mod blip;
+mod resampler;
 
 use crate::apu::channels::ChannelType;
 use crate::apu::channels::PulseChannel;
 use crate::apu::channels::WaveChannel;
 use crate::apu::channels::NoiseChannel;
+use crate::apu::blip::BlipBuffer;
+use crate::apu::resampler::Resampler;
 use crate::save_state::APUState;
 
-pub const AUDIO_BUFFER_SIZE: usize = 1024;
+pub const DEFAULT_BUFFER_SIZE: usize = 512; // In frames, a sample for each output channel
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const APU_FREQUENCY: u32 = 4194304; // The APU is ticked once per T-Cycle, even in double speed mode
+const NATIVE_DIVIDER: u16 = 32; // The mixed output is averaged over 32 T-Cycles
+const NATIVE_SAMPLE_RATE: u32 = APU_FREQUENCY / NATIVE_DIVIDER as u32; // 131072 Hz
+const MIX_INTERVAL: u16 = 4; // The channels are sampled every 4 T-Cycles (must be a power of 2)
+const BLIP_INTERVAL: u16 = 2; // The channels never change between 2 T-Cycles (must be a power of 2)
 
 pub struct APU {
 	callback: Box<dyn Fn(&[f32])>,
@@ -19,16 +29,28 @@ pub struct APU {
 	channel3: WaveChannel,
 	channel4: NoiseChannel,
 	mute_channels: [bool; 4],
+	channel_volumes: [f32; 4], // From 0.0 to 1.0, applied before mixing
+	master_volume: f32, // From 0.0 to 1.0
+	output_channels: usize, // 1 (mono) or 2 (stereo)
+	mono: bool, // Downmix to mono, even with 2 output channels
 
 	pub nr50: u8, // Master volume
 	pub nr51: u8, // Sound panning
 	pub nr52: u8, // Audio master control
 	div_apu: u8,
 	prev_div_apu: u8,
-	capacitor: f32,
+	capacitors: [f32; 2], // One for each stereo channel
+	charge_factor: f32, // For the high pass filter, depends on the sample rate
 	internal_cycles: u16, // Tracks the current cycle
+	mix_accumulator: (f32, f32), // Sum of the channel samples since the last native sample
+	resampler: Resampler,
+	resampled: Vec<(f32, f32)>, // Reused to avoid allocating for every native sample
+	band_limited: bool, // Synthesize the channels with band-limited steps instead of sampling them
+	blip_buffer: BlipBuffer,
+	blip_level: (f32, f32), // The mixed output at the last band-limited step
 
 	is_mute: bool,
+	output_enabled: bool, // False while frames are emulated silently (e.g. when rewinding)
 }
 
 impl APU {
@@ -40,7 +62,7 @@ impl APU {
 		let channel4 = NoiseChannel::new();
 		APU {
 			callback,
-			buffer: vec![0.0; AUDIO_BUFFER_SIZE],
+			buffer: vec![0.0; DEFAULT_BUFFER_SIZE * 2],
 			buffer_position: 0,
 			is_buffer_full: false,
 			
@@ -49,15 +71,27 @@ impl APU {
 			channel3,
 			channel4,
 			mute_channels: [false; 4],
+			channel_volumes: [1.0; 4],
+			master_volume: 1.0,
+			output_channels: 2,
+			mono: false,
 			
 			nr50: 0x77,
 			nr51: 0xF3,
 			nr52: 0xF1,
 			div_apu: 0,
 			prev_div_apu: 0,
-			capacitor: 0.0,
+			capacitors: [0.0; 2],
+			charge_factor: APU::charge_factor(DEFAULT_SAMPLE_RATE),
 			internal_cycles: 0,
+			mix_accumulator: (0.0, 0.0),
+			resampler: Resampler::new(NATIVE_SAMPLE_RATE as f64, DEFAULT_SAMPLE_RATE as f64),
+			resampled: Vec::new(),
+			band_limited: true,
+			blip_buffer: BlipBuffer::new(APU_FREQUENCY as f64, DEFAULT_SAMPLE_RATE as f64),
+			blip_level: (0.0, 0.0),
 			is_mute: false,
+			output_enabled: true,
 		}
 	}
 
@@ -68,7 +102,7 @@ impl APU {
 		self.channel3 = WaveChannel::new();
 		self.channel4 = NoiseChannel::new();
 
-		self.buffer = vec![0.0; AUDIO_BUFFER_SIZE];
+		self.buffer = vec![0.0; self.buffer.len()];
 		self.buffer_position = 0;
 		self.is_buffer_full = false;
 		
@@ -77,26 +111,94 @@ impl APU {
 		self.nr52 = 0xF1;
 		self.div_apu = 0;
 		self.prev_div_apu = 0;
-		self.capacitor = 0.0;
+		self.capacitors = [0.0; 2];
 		self.internal_cycles = 0;
+		self.mix_accumulator = (0.0, 0.0);
+		self.resampler.clear();
+		self.blip_buffer.clear();
+		self.blip_level = (0.0, 0.0);
+	}
+
+	// Changes the sample rate of the output
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.resampler.set_output_rate(sample_rate as f64);
+		self.blip_buffer.set_output_rate(sample_rate as f64);
+		self.charge_factor = APU::charge_factor(sample_rate);
+	}
+
+	// Changes the number of frames sent to the callback function at once
+	pub fn set_buffer_size(&mut self, buffer_size: usize) {
+		self.buffer = vec![0.0; buffer_size.max(1) * self.output_channels];
+		self.buffer_position = 0;
+	}
+
+	// Changes the number of output channels, either 1 (mono) or 2 (stereo)
+	pub fn set_output_channels(&mut self, channels: usize) {
+		let buffer_size = self.buffer.len() / self.output_channels;
+		self.output_channels = channels.clamp(1, 2);
+		self.set_buffer_size(buffer_size);
+	}
+
+	pub fn set_mono(&mut self, mono: bool) {
+		self.mono = mono;
+	}
+
+	pub fn set_master_volume(&mut self, volume: f32) {
+		self.master_volume = volume.clamp(0.0, 1.0);
+	}
+
+	pub fn set_channel_volume(&mut self, i: usize, volume: f32) {
+		self.channel_volumes[i] = volume.clamp(0.0, 1.0);
+	}
+
+	// Dynamic rate control: tells the resampler how full the frontend's audio buffer is (0.0 to 1.0)
+	pub fn set_buffer_fill(&mut self, fill: f32) {
+		self.resampler.set_buffer_fill(fill);
+		self.blip_buffer.set_buffer_fill(fill);
+	}
+
+	// Switches between band-limited synthesis and sampling the channels
+	pub fn set_band_limited(&mut self, enabled: bool) {
+		if enabled != self.band_limited {
+			self.band_limited = enabled;
+			self.resampler.clear();
+			self.blip_buffer.clear();
+			self.blip_level = (0.0, 0.0);
+			self.mix_accumulator = (0.0, 0.0);
+		}
+	}
+
+	// The capacitor of the high pass filter loses 0.999958 of its charge every T-Cycle
+	fn charge_factor(sample_rate: u32) -> f32 {
+		0.999958f64.powf(APU_FREQUENCY as f64 / sample_rate as f64) as f32
 	}
 
 	pub fn toggle_mute(&mut self) {
 		self.is_mute = !self.is_mute;
 	}
 
+	// Enables or disables sending samples to the callback function
+	pub fn set_output(&mut self, enabled: bool) {
+		self.output_enabled = enabled;
+	}
+
+	pub fn output_enabled(&self) -> bool {
+		self.output_enabled
+	}
+
 	pub fn toggle_channel(&mut self, i: usize) {
 		self.mute_channels[i] = !self.mute_channels[i];
 	}
 	
-	pub fn tick(&mut self, div: u8) {
+	pub fn tick(&mut self, div: u8, double_speed: bool) {
 		self.channel1.duty_cycle();
 		self.channel2.duty_cycle();
 		self.channel3.duty_cycle();
 		self.channel4.duty_cycle();
 
-		// Increment the DIV APU when detecting a falling edge
-		if self.is_enabled() && (self.prev_div_apu >> 4) & 0x01 == 1 && (div >> 4) & 0x01 == 0 {
+		// Increment the DIV APU when detecting a falling edge, of bit 5 instead of 4 in double speed
+		let div_bit = if double_speed {5} else {4};
+		if self.is_enabled() && (self.prev_div_apu >> div_bit) & 0x01 == 1 && (div >> div_bit) & 0x01 == 0 {
 			self.div_apu = self.div_apu.wrapping_add(1);
 			self.channel1.tick(self.div_apu);
 			self.channel2.tick(self.div_apu);
@@ -105,68 +207,108 @@ impl APU {
 		}
 		self.prev_div_apu = div;
 
-		// Magic number = (apu_freq / 44.1kHz), apu_freq = 2^222
-		if self.internal_cycles >= 95 {
+		self.internal_cycles += 1;
+		if self.band_limited {
+			if self.internal_cycles & (BLIP_INTERVAL - 1) == 0 {
+				self.push_blip_step();
+			}
+			if self.internal_cycles >= NATIVE_DIVIDER {
+				self.internal_cycles = 0;
+			}
+			return;
+		}
+
+		// Sample the channels at a high rate, and average them to get a native
+		// sample. Nothing is mixed while the output is disabled, e.g. when
+		// emulating without audio
+		if self.internal_cycles & (MIX_INTERVAL - 1) == 0 && self.output_enabled {
+			let (left, right) = self.mix();
+			self.mix_accumulator.0 += left;
+			self.mix_accumulator.1 += right;
+		}
+		if self.internal_cycles >= NATIVE_DIVIDER {
 			self.internal_cycles = 0;
-			self.mix();
+			let samples_per_native = (NATIVE_DIVIDER / MIX_INTERVAL) as f32;
+			let native_sample = (self.mix_accumulator.0 / samples_per_native,
+								 self.mix_accumulator.1 / samples_per_native);
+			self.mix_accumulator = (0.0, 0.0);
+			self.push_native_sample(native_sample);
 		}
-		self.internal_cycles += 1;
 	}
 
-	// Fills the audio buffer with a new sample
-	fn mix(&mut self) {
-		let channel1_sample = if self.channel1.active && !self.mute_channels[0] {
-			self.channel1.get_sample()
-		} else {
-			0.0
-		};
-		let channel2_sample = if self.channel2.active && !self.mute_channels[1] {
-			self.channel2.get_sample()
-		} else {
-			0.0
-		};
-		let channel3_sample = if self.channel3.active && !self.mute_channels[2] {
-			self.channel3.get_sample()
-		} else {
-			0.0
-		};
-		let channel4_sample = if self.channel4.active && !self.mute_channels[3] {
-			self.channel4.get_sample()
-		} else {
-			0.0
-		};
-
-		let mut left_mix_sample = match self.is_mute {
-			true => 0.0,
-			false => channel1_sample + channel2_sample
-			+ channel3_sample + channel4_sample,
-		};
-		let mut right_mix_sample = match self.is_mute {
-			true => 0.0,
-			false => channel1_sample + channel2_sample
-			+ channel3_sample + channel4_sample
-		} ;
-
-		left_mix_sample /= 4.0;
-		right_mix_sample /= 4.0;
-		
-		let ls = self.high_pass(left_mix_sample);
-		let rs = self.high_pass(right_mix_sample);
+	// Returns the current mixed sample of all channels
+	fn mix(&self) -> (f32, f32) {
+		if self.is_mute {
+			return (0.0, 0.0);
+		}
+		let samples = [
+			if self.channel1.active { self.channel1.get_sample() } else { 0.0 },
+			if self.channel2.active { self.channel2.get_sample() } else { 0.0 },
+			if self.channel3.active { self.channel3.get_sample() } else { 0.0 },
+			if self.channel4.active { self.channel4.get_sample() } else { 0.0 },
+		];
 
-		self.buffer[self.buffer_position] = ls;
-		self.buffer[self.buffer_position + 1] = rs;
-		self.buffer_position += 2;
-		
-		if self.buffer_position >= AUDIO_BUFFER_SIZE {
-			(self.callback)(self.buffer.as_ref());
-			self.buffer_position = 0;
+		let mut mix_sample = 0.0;
+		for (i, sample) in samples.iter().enumerate() {
+			if !self.mute_channels[i] {
+				mix_sample += sample * self.channel_volumes[i];
+			}
+		}
+		(mix_sample / 4.0, mix_sample / 4.0)
+	}
+
+	// Resamples a native sample, and fills the audio buffer with any new output samples
+	fn push_native_sample(&mut self, sample: (f32, f32)) {
+		if !self.output_enabled {
+			return;
+		}
+		self.resampler.push(sample, &mut self.resampled);
+		self.output_resampled();
+	}
+
+	// Adds a band-limited step if the mixed output changed, and fills the audio buffer with any new output samples
+	fn push_blip_step(&mut self) {
+		if !self.output_enabled {
+			return;
+		}
+		self.blip_buffer.advance(BLIP_INTERVAL as u32, &mut self.resampled);
+		let level = self.mix();
+		if level != self.blip_level {
+			self.blip_buffer.add_delta((level.0 - self.blip_level.0, level.1 - self.blip_level.1));
+			self.blip_level = level;
 		}
+		self.output_resampled();
+	}
+
+	// Fills the audio buffer with the new output samples
+	fn output_resampled(&mut self) {
+		for i in 0..self.resampled.len() {
+			let (left, right) = self.resampled[i];
+			let mut ls = self.high_pass(left, 0) * self.master_volume;
+			let mut rs = self.high_pass(right, 1) * self.master_volume;
+			if self.mono || self.output_channels == 1 {
+				ls = (ls + rs) / 2.0;
+				rs = ls;
+			}
+
+			self.buffer[self.buffer_position] = ls;
+			if self.output_channels == 2 {
+				self.buffer[self.buffer_position + 1] = rs;
+			}
+			self.buffer_position += self.output_channels;
+
+			if self.buffer_position >= self.buffer.len() {
+				(self.callback)(self.buffer.as_ref());
+				self.buffer_position = 0;
+			}
+		}
+		self.resampled.clear();
 	}
 
 	// Simulates a high pass filter
-	fn high_pass(&mut self, in_sample: f32) -> f32 {
-        let out = in_sample - self.capacitor;
-        self.capacitor = in_sample - out * 0.996;
+	fn high_pass(&mut self, in_sample: f32, channel: usize) -> f32 {
+        let out = in_sample - self.capacitors[channel];
+        self.capacitors[channel] = in_sample - out * self.charge_factor;
         out
     }
 	
@@ -303,7 +445,7 @@ impl APU {
 			nr52: self.nr52,
 			div_apu: self.div_apu,
 			prev_div_apu: self.prev_div_apu,
-			capacitor: self.capacitor,
+			capacitors: self.capacitors,
 			internal_cycles: self.internal_cycles,
 			is_mute: self.is_mute,
 		}
@@ -311,8 +453,14 @@ impl APU {
 
 	// Loads an APUState to the APU
 	pub fn load_state(&mut self, apu_state: APUState) {
-		self.buffer = apu_state.buffer.clone();
-		self.buffer_position = apu_state.buffer_position;
+		// The buffer's size depends on the frontend, and not on the state
+		if apu_state.buffer.len() == self.buffer.len() {
+			self.buffer = apu_state.buffer.clone();
+			self.buffer_position = apu_state.buffer_position;
+		} else {
+			self.buffer.fill(0.0);
+			self.buffer_position = 0;
+		}
 		self.is_buffer_full = apu_state.is_buffer_full;
 		self.channel1 = apu_state.channel1.clone();
 		self.channel2 = apu_state.channel2.clone();
@@ -324,7 +472,7 @@ impl APU {
 		self.nr52 = apu_state.nr52;
 		self.div_apu = apu_state.div_apu;
 		self.prev_div_apu = apu_state.prev_div_apu;
-		self.capacitor = apu_state.capacitor;
+		self.capacitors = apu_state.capacitors;
 		self.internal_cycles = apu_state.internal_cycles;
 		self.is_mute = apu_state.is_mute;
 	}
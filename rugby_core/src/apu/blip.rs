@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+
+use crate::apu::resampler::windowed_sinc;
+
+const TAPS: usize = 16; // Width of a band-limited step, in output samples
+const PHASES: usize = 64; // Number of precomputed fractional positions
+const CUTOFF: f64 = 0.90; // Cutoff as a fraction of the output's Nyquist frequency
+const MAX_RATE_ADJUSTMENT: f64 = 0.005; // Dynamic rate control never changes the pitch more than 0.5%
+
+// Blip buffer: instead of sampling the channels, every change of their output
+// is added as a band-limited step at its exact time, directly at the output
+// rate. The output is then the running sum of all steps, which has no
+// aliasing no matter how high pitched the channels are.
+pub struct BlipBuffer {
+	clock_rate: f64,
+	output_rate: f64,
+	rate_adjustment: f64,
+	samples_per_clock: f64,
+	kernel: Vec<f32>, // PHASES rows of TAPS coefficients, each row sums to 1
+	deltas: VecDeque<(f32, f32)>, // Pending steps, the front is the next output sample
+	time: f64, // Fractional position of the current clock, after the front of the deltas
+	integrator: (f32, f32),
+}
+
+impl BlipBuffer {
+	pub fn new(clock_rate: f64, output_rate: f64) -> Self {
+		let mut blip_buffer = BlipBuffer {
+			clock_rate,
+			output_rate,
+			rate_adjustment: 1.0,
+			samples_per_clock: 0.0,
+			kernel: BlipBuffer::build_kernel(),
+			deltas: VecDeque::from(vec![(0.0, 0.0); TAPS + 1]),
+			time: 0.0,
+			integrator: (0.0, 0.0),
+		};
+		blip_buffer.update_ratio();
+		blip_buffer
+	}
+
+	pub fn set_output_rate(&mut self, output_rate: f64) {
+		self.output_rate = output_rate;
+		self.update_ratio();
+	}
+
+	// Adjusts the ratio based on how full the frontend's audio buffer is,
+	// from 0.0 (empty) to 1.0 (full). Half full is the target.
+	pub fn set_buffer_fill(&mut self, fill: f32) {
+		let fill = fill.clamp(0.0, 1.0) as f64;
+		self.rate_adjustment = 1.0 + MAX_RATE_ADJUSTMENT * (2.0 * fill - 1.0);
+		self.update_ratio();
+	}
+
+	pub fn clear(&mut self) {
+		self.deltas = VecDeque::from(vec![(0.0, 0.0); TAPS + 1]);
+		self.time = 0.0;
+		self.integrator = (0.0, 0.0);
+	}
+
+	// Adds a step of the given amplitude (for each stereo channel) at the current clock
+	pub fn add_delta(&mut self, delta: (f32, f32)) {
+		let phase = ((self.time * PHASES as f64) as usize).min(PHASES - 1);
+		let row = &self.kernel[phase * TAPS..(phase + 1) * TAPS];
+		for (sample, &coefficient) in self.deltas.iter_mut().zip(row) {
+			sample.0 += delta.0 * coefficient;
+			sample.1 += delta.1 * coefficient;
+		}
+	}
+
+	// Moves the current clock forward, and appends every output sample that no longer changes
+	pub fn advance(&mut self, clocks: u32, output: &mut Vec<(f32, f32)>) {
+		self.time += clocks as f64 * self.samples_per_clock;
+		while self.time >= 1.0 {
+			self.time -= 1.0;
+			let (left, right) = self.deltas.pop_front().unwrap();
+			self.deltas.push_back((0.0, 0.0));
+			self.integrator.0 += left;
+			self.integrator.1 += right;
+			output.push(self.integrator);
+		}
+	}
+
+	fn update_ratio(&mut self) {
+		self.samples_per_clock = self.output_rate / (self.clock_rate * self.rate_adjustment);
+	}
+
+	// Precomputes the windowed sinc impulse for every fractional position
+	fn build_kernel() -> Vec<f32> {
+		let mut kernel = Vec::with_capacity(PHASES * TAPS);
+		for phase in 0..PHASES {
+			let fraction = phase as f64 / PHASES as f64;
+			let row: Vec<f64> = (0..TAPS).map(|i| {
+				windowed_sinc(i as f64 - (TAPS / 2) as f64 - fraction, CUTOFF, TAPS)
+			}).collect();
+			// Normalize every row, so every step has exactly the right amplitude
+			let sum: f64 = row.iter().sum();
+			kernel.extend(row.iter().map(|coefficient| (coefficient / sum) as f32));
+		}
+		kernel
+	}
+}
@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+const TAPS: usize = 32; // Length of the filter, in input samples
+const PHASES: usize = 256; // Number of precomputed fractional positions
+const CUTOFF: f64 = 0.90; // Cutoff as a fraction of the output's Nyquist frequency
+const MAX_RATE_ADJUSTMENT: f64 = 0.005; // Dynamic rate control never changes the pitch more than 0.5%
+
+// Band-limited resampler (windowed sinc), converting stereo samples from the
+// APU's native rate to any output rate. The ratio can be slightly adjusted on
+// the fly (dynamic rate control), so the frontend's audio buffer never runs
+// dry or overflows, even when the video is synced to the display instead.
+pub struct Resampler {
+	input_rate: f64,
+	output_rate: f64,
+	rate_adjustment: f64,
+	kernel: Vec<f32>, // (PHASES + 1) rows of TAPS coefficients
+	history: VecDeque<(f32, f32)>,
+	position: f64, // Position of the next output sample, between the two middle input samples
+}
+
+impl Resampler {
+	pub fn new(input_rate: f64, output_rate: f64) -> Self {
+		Resampler {
+			input_rate,
+			output_rate,
+			rate_adjustment: 1.0,
+			kernel: Resampler::build_kernel(input_rate, output_rate),
+			history: VecDeque::from(vec![(0.0, 0.0); TAPS]),
+			position: 0.0,
+		}
+	}
+
+	// Changes the output rate, and rebuilds the filter for its Nyquist frequency
+	pub fn set_output_rate(&mut self, output_rate: f64) {
+		self.output_rate = output_rate;
+		self.kernel = Resampler::build_kernel(self.input_rate, output_rate);
+	}
+
+	// Adjusts the ratio based on how full the frontend's audio buffer is,
+	// from 0.0 (empty) to 1.0 (full). Half full is the target.
+	pub fn set_buffer_fill(&mut self, fill: f32) {
+		let fill = fill.clamp(0.0, 1.0) as f64;
+		self.rate_adjustment = 1.0 + MAX_RATE_ADJUSTMENT * (2.0 * fill - 1.0);
+	}
+
+	// Clears the history, e.g. after a reset
+	pub fn clear(&mut self) {
+		self.history = VecDeque::from(vec![(0.0, 0.0); TAPS]);
+		self.position = 0.0;
+	}
+
+	// Pushes a new input sample, and appends every output sample that is ready to output
+	pub fn push(&mut self, sample: (f32, f32), output: &mut Vec<(f32, f32)>) {
+		self.history.pop_front();
+		self.history.push_back(sample);
+		self.position -= 1.0;
+
+		let step = self.input_rate / self.output_rate * self.rate_adjustment;
+		while self.position < 0.0 {
+			output.push(self.filter(self.position + 1.0));
+			self.position += step;
+		}
+	}
+
+	// Returns the filtered sample at a fractional position after the middle of the history
+	fn filter(&self, fraction: f64) -> (f32, f32) {
+		let phase = fraction * PHASES as f64;
+		let row = (phase as usize).min(PHASES - 1);
+		let weight = (phase - row as f64) as f32;
+		let row0 = &self.kernel[row * TAPS..(row + 1) * TAPS];
+		let row1 = &self.kernel[(row + 1) * TAPS..(row + 2) * TAPS];
+
+		let mut left = 0.0;
+		let mut right = 0.0;
+		for (i, &(l, r)) in self.history.iter().enumerate() {
+			let coefficient = row0[i] + (row1[i] - row0[i]) * weight;
+			left += l * coefficient;
+			right += r * coefficient;
+		}
+		(left, right)
+	}
+
+	// Precomputes the windowed sinc for every fractional position
+	fn build_kernel(input_rate: f64, output_rate: f64) -> Vec<f32> {
+		let cutoff = CUTOFF * (output_rate / input_rate).min(1.0);
+		let mut kernel = Vec::with_capacity((PHASES + 1) * TAPS);
+		for phase in 0..=PHASES {
+			let fraction = phase as f64 / PHASES as f64;
+			let row: Vec<f64> = (0..TAPS).map(|i| {
+				windowed_sinc(i as f64 - (TAPS / 2 - 1) as f64 - fraction, cutoff, TAPS)
+			}).collect();
+			// Normalize every row, so there is no gain and no ripple between phases
+			let sum: f64 = row.iter().sum();
+			kernel.extend(row.iter().map(|coefficient| (coefficient / sum) as f32));
+		}
+		kernel
+	}
+}
+
+// Returns the Blackman-windowed sinc at t (in samples from its center), for a
+// cutoff given as a fraction of the Nyquist frequency and a window of some taps
+pub fn windowed_sinc(t: f64, cutoff: f64, taps: usize) -> f64 {
+	let half = taps as f64 / 2.0;
+	let sinc = match t == 0.0 {
+		true => 1.0,
+		false => (PI * cutoff * t).sin() / (PI * cutoff * t),
+	};
+	let x = (t + half) / taps as f64; // From 0.0 to 1.0 over the window
+	let window = 0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos();
+	sinc * window.max(0.0)
+}
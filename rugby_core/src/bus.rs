@@ -1,30 +1,32 @@
 use crate::apu::APU;
 use crate::cartridge::Cartridge;
+use crate::dma::{Hdma, OamDma};
 use crate::gb_mode::GBMode;
 use crate::mmu::MMU;
+use crate::model::Model;
 use crate::ppu::PPU;
 use crate::save_state::BusState;
 
-struct HDMA {
-	hdma_index: usize,
-	hdma_length: usize,
-	hdma_active: bool,
-	src_address: u16,
-	dest_address: u16,	
-}
+const FRAME_DOTS: u32 = 70224;
+const SPEED_SWITCH_CYCLES: u16 = 2050; // M-Cycles the CPU is paused for after a speed switch
 
 pub struct Bus {
 	pub apu: APU,
 	pub mmu: MMU,
 	pub ppu: PPU,
+	oam_dma: OamDma,
 
 	// For Gameboy Color
 	gb_mode: GBMode,
-	hdma: [u8; 5],
-	dma_active: bool,
-	hdma_struct: HDMA,
+	hdma: Hdma,
 	pub key1: u8,
 	pub double_speed: bool,
+	speed_switch_cycles: u16,
+	stopped_dots: u32, // Dots spent in STOP mode, so we can still output frames
+
+	// Writes to these addresses are kept, for scripts to react to
+	pub watched_addresses: Vec<u16>,
+	pub watch_hits: Vec<(u16, u8)>,
 }
 
 impl Bus {
@@ -36,71 +38,87 @@ impl Bus {
 			apu,
 			mmu,
 			ppu,
+			oam_dma: OamDma::new(),
 
 			gb_mode: GBMode::DMG,
-			hdma: [0xFF; 5],
-			dma_active: false,
-			hdma_struct: HDMA {
-				hdma_index: 0,
-				hdma_length: 0,
-				hdma_active: false,
-				src_address: 0,
-				dest_address: 0,
-			},
+			hdma: Hdma::new(),
 			key1: 0x7E,
 			double_speed: false,
+			speed_switch_cycles: 0,
+			stopped_dots: 0,
+
+			watched_addresses: Vec::new(),
+			watch_hits: Vec::new(),
 		}
 	}
 
 	// Initializes Bus
-	pub fn initialize(&mut self, gb_mode: GBMode) {
+	pub fn initialize(&mut self, gb_mode: GBMode, model: Model) {
 		self.gb_mode = gb_mode;
 		
-		self.mmu.initialize(gb_mode);
+		self.mmu.initialize(gb_mode, model);
 		self.ppu.initialize(gb_mode);
 		self.apu.reset();
 		
-		self.dma_active = false;
-		self.hdma = [0xFF; 5];
-		self.hdma_struct = HDMA {
-			hdma_index: 0,
-			hdma_length: 0,
-			hdma_active: false,
-			src_address: 0,
-			dest_address: 0,
-		};
+		self.oam_dma = OamDma::new();
+		self.hdma = Hdma::new();
 		self.key1 = 0x7E;
+
+		// The registers that the boot ROMs of some models leave differently
+		if model.is_color() {
+			self.oam_dma.register = 0x00;
+		}
+		if model == Model::DMG0 {
+			self.ppu.stat = 0x81;
+		}
 		self.double_speed = false;
+		self.speed_switch_cycles = 0;
+		self.stopped_dots = 0;
 	}
 	
 	pub fn load_rom(&mut self, cartridge: Box<dyn Cartridge>) {
 		self.mmu = MMU::new(cartridge);
 	}
 	
-	pub fn tick(&mut self) {
+	// Advances every component by one M-Cycle, and then by the M-Cycles the CPU
+	// is stalled for a VRAM DMA. Returns the total number of M-Cycles.
+	pub fn tick(&mut self) -> u32 {
+		self.tick_components();
+		let mut cycles = 1;
+		while self.hdma.is_transferring() {
+			// 2 bytes are copied every M-Cycle, or 1 in double speed, so a block always takes 8 µs
+			let bytes_per_cycle = if self.double_speed {1} else {2};
+			for _ in 0..bytes_per_cycle {
+				if let Some((source, destination)) = self.hdma.step() {
+					let byte = self.hdma_read(source);
+					self.ppu.set_vram(destination as usize, byte);
+				}
+			}
+			self.tick_components();
+			cycles += 1;
+		}
+		cycles
+	}
+
+	fn tick_components(&mut self) {
 		let speed_factor = if self.double_speed {2} else {1};
 		for _ in 0..(4 / speed_factor) {
-			self.apu.tick(self.mmu.timer.div);
+			self.apu.tick(self.mmu.timer.div, self.double_speed);
 		}
 		for _ in 0..(4 / speed_factor) {
 			self.ppu.dot();
 		}
-		// Check for HDMA transfers
+		// Copy a byte for the OAM DMA
+		if let Some((source, index)) = self.oam_dma.step() {
+			let byte = self.dma_read(source);
+			self.ppu.set_oam(index, byte);
+			self.oam_dma.last_byte = byte;
+		}
+		// An HBlank DMA copies a block every HBlank, but there are none while the LCD is off
 		if self.ppu.entered_hblank {
 			self.ppu.entered_hblank = false;
-			if self.hdma_struct.hdma_active {
-				// Transfer a block of 16 bytes
-				for i in 0..16 {
-					let src_index = self.hdma_struct.src_address + self.hdma_struct.hdma_index as u16 + i;
-					let dest_index = self.hdma_struct.dest_address + self.hdma_struct.hdma_index as u16 + i;
-					let value = self.mmu.get_byte(src_index);
-					self.ppu.set_vram(dest_index as usize, value);
-				}
-				self.hdma_struct.hdma_index += 16;
-				if self.hdma_struct.hdma_index >= self.hdma_struct.hdma_length {
-					self.hdma_struct.hdma_active = false;
-					self.hdma[4] = 0xFF;
-				}
+			if !self.ppu.ppu_disabled {
+				self.hdma.hblank();
 			}
 		}
 		
@@ -116,13 +134,25 @@ impl Bus {
 			if_register = Bus::set_bit(if_register, 1, 1);
 			self.mmu.set_byte(0xFF0F, if_register);
 		}
-		self.mmu.timer.tick();
+		// DIV doesn't tick while the speed switches
+		if self.speed_switch_cycles > 0 {
+			self.speed_switch_cycles -= 1;
+		} else {
+			self.mmu.timer.tick();
+			self.mmu.serial.tick(self.mmu.timer.counter());
+		}
 		if self.mmu.timer.timer_interrupt == 1 {
 			self.mmu.timer.timer_interrupt = 0;
 			let mut if_register = self.mmu.get_byte(0xFF0F);
 			if_register = Bus::set_bit(if_register, 2, 1);
 			self.mmu.set_byte(0xFF0F, if_register);
 		}
+		if self.mmu.serial.serial_interrupt {
+			self.mmu.serial.serial_interrupt = false;
+			let mut if_register = self.mmu.get_byte(0xFF0F);
+			if_register = Bus::set_bit(if_register, 3, 1);
+			self.mmu.set_byte(0xFF0F, if_register);
+		}
 		if self.mmu.joypad_interrupt == true {
 			self.mmu.joypad_interrupt = false;
 			let mut if_register = self.mmu.get_byte(0xFF0F);
@@ -131,8 +161,44 @@ impl Bus {
 		}
 	}
 
+	// Called every M-Cycle in STOP mode, where every clock is stopped. The
+	// screen stays the same, but we still need to tell when a frame would be ready.
+	pub fn tick_stopped(&mut self) {
+		let speed_factor = if self.double_speed {2} else {1};
+		self.stopped_dots += 4 / speed_factor;
+		if self.stopped_dots >= FRAME_DOTS {
+			self.stopped_dots = 0;
+			self.ppu.frame_ready = true;
+		}
+	}
+
+	// Called by the STOP instruction. Switches the speed if KEY1 was armed and
+	// returns true, otherwise the CPU should enter STOP mode. DIV is reset in both cases.
+	pub fn stop(&mut self) -> bool {
+		self.mmu.timer.reset_timer();
+		if self.gb_mode == GBMode::CGB && self.key1 & 0x01 == 1 {
+			self.double_speed = !self.double_speed;
+			self.key1 &= 0xFE;
+			self.speed_switch_cycles = SPEED_SWITCH_CYCLES;
+			return true;
+		}
+		false
+	}
+
+	// True while the CPU is paused after a speed switch
+	pub fn is_switching_speed(&self) -> bool {
+		self.speed_switch_cycles > 0
+	}
+
 	// Get 8-bit value from memory at a specific address
 	pub fn get_byte(&self, address: u16) -> u8 {
+		// During OAM DMA, the CPU reads whatever the DMA is reading, and OAM is unavailable
+		if self.oam_dma.is_blocking() && address < 0xFF00 {
+			return match address {
+				0xFE00..=0xFEFF => 0xFF,
+				_ => self.oam_dma.last_byte,
+			};
+		}
 		match address {
 			0x8000..=0x9FFF => self.ppu.get_vram(address as usize - 0x8000),
 			0xFE00..=0xFE9F => self.ppu.get_oam(address as usize - 0xFE00),
@@ -143,6 +209,7 @@ impl Bus {
 			0xFF43 => self.ppu.scx,
 			0xFF44 => self.ppu.ly,
 			0xFF45 => self.ppu.lyc,
+			0xFF46 => self.oam_dma.register,
 			0xFF47 => self.ppu.bgp,
 			0xFF48 => self.ppu.obp0,
 			0xFF49 => self.ppu.obp1,
@@ -156,19 +223,10 @@ impl Bus {
 				},
 			},
 			0xFF4F => self.ppu.vbk | 0xFE,
-			0xFF51..=0xFF54 => match self.gb_mode {
-				GBMode::DMG => 0xFF,
-				GBMode::CGB => self.hdma[address as usize - 0xFF51],
-			},
+			0xFF51..=0xFF54 => 0xFF, // Write-only
 			0xFF55 => match self.gb_mode {
 				GBMode::DMG => 0xFF,
-				GBMode::CGB => match self.hdma_struct.hdma_active {
-					true => {
-						let value = (self.hdma_struct.hdma_length / 0x10) - 1;
-						value as u8
-					},
-					false => 0xFF,
-				},
+				GBMode::CGB => self.hdma.get_hdma5(),
 			}
 			0xFF68 => match self.ppu.gb_mode {
 				GBMode::DMG => 0xFF,
@@ -199,6 +257,13 @@ impl Bus {
 	
 	// Set an 8-bit value at a specific address in memory
 	pub fn set_byte(&mut self, address: u16, value: u8) {
+		// During OAM DMA, the CPU can only write to HRAM and the I/O registers
+		if self.oam_dma.is_blocking() && address < 0xFF00 {
+			return;
+		}
+		if self.watched_addresses.contains(&address) {
+			self.watch_hits.push((address, value));
+		}
 		match address {
 			0x8000..=0x9FFF => self.ppu.set_vram(address as usize - 0x8000, value),
 			0xFE00..=0xFE9F => self.ppu.set_oam(address as usize - 0xFE00, value),
@@ -208,56 +273,27 @@ impl Bus {
 			0xFF42 => self.ppu.scy = value,
 			0xFF43 => self.ppu.scx = value,
 			0xFF45 => self.ppu.lyc = value,
-			0xFF46 => {
-				self.dma_active = true;
-				self.dma_transfer(value);
-				self.dma_active = false;
-			},
+			0xFF46 => self.oam_dma.start(value),
 			0xFF47 => self.ppu.bgp = value,
 			0xFF48 => self.ppu.obp0 = value,
 			0xFF49 => self.ppu.obp1 = value,
 			0xFF4A => self.ppu.wy = value,
 			0xFF4B => self.ppu.wx = value,
-			0xFF4D => self.key1 = value,
+			0xFF4D => match self.gb_mode {
+				GBMode::DMG => (),
+				GBMode::CGB => self.key1 = value & 0x01,
+			},
 			0xFF4F => self.ppu.vbk = value & 0x01,
 			0xFF51..=0xFF54 => match self.gb_mode {
 				GBMode::DMG => (),
-				GBMode::CGB => self.hdma[address as usize - 0xFF51] = value,
+				GBMode::CGB => self.hdma.set_register(address, value),
 			},
 			0xFF55 => match self.gb_mode {
 				GBMode::DMG => (),
-				GBMode::CGB => {
-					self.hdma[4] = value;
-					let dma_length = ((self.hdma[4] & 0x7F) as u16 + 1) * 0x10;
-					let dma_mode = self.hdma[4] & 0x80;
-					if self.hdma_struct.hdma_active && dma_mode == 0 {
-						self.hdma_struct.hdma_active = false;
-					} else {
-						let high_byte = self.hdma[0];
-						let low_byte = self.hdma[1];
-						let src_address = ((high_byte as u16) << 8) | (low_byte as u16 & 0xF0);
-
-						let high_byte = self.hdma[2];
-						let low_byte = self.hdma[3];
-						let dest_address = ((high_byte as u16 & 0x1F) << 8) | (low_byte as u16 & 0xF0);
-						
-						if dma_mode != 0 {
-							self.hdma_struct.hdma_length = dma_length as usize;
-							self.hdma_struct.hdma_index = 0;
-							self.hdma_struct.hdma_active = true;
-							self.hdma_struct.src_address = src_address;
-							self.hdma_struct.dest_address = dest_address;
-						} else {
-							// General-purpose DMA
-							for i in 0..dma_length {
-								let value = self.mmu.get_byte(src_address + i);
-								self.ppu.set_vram(dest_address as usize + i as usize, value);
-							}
-						}
-					}
-
-				},
+				GBMode::CGB => self.hdma.set_hdma5(value, !self.ppu.ppu_disabled),
 			},
+			// Color RAM is locked for DMG games, even in compatibility mode
+			0xFF68..=0xFF6B if self.gb_mode == GBMode::DMG => (),
 			0xFF68 => self.ppu.bgpi = value,
 			0xFF6A => self.ppu.obpi = value,
 			0xFF69 | 0xFF6B => self.ppu.set_palette(address as usize, value),
@@ -265,16 +301,24 @@ impl Bus {
 		}
 	}
 
-	// DMA transfer
-	// TODO: Make the timing correct
-	fn dma_transfer(&mut self, value: u8) {
-		let base_address = (value as u16) << 8;
-		for i in 0..=0x9F {
-			let byte = self.get_byte(base_address + i);
-			self.set_byte(0xFE00+i, byte);
+	// Reads a byte for a DMA transfer, which the CPU's restrictions don't apply to
+	fn dma_read(&self, address: u16) -> u8 {
+		match address {
+			0x8000..=0x9FFF => self.ppu.get_vram(address as usize - 0x8000),
+			_ => self.mmu.get_byte(address),
 		}
 	}
-		
+
+	// Reads a byte for a VRAM DMA. VRAM can't be a source, and sources after
+	// 0xDFFF read from external RAM instead.
+	fn hdma_read(&self, address: u16) -> u8 {
+		match address {
+			0x8000..=0x9FFF => 0xFF,
+			0xE000..=0xFFFF => self.mmu.get_byte(address - 0x4000),
+			_ => self.mmu.get_byte(address),
+		}
+	}
+
 	// Set a bit in a u8
 	fn set_bit(value: u8, bit_position: u8, bit_value: u8) -> u8 {
 		let new_value = match bit_value {
@@ -288,16 +332,27 @@ impl Bus {
 	// Creates a BusState from the Bus
 	pub fn create_state(&self) -> BusState {
 		BusState {
+			oam_dma: self.oam_dma,
+			hdma: self.hdma,
+			key1: self.key1,
+			double_speed: self.double_speed,
+			speed_switch_cycles: self.speed_switch_cycles,
 			mmu_state: self.mmu.create_state(),
 			ppu_state: self.ppu.create_state(),
 			apu_state: self.apu.create_state(),
 		}
 	}
 
-	// Loads a BusState to the Bus
-	pub fn load_state(&mut self, bus_state: BusState) {
-		self.mmu.load_state(bus_state.mmu_state);
+	// Loads a BusState to the Bus, starting with the MMU since its state can be invalid
+	pub fn load_state(&mut self, bus_state: BusState) -> Result<(), String> {
+		self.mmu.load_state(bus_state.mmu_state)?;
+		self.oam_dma = bus_state.oam_dma;
+		self.hdma = bus_state.hdma;
+		self.key1 = bus_state.key1;
+		self.double_speed = bus_state.double_speed;
+		self.speed_switch_cycles = bus_state.speed_switch_cycles;
 		self.ppu.load_state(bus_state.ppu_state);
 		self.apu.load_state(bus_state.apu_state);
+		Ok(())
 	}
 }
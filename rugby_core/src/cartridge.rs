@@ -6,7 +6,6 @@ pub mod mbc5;
 
 use std::path::PathBuf;
 
-use crate::gb_mode::GBMode;
 use romonly::RomOnly;
 use mbc1::MBC1;
 use mbc2::MBC2;
@@ -16,14 +15,15 @@ use mbc5::MBC5;
 pub const ROM_BANK_SIZE: usize = 16384;
 pub const RAM_BANK_SIZE: usize = 8192;
 
-// Returns a cartridge, and whether it is for Gameboy or Gameboy Color
-pub fn load(data_buffer_opt: Option<Vec<u8>>, path_buf: Option<PathBuf>) -> (Box<dyn Cartridge>, GBMode) {
+// Returns the cartridge of a ROM, which keeps its save file next to the ROM's
+// path, if there is one. Without a ROM, returns a blank cartridge
+pub fn load(data_buffer_opt: Option<Vec<u8>>, path_buf: Option<PathBuf>) -> Box<dyn Cartridge> {
 
 	// If no path is given, create a dummy ROM
 	if data_buffer_opt.is_none() {
 		let data_buffer = vec![0u8; 32768];
 		let cartridge = Box::new(RomOnly::new(&data_buffer));
-		return (cartridge, GBMode::DMG);
+		return cartridge;
 	}
 
 	let save_path = match path_buf {
@@ -51,15 +51,9 @@ pub fn load(data_buffer_opt: Option<Vec<u8>>, path_buf: Option<PathBuf>) -> (Box
         _ => unreachable!("Cartridge::load(), ram_banks"),
     };
 
-	let gb_mode = match data_buffer[0x0143] {
-		0x80 | 0xC0 => GBMode::CGB,
-		_ => GBMode::DMG,
-	};
-	
     // println!("Cartridge type: {:02X}", cartridge_type);
     // println!("ROM size: {}", _rom_size);
     // println!("Number of RAM banks: {}", ram_banks);
-	// println!("GBMode: {:?}", gb_mode);
     let cartridge: Box<dyn Cartridge> = match cartridge_type {
         0x00 => Box::new(RomOnly::new(&data_buffer)),
         0x01 | 0x02 => Box::new(MBC1::new(&data_buffer, ram_banks, None)),
@@ -75,7 +69,12 @@ pub fn load(data_buffer_opt: Option<Vec<u8>>, path_buf: Option<PathBuf>) -> (Box
 		0x1E => Box::new(MBC5::new(&data_buffer, ram_banks, save_path)),
         _ => unreachable!("Cartridge::load()"),
     };
-    (cartridge, gb_mode)
+    cartridge
+}
+
+// Returns whether the external RAM of a cartridge type is kept by a battery
+pub fn has_battery(cartridge_type: u8) -> bool {
+	matches!(cartridge_type, 0x03 | 0x06 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E)
 }
 
 pub trait Cartridge {
@@ -83,7 +82,13 @@ pub trait Cartridge {
     fn write(&mut self, address: u16, value: u8);
 	fn save(&mut self);
 	fn update_clock(&mut self);
+	// The external RAM, like it is written to the .sav file. It is only ever
+	// changed in place, so frontends can keep a pointer to it
+	fn ram(&mut self) -> &mut [u8];
 
 	fn create_state(&self) -> String;
 	fn load_state(&mut self, json_string: &str);
+	// The same state in a compact binary format, for rewinding and snapshots
+	fn create_snapshot(&self) -> Vec<u8>;
+	fn load_snapshot(&mut self, snapshot: &[u8]) -> Result<(), String>;
 }
@@ -31,6 +31,15 @@ pub struct MBC1 {
 }
 
 impl MBC1 {
+	// Loads the state of a save state or a snapshot, keeping the ROM
+	fn restore(&mut self, state: MBC1) {
+		self.ram.clone_from(&state.ram);
+		self.ram_enable = state.ram_enable;
+		self.rom_bank_number = state.rom_bank_number;
+		self.ram_bank_number = state.ram_bank_number;
+		self.rom_bit_mask = state.rom_bit_mask;
+	}
+
     pub fn new(data_buffer: &Vec<u8>, ram_banks: usize, save_path: Option<PathBuf>) -> Self {
         let mut rom: Vec<u8> = Vec::new();
         rom.extend_from_slice(data_buffer);
@@ -189,22 +198,30 @@ impl Cartridge for MBC1 {
 		
 	}
 
+	fn ram(&mut self) -> &mut [u8] {
+		&mut self.ram
+	}
+
 	fn create_state(&self) -> String {
 		serde_json::to_string(&self).unwrap()
 	}
 
 	fn load_state(&mut self, json_string: &str) {
 		match serde_json::from_str::<MBC1>(json_string) {
-            Ok(state) => {
-				self.ram = state.ram.clone();
-				self.ram_enable = state.ram_enable;
-				self.rom_bank_number = state.rom_bank_number;
-				self.ram_bank_number = state.ram_bank_number;
-				self.rom_bit_mask = state.rom_bit_mask;
-            },
+            Ok(state) => self.restore(state),
             Err(e) => {
                 eprintln!("Failed to deserialize state: {}", e);
             }
         }
 	}
+
+	fn create_snapshot(&self) -> Vec<u8> {
+		bincode::serialize(&self).unwrap()
+	}
+
+	fn load_snapshot(&mut self, snapshot: &[u8]) -> Result<(), String> {
+		let state = bincode::deserialize::<MBC1>(snapshot).map_err(|e| e.to_string())?;
+		self.restore(state);
+		Ok(())
+	}
 }
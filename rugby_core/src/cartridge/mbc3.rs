@@ -139,6 +139,18 @@ pub struct MBC3 {
 }
 
 impl MBC3 {
+	// Loads the state of a save state or a snapshot, keeping the ROM
+	fn restore(&mut self, state: MBC3) {
+		self.ram.clone_from(&state.ram);
+		self.ram_timer_enable = state.ram_timer_enable;
+		self.rom_bank_number = state.rom_bank_number;
+		self.ram_bank_number = state.ram_bank_number;
+		if let Some(clock) = self.clock.as_mut() {
+			*clock = state.clock.unwrap().clone();
+		}
+		self.latch_clock_00= state.latch_clock_00;
+	}
+
     pub fn new(data_buffer: &Vec<u8>, ram_banks: usize, save_path: Option<PathBuf>, has_clock: bool) -> Self {
         let mut rom: Vec<u8> = Vec::new();
         rom.extend_from_slice(data_buffer);
@@ -316,26 +328,31 @@ impl Cartridge for MBC3 {
 		}
 	}
 
+	fn ram(&mut self) -> &mut [u8] {
+		&mut self.ram
+	}
+
 	fn create_state(&self) -> String {
 		serde_json::to_string(&self).unwrap()
 	}
 
 	fn load_state(&mut self, json_string: &str) {
 		match serde_json::from_str::<MBC3>(json_string) {
-            Ok(state) => {
-				self.ram = state.ram.clone();
-				self.ram_timer_enable = state.ram_timer_enable;
-				self.rom_bank_number = state.rom_bank_number;
-				self.ram_bank_number = state.ram_bank_number;
-				if let Some(clock) = self.clock.as_mut() {
-					*clock = state.clock.unwrap().clone();
-				}
-				self.latch_clock_00= state.latch_clock_00;
-            },
+            Ok(state) => self.restore(state),
             Err(e) => {
                 eprintln!("Failed to deserialize state: {}", e);
             }
         }
 	}
+
+	fn create_snapshot(&self) -> Vec<u8> {
+		bincode::serialize(&self).unwrap()
+	}
+
+	fn load_snapshot(&mut self, snapshot: &[u8]) -> Result<(), String> {
+		let state = bincode::deserialize::<MBC3>(snapshot).map_err(|e| e.to_string())?;
+		self.restore(state);
+		Ok(())
+	}
 	
 }
@@ -40,6 +40,10 @@ impl Cartridge for RomOnly {
 		
 	}
 
+	fn ram(&mut self) -> &mut [u8] {
+		&mut []
+	}
+
 	fn create_state(&self) -> String {
 		"".to_string()
 	}
@@ -47,4 +51,12 @@ impl Cartridge for RomOnly {
 	fn load_state(&mut self, _json_string: &str) {
 		
 	}
+
+	fn create_snapshot(&self) -> Vec<u8> {
+		Vec::new()
+	}
+
+	fn load_snapshot(&mut self, _snapshot: &[u8]) -> Result<(), String> {
+		Ok(())
+	}
 }
@@ -0,0 +1,192 @@
+// A single decoded cheat code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatCode {
+	// Writes a value to RAM after every frame. Written as TTVVLLHH: a type
+	// (01 for most codes, 8X or 9X for WRAM bank X), the value, and the
+	// address with its low byte first
+	GameShark { bank: Option<u8>, address: u16, value: u8 },
+	// Replaces the value read from the ROM at an address, only if the ROM has
+	// the compare value there (so it doesn't affect other banks). Written as
+	// ABC-DEF, or ABC-DEF-GHI with the compare value
+	GameGenie { address: u16, value: u8, compare: Option<u8> },
+}
+
+// A named group of codes, that is enabled or disabled at once
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+	pub name: String,
+	pub code: String, // One or more codes, joined with +
+	pub enabled: bool,
+}
+
+impl CheatCode {
+	pub fn parse(code: &str) -> Result<CheatCode, String> {
+		let code = code.trim().to_ascii_uppercase();
+		let digits: Vec<u8> = code.chars().filter(|&c| c != '-')
+			.map(|c| c.to_digit(16).map(|digit| digit as u8).ok_or(format!("Invalid character '{}' in {}", c, code)))
+			.collect::<Result<_, _>>()?;
+
+		match (code.contains('-'), digits.len()) {
+			(false, 8) => {
+				let byte = |i: usize| (digits[i] << 4) | digits[i + 1];
+				let bank = match byte(0) & 0xF0 {
+					0x80 | 0x90 => Some(byte(0) & 0x07),
+					_ => None,
+				};
+				let address = ((byte(6) as u16) << 8) | byte(4) as u16;
+				// Writes to the ROM would switch banks instead
+				if address < 0x8000 {
+					return Err(format!("{} doesn't write to RAM", code));
+				}
+				Ok(CheatCode::GameShark {
+					bank,
+					address,
+					value: byte(2),
+				})
+			},
+			(true, 6) | (true, 9) => {
+				let address = (((digits[5] ^ 0x0F) as u16) << 12) | ((digits[2] as u16) << 8)
+					| ((digits[3] as u16) << 4) | digits[4] as u16;
+				if address >= 0x8000 {
+					return Err(format!("{} doesn't patch the ROM", code));
+				}
+				// The 8th digit is unused
+				let compare = match digits.len() {
+					9 => Some(((digits[6] << 4) | digits[8]).rotate_right(2) ^ 0xBA),
+					_ => None,
+				};
+				Ok(CheatCode::GameGenie {
+					address,
+					value: (digits[0] << 4) | digits[1],
+					compare,
+				})
+			},
+			_ => Err(format!("{} is neither a GameShark nor a Game Genie code", code)),
+		}
+	}
+}
+
+impl Cheat {
+	pub fn codes(&self) -> Result<Vec<CheatCode>, String> {
+		self.code.split('+').map(CheatCode::parse).collect()
+	}
+}
+
+// Returns the value read from the ROM, after the Game Genie codes
+pub fn patch_rom(codes: &[CheatCode], address: u16, value: u8) -> u8 {
+	for code in codes {
+		if let CheatCode::GameGenie { address: patched, value: new_value, compare } = *code {
+			if patched == address && compare.is_none_or(|compare| compare == value) {
+				return new_value;
+			}
+		}
+	}
+	value
+}
+
+// Cheat files have a cheat per line, like "[x] 010F56C1 Infinite lives",
+// with [ ] for disabled cheats. Lines starting with # are comments
+pub fn cheats_to_text(cheats: &[Cheat]) -> String {
+	let mut text = String::from("# Rugby cheats: [x] for enabled or [ ] for disabled, the codes joined with +, and the name\n");
+	for cheat in cheats {
+		let enabled = if cheat.enabled {"[x]"} else {"[ ]"};
+		text += format!("{} {} {}", enabled, cheat.code, cheat.name).trim_end();
+		text += "\n";
+	}
+	text
+}
+
+pub fn cheats_from_text(text: &str) -> Vec<Cheat> {
+	let mut cheats = Vec::new();
+	for line in text.lines() {
+		let (enabled, rest) = match line.trim() {
+			line if line.starts_with("[x]") || line.starts_with("[X]") => (true, &line[3..]),
+			line if line.starts_with("[ ]") => (false, &line[3..]),
+			_ => continue,
+		};
+		let (code, name) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+		cheats.push(Cheat {
+			name: name.trim().to_string(),
+			code: code.to_string(),
+			enabled,
+		});
+	}
+	cheats
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_gameshark_codes() {
+		assert_eq!(CheatCode::parse("010F56C1"), Ok(CheatCode::GameShark { bank: None, address: 0xC156, value: 0x0F }));
+		assert_eq!(CheatCode::parse(" 01ff11c1 "), Ok(CheatCode::GameShark { bank: None, address: 0xC111, value: 0xFF }));
+		// 8X and 9X write to a WRAM bank
+		assert_eq!(CheatCode::parse("9163A5D2"), Ok(CheatCode::GameShark { bank: Some(1), address: 0xD2A5, value: 0x63 }));
+		assert_eq!(CheatCode::parse("830041DA"), Ok(CheatCode::GameShark { bank: Some(3), address: 0xDA41, value: 0x00 }));
+	}
+
+	#[test]
+	fn rejects_gameshark_codes_below_ram() {
+		for code in ["01010020", "01FF0000", "0100FF7F"] {
+			assert!(CheatCode::parse(code).is_err(), "{}", code);
+		}
+		// VRAM is the first address that isn't in the ROM
+		assert!(CheatCode::parse("01000080").is_ok());
+	}
+
+	#[test]
+	fn parses_game_genie_codes() {
+		// The address is the 6th digit xor F, then the 3rd, 4th and 5th digits
+		assert_eq!(CheatCode::parse("00A-17B"), Ok(CheatCode::GameGenie { address: 0x4A17, value: 0x00, compare: None }));
+		assert_eq!(CheatCode::parse("3E0-4DF"), Ok(CheatCode::GameGenie { address: 0x004D, value: 0x3E, compare: None }));
+		// The compare value is the 7th and 9th digits, rotated right by 2 and xored with BA
+		assert_eq!(CheatCode::parse("00A-17B-C49"), Ok(CheatCode::GameGenie { address: 0x4A17, value: 0x00, compare: Some(0xC8) }));
+		assert_eq!(CheatCode::parse("fa2-60e-e6e"), Ok(CheatCode::GameGenie { address: 0x1260, value: 0xFA, compare: Some(0x01) }));
+	}
+
+	#[test]
+	fn rejects_invalid_codes() {
+		// Game Genie codes only patch the ROM
+		assert!(CheatCode::parse("00A-170").is_err());
+		for code in ["", "010F56C", "010F56C1F", "010G56C1", "00A-17B-C4", "00A17B"] {
+			assert!(CheatCode::parse(code).is_err(), "{}", code);
+		}
+	}
+
+	#[test]
+	fn patches_the_rom_with_the_compare_value() {
+		let codes = [
+			CheatCode::GameGenie { address: 0x4A17, value: 0x00, compare: Some(0xC8) },
+			CheatCode::GameGenie { address: 0x0150, value: 0x3E, compare: None },
+		];
+		assert_eq!(patch_rom(&codes, 0x4A17, 0xC8), 0x00);
+		assert_eq!(patch_rom(&codes, 0x4A17, 0x12), 0x12); // Another bank
+		assert_eq!(patch_rom(&codes, 0x0150, 0x12), 0x3E);
+		assert_eq!(patch_rom(&codes, 0x0151, 0x12), 0x12);
+	}
+
+	#[test]
+	fn cheat_file_round_trip() {
+		let cheats = vec![
+			Cheat { name: "Infinite lives".to_string(), code: "010F56C1".to_string(), enabled: true },
+			Cheat { name: "Max money".to_string(), code: "019947D3+019948D3".to_string(), enabled: false },
+			Cheat { name: String::new(), code: "00A-17B-C49".to_string(), enabled: true },
+		];
+		let text = cheats_to_text(&cheats);
+		assert!(text.contains("[x] 010F56C1 Infinite lives\n"));
+		assert!(text.contains("[ ] 019947D3+019948D3 Max money\n"));
+		assert!(text.contains("[x] 00A-17B-C49\n"));
+		assert_eq!(cheats_from_text(&text), cheats);
+	}
+
+	#[test]
+	fn reads_cheat_files_written_by_hand() {
+		let text = "# Comment\n\n  [X] 010F56C1   Infinite  lives \nnot a cheat\n[ ]  00A-17B\n";
+		assert_eq!(cheats_from_text(text), vec![
+			Cheat { name: "Infinite  lives".to_string(), code: "010F56C1".to_string(), enabled: true },
+			Cheat { name: String::new(), code: "00A-17B".to_string(), enabled: false },
+		]);
+	}
+}
@@ -14,3 +14,33 @@ pub enum LogicalColor {
 	DarkGray,
 	Black,
 }
+
+impl Color {
+	// Packs the color into 16 bits, for snapshots. RGB colors only use 15 bits,
+	// so the highest one marks logical colors
+	pub fn to_bits(self) -> u16 {
+		match self {
+			Color::Logical(color) => 0x8000 | color as u16,
+			Color::RGB(rgb) => rgb & 0x7FFF,
+		}
+	}
+
+	pub fn from_bits(bits: u16) -> Self {
+		match bits {
+			0x8000 => Color::Logical(LogicalColor::White),
+			0x8001 => Color::Logical(LogicalColor::LightGray),
+			0x8002 => Color::Logical(LogicalColor::DarkGray),
+			0x8003.. => Color::Logical(LogicalColor::Black),
+			_ => Color::RGB(bits),
+		}
+	}
+}
+
+// The order of the bytes of a pixel in the framebuffer. Seen as a u32, the
+// channels are in the opposite order on little-endian machines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum PixelFormat {
+	RGBA8888, // e.g. for egui and the canvas on the web
+	BGRA8888, // e.g. for XRGB8888 surfaces
+}
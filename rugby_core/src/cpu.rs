@@ -18,6 +18,7 @@ pub struct CPU {
 	ime_scheduled: bool,
 	halt_mode: bool,
 	halt_bug: bool,
+	stop_mode: bool,
 	rtc_oscillator: u64,
 
 	// Required for Gameboy Color emulation
@@ -39,25 +40,22 @@ impl CPU {
 			ime_scheduled: false,
 			halt_mode: false,
 			halt_bug: false,
+			stop_mode: false,
 			rtc_oscillator: 0,
 
 			gb_mode: GBMode::DMG,
 		}
 	}
 
-	// Initializes registers, PC, and lookup table
-	pub fn initialize(&mut self, gb_mode: GBMode) {
+	// Initializes registers (to the values the boot ROM leaves), PC, and lookup table
+	pub fn initialize(&mut self, gb_mode: GBMode, cpu_registers: [u8; 10]) {
 		self.gb_mode = gb_mode;
-		self.cpu_registers = match self.gb_mode {
-			GBMode::DMG => [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D,
-							0xFF, 0xFE],
-			GBMode::CGB => [0x11, 0x40, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D,
-							  0xFF, 0xFE],
-		};
+		self.cpu_registers = cpu_registers;
 		self.pc = 0x100;
 		self.ime = 0;
 		self.halt_mode = false;
 		self.halt_bug = false;
+		self.stop_mode = false;
 		self.build_lookup_tables();
 	}
 
@@ -77,6 +75,19 @@ impl CPU {
 	
 	// Fetches and executes the next instruction 
 	pub fn step(&mut self, bus: &mut Bus) {
+		// The CPU is paused for a while after a speed switch
+		if bus.is_switching_speed() {
+			self.tick(bus);
+			return;
+		}
+		// In STOP mode, only a button press wakes the CPU up
+		if self.stop_mode {
+			if bus.get_byte(0xFF00) & 0x0F == 0x0F {
+				bus.tick_stopped();
+				return;
+			}
+			self.stop_mode = false;
+		}
 		let opcode = bus.get_byte(self.pc);
 		#[cfg(feature = "debug")]
 		{
@@ -118,11 +129,12 @@ impl CPU {
 
 	// Increments all parts by one M-Cycle
 	pub fn tick(&mut self, bus: &mut Bus) {
-		bus.tick();
-		self.rtc_oscillator += 1;
-		if self.rtc_oscillator % 1048576 == 0 {
+		// The bus might take longer, if the CPU is stalled by a VRAM DMA
+		let cycles = bus.tick();
+		self.rtc_oscillator += cycles as u64;
+		if self.rtc_oscillator >= 1048576 {
 			bus.mmu.cartridge.update_clock();
-			self.rtc_oscillator = 0
+			self.rtc_oscillator -= 1048576;
 		}
 	}
 
@@ -314,6 +326,7 @@ impl CPU {
 			ime: self.ime,
 			ime_scheduled: self.ime_scheduled,
 			halt_mode: self.halt_mode,
+			stop_mode: self.stop_mode,
 			rtc_oscillator: self.rtc_oscillator,
 			gb_mode: self.gb_mode,
 		}
@@ -327,6 +340,7 @@ impl CPU {
 		self.ime = cpu_state.ime;
 		self.ime_scheduled = cpu_state.ime_scheduled;
 		self.halt_mode = cpu_state.halt_mode;
+		self.stop_mode = cpu_state.stop_mode;
 		self.rtc_oscillator = cpu_state.rtc_oscillator;
 		self.gb_mode = cpu_state.gb_mode;
 	}
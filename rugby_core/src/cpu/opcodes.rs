@@ -2803,12 +2803,10 @@ impl CPU {
 
 	// STOP: Low power standby mode
 	fn opcode_stop(&mut self, bus: &mut Bus) {
-		let input_bits = (bus.get_byte(0xFF00) >> 4) & 0x0F;
-		if input_bits != 0 {
-			if bus.key1 & 0x01 == 1 {
-				bus.double_speed = !bus.double_speed;
-				bus.key1 = bus.key1 & 0x00;
-			}
+		// Either switch the speed, or stop until a button is pressed
+		let button_pressed = bus.get_byte(0xFF00) & 0x0F != 0x0F;
+		if !bus.stop() && !button_pressed {
+			self.stop_mode = true;
 		}
 		self.pc += 1;
 	}
@@ -0,0 +1,163 @@
+use serde::{Serialize, Deserialize};
+
+const OAM_SIZE: u8 = 160;
+
+// OAM DMA, which copies 160 bytes to OAM, one byte every M-Cycle
+#[derive(Clone, Copy)]
+#[derive(Serialize, Deserialize)]
+pub struct OamDma {
+	pub register: u8, // The last value written to 0xFF46
+	source: u16,
+	index: u8,
+	startup: u8, // M-Cycles left before the first byte is copied
+	active: bool,
+	pub last_byte: u8, // The byte on the bus, which the CPU reads on a bus conflict
+}
+
+impl OamDma {
+	pub fn new() -> Self {
+		OamDma {
+			register: 0xFF,
+			source: 0,
+			index: 0,
+			startup: 0,
+			active: false,
+			last_byte: 0xFF,
+		}
+	}
+
+	// Starts a new transfer (restarting any transfer in progress)
+	pub fn start(&mut self, value: u8) {
+		self.register = value;
+		// Sources after 0xDFFF read from the echo of WRAM
+		let source = if value >= 0xE0 { value - 0x20 } else { value };
+		self.source = (source as u16) << 8;
+		self.index = 0;
+		self.startup = 1;
+		self.active = true;
+	}
+
+	// Called every M-Cycle, returns the source address and the OAM index of the byte to copy (if any)
+	pub fn step(&mut self) -> Option<(u16, usize)> {
+		if !self.active {
+			return None;
+		}
+		if self.startup > 0 {
+			self.startup -= 1;
+			return None;
+		}
+		let index = self.index;
+		self.index += 1;
+		if self.index >= OAM_SIZE {
+			self.active = false;
+		}
+		Some((self.source + index as u16, index as usize))
+	}
+
+	// True while the CPU can only access HRAM and the I/O registers
+	pub fn is_blocking(&self) -> bool {
+		self.active && self.startup == 0
+	}
+}
+
+const HDMA_BLOCK_SIZE: u8 = 16;
+const VRAM_SIZE: u16 = 0x2000;
+
+// CGB VRAM DMA, which copies blocks of 16 bytes to VRAM. A general-purpose DMA
+// copies every block at once, while an HBlank DMA copies a block every HBlank.
+// The CPU is stalled while a block is copied.
+#[derive(Clone, Copy)]
+#[derive(Serialize, Deserialize)]
+pub struct Hdma {
+	source: u16,
+	destination: u16, // Offset inside VRAM
+	blocks_left: u8,
+	active: bool,
+	general: bool, // General-purpose DMA, instead of HBlank DMA
+	block_bytes_left: u8, // Bytes left to copy from the current block
+}
+
+impl Hdma {
+	pub fn new() -> Self {
+		Hdma {
+			source: 0,
+			destination: 0,
+			blocks_left: 0,
+			active: false,
+			general: false,
+			block_bytes_left: 0,
+		}
+	}
+
+	// Writes to HDMA1-HDMA4 (0xFF51-0xFF54)
+	pub fn set_register(&mut self, address: u16, value: u8) {
+		match address {
+			0xFF51 => self.source = (self.source & 0x00F0) | ((value as u16) << 8),
+			0xFF52 => self.source = (self.source & 0xFF00) | (value as u16 & 0xF0),
+			0xFF53 => self.destination = (self.destination & 0x00F0) | ((value as u16 & 0x1F) << 8),
+			0xFF54 => self.destination = (self.destination & 0x1F00) | (value as u16 & 0xF0),
+			_ => panic!("Invalid HDMA register: {:04X}", address),
+		}
+	}
+
+	// Reads HDMA5 (0xFF55): the number of blocks left minus 1, and bit 7 set if no HBlank DMA is active
+	pub fn get_hdma5(&self) -> u8 {
+		match (self.active, self.blocks_left) {
+			(true, _) => (self.blocks_left - 1) & 0x7F,
+			(false, 0) => 0xFF,
+			(false, blocks_left) => 0x80 | ((blocks_left - 1) & 0x7F),
+		}
+	}
+
+	// Writes HDMA5 (0xFF55), which starts a transfer, or cancels an HBlank DMA
+	pub fn set_hdma5(&mut self, value: u8, lcd_enabled: bool) {
+		let hblank_mode = value & 0x80 != 0;
+		if self.active && !self.general && !hblank_mode {
+			// The block being copied is still finished
+			self.active = false;
+			return;
+		}
+		self.blocks_left = (value & 0x7F) + 1;
+		self.active = true;
+		self.general = !hblank_mode;
+		// Without HBlanks when the LCD is off, an HBlank DMA copies its first block right away
+		if self.general || !lcd_enabled {
+			self.block_bytes_left = HDMA_BLOCK_SIZE;
+		}
+	}
+
+	// Called when the PPU enters HBlank with the LCD on
+	pub fn hblank(&mut self) {
+		if self.active && !self.general && self.block_bytes_left == 0 {
+			self.block_bytes_left = HDMA_BLOCK_SIZE;
+		}
+	}
+
+	// True while a block is copied, and the CPU is stalled
+	pub fn is_transferring(&self) -> bool {
+		self.block_bytes_left > 0
+	}
+
+	// Returns the source address and the VRAM offset of the next byte to copy (if any)
+	pub fn step(&mut self) -> Option<(u16, u16)> {
+		if self.block_bytes_left == 0 {
+			return None;
+		}
+		let addresses = (self.source, self.destination);
+		self.source = self.source.wrapping_add(1);
+		self.destination += 1;
+		self.block_bytes_left -= 1;
+		if self.block_bytes_left == 0 {
+			self.blocks_left -= 1;
+			if self.blocks_left == 0 || self.destination >= VRAM_SIZE {
+				// The transfer also stops once it reaches the end of VRAM
+				self.blocks_left = 0;
+				self.active = false;
+				self.destination &= VRAM_SIZE - 1;
+			} else if self.general {
+				self.block_bytes_left = HDMA_BLOCK_SIZE;
+			}
+		}
+		Some(addresses)
+	}
+}
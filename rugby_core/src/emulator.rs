@@ -1,72 +1,155 @@
-use std::collections::VecDeque;
 use std::process;
 use std::path::PathBuf;
-#[cfg(feature = "rewind")]
 use std::time::Duration;
 
 use crate::bus::Bus;
-use crate::cartridge::load;
+use crate::cartridge::{has_battery, load};
+use crate::cheats::{Cheat, CheatCode};
+use crate::colorization;
 use crate::cpu::CPU;
+use crate::gb_mode::GBMode;
 use crate::color::Color;
 use crate::color::LogicalColor;
+use crate::color::PixelFormat;
+use crate::filter::{ColorCorrection, ScreenTint, FrameBlending};
+use crate::framebuffer::Framebuffer;
 use crate::input::Input;
 use crate::input::EmulatorInput;
+use crate::model::Model;
+use crate::movie::{crc32, Anchor, Movie, MovieMode};
 use crate::ppu::GB_WIDTH;
 use crate::ppu::GB_HEIGHT;
-use crate::save_state::EmulatorState;
+use crate::rewind::RewindBuffer;
+use crate::rewind::DEFAULT_REWIND_CAPACITY;
+use crate::sgb::{SGB_WIDTH, SGB_HEIGHT};
+use crate::save_state::{EmulatorState, BusState, CPUState};
 
-const REWIND_STACK_CAPACITY: usize = 300; // 60 equals about 1 second
-#[cfg(feature = "rewind")]
 const REWIND_TIME: u64 = 5; 
+const BOOT_FRAMES: u32 = 120; // About as long as the CGB boot animation, where a palette can be picked
+
+// The CPU registers, for debuggers and scripts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Registers {
+	pub a: u8,
+	pub f: u8,
+	pub b: u8,
+	pub c: u8,
+	pub d: u8,
+	pub e: u8,
+	pub h: u8,
+	pub l: u8,
+	pub sp: u16,
+	pub pc: u16,
+}
 
 pub struct Emulator {
 	cpu: CPU,
 	bus: Bus,
 
-	rewind_stack: VecDeque<String>,
-	rewind_screens: VecDeque<[[Color; GB_WIDTH]; GB_HEIGHT]>,
+	rewind_buffer: RewindBuffer,
+	rewind_enabled: bool,
 	pub save_states: Vec<String>,
 	pub select_save_states: Vec<String>,
 
 	emulator_active: bool,
 	current_bg_map: u8, // The background map to show (for debugging)
+
+	model: Option<Model>, // None picks the model from the game's header
+	colorization_palette: Option<usize>, // A palette from colorization::PALETTE_NAMES, or None to pick it from the title
+	boot_frames: u32, // Frames left where a button combination picks the palette
+
+	framebuffer: Framebuffer,
+
+	rom_checksum: u32, // CRC-32 of the ROM, to check that movies are played with the same game
+	movie: Option<Movie>,
+	movie_mode: MovieMode,
+	movie_frame: usize, // The next frame of the movie
+	movie_read_only: bool, // Loading a save state in read-only mode doesn't change the movie
+
+	gameshark: Vec<CheatCode>, // Written to RAM after every frame
+	cheat_codes: String, // The enabled codes, joined with +, which movies must be played with
 }
 
 impl Emulator {
 	pub fn new(data_buffer: Option<Vec<u8>>, path_buf: Option<PathBuf>, callback: Box<dyn Fn(&[f32])>) -> Self {
 		let emulator_active = path_buf.is_some();
-		let (cartridge, gb_mode) = load(data_buffer, path_buf);
+		let rom_checksum = data_buffer.as_deref().map_or(0, crc32);
+		let cartridge = load(data_buffer, path_buf);
 		
-		let mut bus = Bus::new(cartridge, callback);
-		bus.initialize(gb_mode);
-		
-		let mut cpu = CPU::new();
-		cpu.initialize(gb_mode);
-		
-		Emulator {
-			cpu,
-			bus,
+		let mut emulator = Emulator {
+			cpu: CPU::new(),
+			bus: Bus::new(cartridge, callback),
 
-			rewind_stack: VecDeque::with_capacity(REWIND_STACK_CAPACITY),
-			rewind_screens: VecDeque::with_capacity(REWIND_STACK_CAPACITY),
+			rewind_buffer: RewindBuffer::new(DEFAULT_REWIND_CAPACITY),
+			rewind_enabled: false,
 			save_states: Vec::new(),
 			select_save_states: vec!["".to_string(); 4],
 			emulator_active,
-			current_bg_map: 0
-		}
+			current_bg_map: 0,
+
+			model: None,
+			colorization_palette: None,
+			boot_frames: 0,
+
+			framebuffer: Framebuffer::new(),
+
+			rom_checksum,
+			movie: None,
+			movie_mode: MovieMode::Finished,
+			movie_frame: 0,
+			movie_read_only: true,
+
+			gameshark: Vec::new(),
+			cheat_codes: String::new(),
+		};
+		emulator.initialize();
+		emulator.update_framebuffer();
+		emulator
 	}
 
 	// Loads a new ROM file
 	pub fn load(&mut self, data_buffer: Option<Vec<u8>>, path_buf: Option<PathBuf>) {
 		self.emulator_active = true;
-		let (cartridge, gb_mode) = load(data_buffer, path_buf);
+		self.rom_checksum = data_buffer.as_deref().map_or(0, crc32);
+		let cartridge = load(data_buffer, path_buf);
 		self.bus.load_rom(cartridge);
-		self.bus.initialize(gb_mode);
-
-		self.cpu.initialize(gb_mode);
+		self.initialize();
 
+		self.movie = None;
 		self.save_states = Vec::new();
 		self.select_save_states = vec!["".to_string(); 4];
+		self.rewind_buffer.clear();
+	}
+
+	// Resets the emulator, keeping the current ROM. Stops the movie, since it can't record resets
+	pub fn reset(&mut self) {
+		self.initialize();
+		self.rewind_buffer.clear();
+		self.movie = None;
+	}
+
+	// The model used at the next reset, picked from the game's header if none is selected
+	fn selected_model(&self) -> Model {
+		self.model.unwrap_or(Model::automatic(self.bus.mmu.cartridge.read(0x0143)))
+	}
+
+	// Initializes every component to the state the boot ROM of the model leaves behind
+	fn initialize(&mut self) {
+		let cgb_flag = self.bus.mmu.cartridge.read(0x0143);
+		let header_checksum = self.bus.mmu.cartridge.read(0x014D);
+		let model = self.selected_model();
+		let gb_mode = model.gb_mode(cgb_flag);
+		self.bus.initialize(gb_mode, model);
+		self.cpu.initialize(gb_mode, model.cpu_registers(gb_mode, header_checksum));
+
+		// Color models run DMG games in compatibility mode
+		self.boot_frames = 0;
+		if model.is_color() && gb_mode == GBMode::DMG {
+			self.apply_colorization();
+			if self.colorization_palette.is_none() {
+				self.boot_frames = BOOT_FRAMES;
+			}
+		}
 	}
 
 	// Run instructions until we are ready to display a new frame
@@ -80,34 +163,283 @@ impl Emulator {
 			return &[[Color::Logical(LogicalColor::White); GB_WIDTH]; GB_HEIGHT];
 		}
 
-		// Rewind
-		if emulator_input.is_some() && emulator_input.unwrap().rewind {
-			#[cfg(feature = "rewind")]
-			{
-				self.pop_rewind_stack();
-				self.bus.ppu.frame_ready = false;
+		// Rewind by dropping the snapshot of the frame on screen, then loading the one before
+		// it and silently running its frame again, so the screen shows the previous frame.
+		// Movies rewind by loading save states instead, to keep track of their frames
+		if emulator_input.is_some() && emulator_input.unwrap().rewind && self.rewind_enabled && self.movie.is_none() {
+			if self.rewind_buffer.len() >= 2 {
+				self.rewind_buffer.pop();
+				let (input, snapshot) = self.rewind_buffer.newest().unwrap();
+				let snapshot = snapshot.to_vec();
+				self.load_rewind_snapshot(&snapshot);
+				let output_enabled = self.bus.apu.output_enabled();
+				self.bus.apu.set_output(false);
+				self.run_frame(input);
+				self.bus.apu.set_output(output_enabled);
 				std::thread::sleep(Duration::from_millis(REWIND_TIME));
-				return self.bus.ppu.get_screen_buffer();
 			}
+			return self.get_screen();
 		}
-		
-		self.bus.mmu.store_input(input);
+
+		let input = self.movie_input(input);
+		if self.rewind_enabled {
+			let snapshot = self.create_rewind_snapshot();
+			self.rewind_buffer.push(input, snapshot);
+		}
+		self.run_frame(input);
+		self.get_screen()
+	}
+
+	// Records the input of the next frame in the movie, or replaces it with the recorded one
+	fn movie_input(&mut self, input: Input) -> Input {
+		let Some(movie) = &mut self.movie else {
+			return input;
+		};
+		match self.movie_mode {
+			MovieMode::Recording => {
+				movie.frames.truncate(self.movie_frame);
+				movie.frames.push(input);
+				self.movie_frame += 1;
+				input
+			},
+			MovieMode::Playing => {
+				let recorded = movie.frames[self.movie_frame];
+				self.movie_frame += 1;
+				// At the end, read-write movies go on recording
+				if self.movie_frame == movie.frames.len() {
+					self.movie_mode = match self.movie_read_only {
+						true => MovieMode::Finished,
+						false => MovieMode::Recording,
+					};
+				}
+				recorded
+			},
+			MovieMode::Finished => input,
+		}
+	}
+
+	// Starts recording a movie, from power-on (which resets the emulator) or from the current state
+	pub fn start_movie_recording(&mut self, anchor: Anchor) {
+		match anchor {
+			Anchor::PowerOn => self.reset(),
+			Anchor::SaveState => self.boot_frames = 0,
+		}
+		let mut title = String::new();
+		for address in 0x0134..=0x0143 {
+			let byte = self.bus.mmu.cartridge.read(address);
+			if byte.is_ascii_graphic() || byte == b' ' {
+				title.push(byte as char);
+			}
+		}
+		self.movie = Some(Movie {
+			title: title.trim().to_string(),
+			rom_checksum: self.rom_checksum,
+			anchor,
+			model: self.selected_model(),
+			fifo_renderer: self.bus.ppu.fifo_renderer(),
+			cheats: self.cheat_codes.clone(),
+			rerecords: 0,
+			frames: Vec::new(),
+			state: self.get_save_string(),
+		});
+		self.movie_mode = MovieMode::Recording;
+		self.movie_frame = 0;
+		self.movie_read_only = false;
+	}
+
+	// Plays a movie from its starting state. Fails if it was recorded with
+	// another ROM, or with other settings that change emulation
+	pub fn start_movie_playback(&mut self, movie: Movie, read_only: bool) -> Result<(), String> {
+		if movie.rom_checksum != self.rom_checksum {
+			return Err(format!("The movie was recorded with another ROM ({}, checksum {:08X})",
+							   movie.title, movie.rom_checksum));
+		}
+		if movie.model != self.selected_model() {
+			return Err(format!("The movie was recorded with the {} model", movie.model.name()));
+		}
+		if movie.fifo_renderer != self.bus.ppu.fifo_renderer() {
+			let renderer = if movie.fifo_renderer {"pixel FIFO"} else {"scanline"};
+			return Err(format!("The movie was recorded with the {} renderer", renderer));
+		}
+		if movie.cheats != self.cheat_codes {
+			return Err(match movie.cheats.as_str() {
+				"" => "The movie was recorded without cheats".to_string(),
+				cheats => format!("The movie was recorded with the cheats {}", cheats),
+			});
+		}
+		let emulator_state = serde_json::from_str::<EmulatorState>(&movie.state).map_err(|e| e.to_string())?;
+		self.reset();
+		if movie.anchor == Anchor::SaveState {
+			self.boot_frames = 0;
+		}
+		self.load_emulator_state(emulator_state)?;
+
+		self.movie_mode = match (movie.frames.is_empty(), read_only) {
+			(false, _) => MovieMode::Playing,
+			(true, true) => MovieMode::Finished,
+			(true, false) => MovieMode::Recording,
+		};
+		self.movie = Some(movie);
+		self.movie_frame = 0;
+		self.movie_read_only = read_only;
+		Ok(())
+	}
+
+	// Stops recording or playing the movie, and returns it
+	pub fn stop_movie(&mut self) -> Option<Movie> {
+		self.movie.take()
+	}
+
+	pub fn movie(&self) -> Option<&Movie> {
+		self.movie.as_ref()
+	}
+
+	// Returns the mode of the movie, and its next frame
+	pub fn movie_status(&self) -> Option<(MovieMode, usize)> {
+		self.movie.as_ref().map(|_| (self.movie_mode, self.movie_frame))
+	}
+
+	// In read-write mode, loading a save state records over the movie from
+	// the frame of the save state, and playing to the end goes on recording
+	pub fn set_movie_read_only(&mut self, read_only: bool) {
+		self.movie_read_only = read_only;
+		self.movie_mode = match (self.movie_mode, read_only) {
+			(MovieMode::Recording, true) => MovieMode::Finished,
+			(MovieMode::Finished, false) => MovieMode::Recording,
+			(mode, _) => mode,
+		};
+	}
+
+	pub fn movie_read_only(&self) -> bool {
+		self.movie_read_only
+	}
+
+	// Runs the CPU until the PPU has a new frame ready
+	fn run_frame(&mut self, input: Input) {
+		// Like the CGB boot ROM, let a button combination pick the palette. The
+		// game doesn't see the buttons meanwhile, so the combination can't reach it
+		let mut game_input = input;
+		if self.boot_frames > 0 {
+			self.boot_frames -= 1;
+			game_input = Input::new();
+			if let Some(combo) = colorization::held_combo(&input) {
+				self.bus.ppu.set_compatibility_palettes(colorization::combo_palettes(combo));
+				self.boot_frames = 0;
+			}
+		}
+		self.bus.mmu.store_input(game_input);
 		while self.bus.ppu.frame_ready == false {
 			self.cpu.step(&mut self.bus);			
 		}
-		{
-			
+		self.bus.ppu.frame_ready = false;
+		self.apply_gameshark();
+		if let Some(sgb) = &mut self.bus.mmu.sgb {
+			sgb.update(self.bus.ppu.get_screen_buffer());
 		}
-		#[cfg(feature = "rewind")]
-		{
-			self.push_rewind_stack();
+		self.update_framebuffer();
+	}
+
+	fn apply_gameshark(&mut self) {
+		for code in &self.gameshark {
+			if let CheatCode::GameShark { bank, address, value } = *code {
+				match (bank, address) {
+					(Some(bank), 0xD000..=0xDFFF) => self.bus.mmu.write_wram_bank(bank, address, value),
+					_ => self.bus.mmu.set_byte(address, value),
+				}
+			}
 		}
-		self.bus.ppu.frame_ready = false;
-		self.bus.ppu.get_screen_buffer()
+	}
+
+	// Replaces the cheats, with the codes of the enabled ones. Invalid codes are ignored
+	pub fn set_cheats(&mut self, cheats: &[Cheat]) {
+		let enabled: Vec<&Cheat> = cheats.iter()
+			.filter(|cheat| cheat.enabled && cheat.codes().is_ok())
+			.collect();
+		self.cheat_codes = enabled.iter()
+			.flat_map(|cheat| cheat.code.split('+').map(|code| code.trim().to_ascii_uppercase()))
+			.collect::<Vec<String>>()
+			.join("+");
+		let codes: Vec<CheatCode> = enabled.iter()
+			.flat_map(|cheat| cheat.codes().unwrap_or_default())
+			.collect();
+		let (gameshark, game_genie) = codes.into_iter()
+			.partition(|code| matches!(code, CheatCode::GameShark { .. }));
+		self.gameshark = gameshark;
+		self.bus.mmu.game_genie = game_genie;
+	}
+
+	// Draws the screen (inside the SGB border, if there is one) to the framebuffer
+	fn update_framebuffer(&mut self) {
+		if !self.emulator_active {
+			self.framebuffer.update(&[[Color::Logical(LogicalColor::White); GB_WIDTH]; GB_HEIGHT]);
+			return;
+		}
+		match &self.bus.mmu.sgb {
+			Some(sgb) => self.framebuffer.update(sgb.get_frame()),
+			None => self.framebuffer.update(self.bus.ppu.get_screen_buffer()),
+		}
+	}
+
+	// Returns the last frame as packed pixels, 4 bytes each in the selected pixel format
+	pub fn framebuffer(&self) -> &[u8] {
+		bytemuck::cast_slice(self.framebuffer.pixels())
+	}
+
+	// Returns the last frame as packed pixels, one u32 each
+	pub fn framebuffer_u32(&self) -> &[u32] {
+		self.framebuffer.pixels()
+	}
+
+	// Returns the width and the height of the framebuffer, which change with
+	// the SGB border and the pixel grid
+	pub fn framebuffer_size(&self) -> (usize, usize) {
+		self.framebuffer.size()
+	}
+
+	// Changes the RGB colors of the 4 shades of the Gameboy
+	pub fn set_palette(&mut self, palette: [[u8; 3]; 4]) {
+		self.framebuffer.set_palette(palette);
+		self.update_framebuffer();
+	}
+
+	pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+		self.framebuffer.set_pixel_format(pixel_format);
+		self.update_framebuffer();
+	}
+
+	// Selects how the RGB555 colors of the Gameboy Color are converted
+	pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+		self.framebuffer.set_color_correction(color_correction);
+		self.update_framebuffer();
+	}
+
+	pub fn set_screen_tint(&mut self, screen_tint: ScreenTint) {
+		self.framebuffer.set_screen_tint(screen_tint);
+		self.update_framebuffer();
+	}
+
+	pub fn set_frame_blending(&mut self, frame_blending: FrameBlending) {
+		self.framebuffer.set_frame_blending(frame_blending);
+		self.update_framebuffer();
+	}
+
+	// Scales every pixel to the given number of pixels, and darkens the lines
+	// between them. None (or a scale of 1) disables it.
+	pub fn set_pixel_grid(&mut self, scale: Option<usize>) {
+		self.framebuffer.set_pixel_grid(scale);
+		self.update_framebuffer();
 	}
 
 	pub fn get_screen(&self) -> &[[Color; GB_WIDTH]; GB_HEIGHT] {
-		self.bus.ppu.get_screen_buffer()
+		match &self.bus.mmu.sgb {
+			Some(sgb) => sgb.get_screen(),
+			None => self.bus.ppu.get_screen_buffer(),
+		}
+	}
+
+	// Returns the screen inside the Super Gameboy border, when the model is SGB
+	pub fn get_sgb_frame(&self) -> Option<&[[Color; SGB_WIDTH]; SGB_HEIGHT]> {
+		self.bus.mmu.sgb.as_ref().map(|sgb| sgb.get_frame())
 	}
 
 	// Updates the emulator's settings
@@ -153,6 +485,14 @@ impl Emulator {
 	pub fn save(&mut self) {
 		self.bus.mmu.cartridge.save();
 	}
+
+	// The external RAM that the cartridge keeps with a battery (empty if it has none), for frontends that save it themselves
+	pub fn battery_ram(&mut self) -> &mut [u8] {
+		match has_battery(self.bus.mmu.cartridge.read(0x0147)) {
+			true => self.bus.mmu.cartridge.ram(),
+			false => &mut [],
+		}
+	}
 	
 	// Creates an EmulatorState from the currently running Emulator
 	pub fn save_state(&mut self, position: Option<usize>) {
@@ -163,12 +503,14 @@ impl Emulator {
 		}
 	}
 
-	fn get_save_string(&mut self) -> String {
+	// Returns the current state, like the save states, e.g. for frontends that keep their own
+	pub fn get_save_string(&mut self) -> String {
 		let json = self.bus.mmu.cartridge.create_state();
 		let emulator_state = EmulatorState {
 			cpu_state: self.cpu.create_state(),
 			bus_state: self.bus.create_state(),
 			cartridge_json: json,
+			movie_frame: self.movie.as_ref().map(|_| self.movie_frame),
 		};
 		let serialized = serde_json::to_string(&emulator_state).unwrap();
 		// println!("Size of JSON: {} bytes", serialized.len());
@@ -195,40 +537,283 @@ impl Emulator {
 	// Load an Emulator state based on the option given
 	fn load_save_string(&mut self, option: i8) {
 		let state_string = match option {
-			-1 => &self.rewind_stack.back().unwrap(),
-			0..=3 => &self.select_save_states[option as usize],
-			_ => &self.save_states.last().unwrap(),
+			0..=3 => self.select_save_states[option as usize].clone(),
+			_ => self.save_states.last().unwrap().clone(),
 		};
-		match serde_json::from_str::<EmulatorState>(&state_string) {
-			Ok(emulator_state) => {
-				self.cpu.load_state(emulator_state.cpu_state);
-				self.bus.load_state(emulator_state.bus_state);
-				self.bus.mmu.cartridge.load_state(&emulator_state.cartridge_json);
-			},
-			Err(e) => {
-				eprintln!("Failed to deserialize state: {}", e);
+		if let Err(e) = self.load_state_string(&state_string) {
+			eprintln!("Failed to deserialize state: {}", e);
+		}
+	}
+
+	// Loads a state returned by get_save_string(). While a movie is playing or
+	// recording, only states saved during the movie can be loaded, since the
+	// movie would go on from the wrong frame otherwise
+	pub fn load_state_string(&mut self, state_string: &str) -> Result<(), String> {
+		let emulator_state = serde_json::from_str::<EmulatorState>(state_string).map_err(|e| e.to_string())?;
+		let movie_frame = emulator_state.movie_frame;
+		if let Some(movie) = &self.movie {
+			match movie_frame {
+				None => return Err("The save state isn't from the movie".to_string()),
+				Some(frame) if frame > movie.frames.len() => {
+					return Err("The save state is from after the end of the movie".to_string());
+				},
+				Some(_) => {},
 			}
 		}
+		self.load_emulator_state(emulator_state)?;
+		if let Some(frame) = movie_frame {
+			self.seek_movie(frame);
+		}
+		Ok(())
+	}
+
+	// The bus is loaded first, since its state can be invalid
+	fn load_emulator_state(&mut self, emulator_state: EmulatorState) -> Result<(), String> {
+		self.bus.load_state(emulator_state.bus_state)?;
+		self.cpu.load_state(emulator_state.cpu_state);
+		self.bus.mmu.cartridge.load_state(&emulator_state.cartridge_json);
+		Ok(())
+	}
+
+	// Continues the movie from the frame of a loaded save state: playing it
+	// in read-only mode, or recording over it in read-write mode
+	fn seek_movie(&mut self, frame: usize) {
+		let Some(movie) = &mut self.movie else {
+			return;
+		};
+		self.movie_frame = frame;
+		self.movie_mode = match (self.movie_read_only, frame < movie.frames.len()) {
+			(true, true) => MovieMode::Playing,
+			(true, false) => MovieMode::Finished,
+			(false, _) => {
+				movie.frames.truncate(frame);
+				movie.rerecords += 1;
+				MovieMode::Recording
+			},
+		};
 	}
 	
-	pub fn push_rewind_stack(&mut self) {
-		if self.rewind_stack.len() == REWIND_STACK_CAPACITY {
-			self.rewind_stack.pop_front();
-			self.rewind_screens.pop_front();
+	// Enables or disables sending audio samples to the callback function,
+	// e.g. to mute the emulator while it is not running at normal speed
+	pub fn set_audio_output(&mut self, enabled: bool) {
+		self.bus.apu.set_output(enabled);
+	}
+
+	// Changes the sample rate of the audio given to the callback function
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.bus.apu.set_sample_rate(sample_rate);
+	}
+
+	// Dynamic rate control: the frontend reports how full its audio buffer is,
+	// from 0.0 (empty) to 1.0 (full), and the audio is very slightly resampled
+	// to keep it half full
+	pub fn set_audio_buffer_fill(&mut self, fill: f32) {
+		self.bus.apu.set_buffer_fill(fill);
+	}
+
+	// Changes the number of frames (a sample for each output channel) given to the callback function at once
+	pub fn set_audio_buffer_size(&mut self, buffer_size: usize) {
+		self.bus.apu.set_buffer_size(buffer_size);
+	}
+
+	// Changes the number of output channels, either 1 (mono) or 2 (stereo, interleaved)
+	pub fn set_audio_channels(&mut self, channels: usize) {
+		self.bus.apu.set_output_channels(channels);
+	}
+
+	// Downmixes the audio to mono, even with 2 output channels
+	pub fn set_mono_audio(&mut self, mono: bool) {
+		self.bus.apu.set_mono(mono);
+	}
+
+	// Changes the master volume, from 0.0 to 1.0
+	pub fn set_master_volume(&mut self, volume: f32) {
+		self.bus.apu.set_master_volume(volume);
+	}
+
+	// Changes the volume of a single channel (0-3), from 0.0 to 1.0
+	pub fn set_channel_volume(&mut self, channel: usize, volume: f32) {
+		self.bus.apu.set_channel_volume(channel, volume);
+	}
+
+	// Switches between band-limited audio synthesis (clean audio), and
+	// sampling the channels directly (the original behavior)
+	pub fn set_band_limited_audio(&mut self, enabled: bool) {
+		self.bus.apu.set_band_limited(enabled);
+	}
+
+	// Switches between the pixel FIFO renderer (accurate mid-scanline effects
+	// and mode 3 timing) and the scanline renderer (faster)
+	pub fn set_fifo_renderer(&mut self, enabled: bool) {
+		self.bus.ppu.set_fifo_renderer(enabled);
+	}
+
+	// Selects the model, or None to pick it from the game's header. Takes
+	// effect when a ROM is loaded, or on reset.
+	pub fn set_model(&mut self, model: Option<Model>) {
+		self.model = model;
+	}
+
+	// Selects one of the colorization palettes, or None to pick it from the game's title like a CGB
+	pub fn set_colorization_palette(&mut self, palette: Option<usize>) {
+		self.colorization_palette = palette;
+		if self.bus.ppu.compatibility_mode {
+			self.apply_colorization();
+		}
+	}
+
+	// Enters compatibility mode, with the selected colorization palette
+	fn apply_colorization(&mut self) {
+		let palettes = match self.colorization_palette {
+			Some(palette) => colorization::combo_palettes(palette),
+			None => {
+				let cartridge = &self.bus.mmu.cartridge;
+				colorization::header_palettes(|address| cartridge.read(address))
+			},
+		};
+		self.bus.ppu.set_compatibility_palettes(palettes);
+	}
+
+	// Enables or disables rewinding. Disabling it also clears the rewind history
+	pub fn set_rewind(&mut self, enabled: bool) {
+		self.rewind_enabled = enabled;
+		if !enabled {
+			self.rewind_buffer.clear();
 		}
-		let state = self.get_save_string();
-		self.rewind_stack.push_back(state);
-		self.rewind_screens.push_back(self.get_screen().clone());
 	}
 
-	pub fn pop_rewind_stack(&mut self) {
-		if self.rewind_stack.len() > 0 {
-			self.load_save_string(-1);
-			self.bus.ppu.screen_buffer = self.rewind_screens.pop_back().unwrap();
-			self.rewind_stack.pop_back();
+	// Sets the memory budget of the rewind history, in bytes
+	pub fn set_rewind_capacity(&mut self, capacity: usize) {
+		self.rewind_buffer.set_capacity(capacity);
+	}
+
+	// Returns how many frames can currently be rewound
+	pub fn rewind_frames(&self) -> usize {
+		self.rewind_buffer.len()
+	}
+
+	// Returns the memory used by the rewind history, in bytes
+	pub fn rewind_memory_usage(&self) -> usize {
+		self.rewind_buffer.memory_usage()
+	}
+
+	// Creates a compact binary snapshot, split into one segment per component
+	fn create_rewind_snapshot(&mut self) -> Vec<Vec<u8>> {
+		vec![
+			bincode::serialize(&self.cpu.create_state()).unwrap(),
+			bincode::serialize(&self.bus.create_state()).unwrap(),
+			self.bus.mmu.cartridge.create_snapshot(),
+		]
+	}
+
+	// Loads a snapshot created by create_rewind_snapshot()
+	fn load_rewind_snapshot(&mut self, snapshot: &[Vec<u8>]) {
+		if let Err(e) = self.try_load_rewind_snapshot(snapshot) {
+			eprintln!("{}", e);
 		}
 	}
+
+	fn try_load_rewind_snapshot(&mut self, snapshot: &[Vec<u8>]) -> Result<(), String> {
+		let [cpu_segment, bus_segment, cartridge_segment] = snapshot else {
+			return Err("Invalid snapshot".to_string());
+		};
+		let cpu_state = bincode::deserialize::<CPUState>(cpu_segment);
+		let bus_state = bincode::deserialize::<BusState>(bus_segment);
+		match (cpu_state, bus_state) {
+			(Ok(cpu_state), Ok(bus_state)) => {
+				self.bus.load_state(bus_state)?;
+				self.cpu.load_state(cpu_state);
+				self.bus.mmu.cartridge.load_snapshot(cartridge_segment)
+			},
+			_ => Err("Failed to deserialize snapshot".to_string()),
+		}
+	}
+
+	// Creates a binary snapshot, like the ones used for rewinding. It's much
+	// faster than a save state, but only valid for the same ROM and build of
+	// the emulator, so it suits searching through states rather than storing them.
+	// Unlike rewinding, which runs the frame again, it also keeps the screen
+	pub fn snapshot(&mut self) -> Vec<u8> {
+		let mut segments = self.create_rewind_snapshot();
+		segments.push(Emulator::pack_screen(self.bus.ppu.get_screen_buffer()));
+		segments.push(match &self.bus.mmu.sgb {
+			Some(sgb) => Emulator::pack_screen(sgb.get_screen()),
+			None => Vec::new(),
+		});
+		bincode::serialize(&segments).unwrap()
+	}
+
+	// Restores a snapshot created by snapshot(), with its screen
+	pub fn restore_snapshot(&mut self, snapshot: &[u8]) -> Result<(), String> {
+		let segments = bincode::deserialize::<Vec<Vec<u8>>>(snapshot).map_err(|e| e.to_string())?;
+		let [state_segments @ .., ppu_screen, sgb_screen] = segments.as_slice() else {
+			return Err("Invalid snapshot".to_string());
+		};
+		self.try_load_rewind_snapshot(state_segments)?;
+		Emulator::unpack_screen(ppu_screen, &mut self.bus.ppu.screen_buffer)?;
+		if let Some(sgb) = &mut self.bus.mmu.sgb {
+			let mut screen = *sgb.get_screen();
+			Emulator::unpack_screen(sgb_screen, &mut screen)?;
+			sgb.set_screen(&screen);
+		}
+		self.update_framebuffer();
+		Ok(())
+	}
+
+	// Packs a screen into 2 bytes per pixel
+	fn pack_screen(screen: &[[Color; GB_WIDTH]; GB_HEIGHT]) -> Vec<u8> {
+		screen.iter().flatten().flat_map(|color| color.to_bits().to_le_bytes()).collect()
+	}
+
+	fn unpack_screen(bytes: &[u8], screen: &mut [[Color; GB_WIDTH]; GB_HEIGHT]) -> Result<(), String> {
+		if bytes.len() != 2 * GB_WIDTH * GB_HEIGHT {
+			return Err("Invalid screen in the snapshot".to_string());
+		}
+		for (color, bits) in screen.iter_mut().flatten().zip(bytes.chunks_exact(2)) {
+			*color = Color::from_bits(u16::from_le_bytes([bits[0], bits[1]]));
+		}
+		Ok(())
+	}
 	
+	// Reads memory like a debugger: RAM is read directly, even during OAM DMA
+	pub fn peek(&self, address: u16) -> u8 {
+		match address {
+			0xA000..=0xDFFF | 0xFF80..=0xFFFE => self.bus.mmu.get_byte(address),
+			0xFEA0..=0xFEFF => 0xFF, // Prohibited area
+			_ => self.bus.get_byte(address),
+		}
+	}
+
+	// Writes to memory like a debugger, e.g. to change a value found with a RAM search
+	pub fn poke(&mut self, address: u16, value: u8) {
+		match address {
+			0xA000..=0xDFFF | 0xFF80..=0xFFFE => self.bus.mmu.set_byte(address, value),
+			_ => self.bus.set_byte(address, value),
+		}
+	}
+
+	pub fn registers(&self) -> Registers {
+		let cpu_state = self.cpu.create_state();
+		let [a, f, b, c, d, e, h, l, s, p] = cpu_state.cpu_registers;
+		Registers {
+			a, f, b, c, d, e, h, l,
+			sp: ((s as u16) << 8) | p as u16,
+			pc: cpu_state.pc,
+		}
+	}
+
+	// Watches writes to addresses, which are kept until take_watch_hits() is
+	// called. Only writes by the CPU are seen, not by DMA or cheats
+	pub fn set_watched_addresses(&mut self, addresses: Vec<u16>) {
+		self.bus.watched_addresses = addresses;
+		self.bus.watch_hits.clear();
+	}
+
+	// Returns every write to a watched address since the last call, in order, with the written value
+	pub fn take_watch_hits(&mut self) -> Vec<(u16, u8)> {
+		std::mem::take(&mut self.bus.watch_hits)
+	}
+
 	pub fn get_tilemap(&self) -> [[[Color; 8]; 8]; 384] {
 		self.bus.ppu.get_tilemap()
 	}
@@ -0,0 +1,110 @@
+use serde::{Serialize, Deserialize};
+
+// How RGB555 colors of the Gameboy Color are shown. The LCDs of the Gameboy
+// Color and the Gameboy Advance show colors darker and less saturated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum ColorCorrection {
+	Off, // Scale the channels to 8 bits (saturated colors)
+	CGB,
+	AGB,
+}
+
+// Tints the screen like the LCDs of the monochrome models
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum ScreenTint {
+	Off,
+	DMG,
+	Pocket,
+}
+
+// Mixes every frame with the previous one, like the slow response of the LCDs.
+// Games that flicker objects on alternate frames use it for transparency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum FrameBlending {
+	Off,
+	Mix, // Average of the last two frames
+	Ghosting, // Average with the previous output, so it fades out slowly
+}
+
+pub const COLOR_CORRECTIONS: [ColorCorrection; 3] = [ColorCorrection::Off, ColorCorrection::CGB, ColorCorrection::AGB];
+pub const SCREEN_TINTS: [ScreenTint; 3] = [ScreenTint::Off, ScreenTint::DMG, ScreenTint::Pocket];
+pub const FRAME_BLENDINGS: [FrameBlending; 3] = [FrameBlending::Off, FrameBlending::Mix, FrameBlending::Ghosting];
+
+const OUTPUT_GAMMA: f32 = 2.2;
+// From the lightest to the darkest shade of the screen
+const DMG_TINT: [[f32; 3]; 4] = [[155.0, 188.0, 15.0], [139.0, 172.0, 15.0], [48.0, 98.0, 48.0], [15.0, 56.0, 15.0]];
+const POCKET_TINT: [[f32; 3]; 4] = [[196.0, 207.0, 161.0], [139.0, 149.0, 109.0], [77.0, 83.0, 60.0], [31.0, 31.0, 31.0]];
+
+impl ColorCorrection {
+	pub fn name(&self) -> &'static str {
+		match self {
+			ColorCorrection::Off => "Off",
+			ColorCorrection::CGB => "Game Boy Color",
+			ColorCorrection::AGB => "Game Boy Advance",
+		}
+	}
+
+	// Returns the RGB888 color of a RGB555 color
+	pub fn correct(&self, rgb: u16) -> [u8; 3] {
+		let channels = [rgb & 0x1F, (rgb >> 5) & 0x1F, (rgb >> 10) & 0x1F];
+		if *self == ColorCorrection::Off {
+			// Simple and fast conversion from 5 bit to 8 bit (but produces saturated colors)
+			return channels.map(|c| ((c << 3) | (c >> 2)) as u8);
+		}
+
+		// Mix the channels in linear light, and then apply the gamma of the output
+		let (gamma, matrix) = self.profile();
+		let linear = channels.map(|c| (c as f32 / 31.0).powf(gamma));
+		matrix.map(|row| {
+			let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+			(value.clamp(0.0, 1.0).powf(1.0 / OUTPUT_GAMMA) * 255.0).round() as u8
+		})
+	}
+
+	// Returns the gamma of the LCD, and how much every channel contributes to
+	// red, green and blue
+	fn profile(&self) -> (f32, [[f32; 3]; 3]) {
+		match self {
+			ColorCorrection::Off => (OUTPUT_GAMMA, [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]),
+			ColorCorrection::CGB => (2.2, [[0.80, 0.275, -0.075], [0.135, 0.64, 0.225], [0.195, 0.155, 0.65]]),
+			ColorCorrection::AGB => (2.5, [[0.82, 0.24, -0.06], [0.125, 0.665, 0.21], [0.195, 0.075, 0.73]]),
+		}
+	}
+}
+
+impl ScreenTint {
+	pub fn name(&self) -> &'static str {
+		match self {
+			ScreenTint::Off => "Off",
+			ScreenTint::DMG => "Game Boy",
+			ScreenTint::Pocket => "Game Boy Pocket",
+		}
+	}
+
+	// Returns the color between the two shades of the screen closest to the brightness of a color
+	pub fn tint(&self, rgb: [u8; 3]) -> [u8; 3] {
+		let shades = match self {
+			ScreenTint::Off => return rgb,
+			ScreenTint::DMG => &DMG_TINT,
+			ScreenTint::Pocket => &POCKET_TINT,
+		};
+		let luma = (0.299 * rgb[0] as f32 + 0.587 * rgb[1] as f32 + 0.114 * rgb[2] as f32) / 255.0;
+		let position = (1.0 - luma) * 3.0;
+		let shade = (position.floor() as usize).min(2);
+		let t = position - shade as f32;
+		[0, 1, 2].map(|i| (shades[shade][i] * (1.0 - t) + shades[shade + 1][i] * t).round() as u8)
+	}
+}
+
+impl FrameBlending {
+	pub fn name(&self) -> &'static str {
+		match self {
+			FrameBlending::Off => "Off",
+			FrameBlending::Mix => "Mix",
+			FrameBlending::Ghosting => "Ghosting",
+		}
+	}
+}
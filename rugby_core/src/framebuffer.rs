@@ -0,0 +1,129 @@
+use crate::color::{Color, LogicalColor, PixelFormat};
+use crate::filter::{ColorCorrection, ScreenTint, FrameBlending};
+
+const DEFAULT_PALETTE: [[u8; 3]; 4] = [[0xFF, 0xFF, 0xFF], [0xB8, 0xB8, 0xB8], [0x68, 0x68, 0x68], [0x00, 0x00, 0x00]];
+const GRID_BRIGHTNESS: u16 = 192; // Out of 256, for the lines between pixels
+
+// Converts the screen to packed pixels, after the palette of logical colors,
+// the color correction and the screen filters. The buffers are reused, so
+// nothing is allocated every frame.
+pub struct Framebuffer {
+	palette: [[u8; 3]; 4],
+	pixel_format: PixelFormat,
+	screen_tint: ScreenTint,
+	frame_blending: FrameBlending,
+	pixel_grid: Option<usize>, // The number of pixels that every pixel is scaled to
+
+	colors: Vec<[u8; 3]>, // Every RGB555 color, after the color correction
+	frame: Vec<[u8; 3]>,
+	previous_frame: Vec<[u8; 3]>,
+	pixels: Vec<u32>,
+	width: usize,
+	height: usize,
+}
+
+impl Framebuffer {
+	pub fn new() -> Self {
+		let mut framebuffer = Framebuffer {
+			palette: DEFAULT_PALETTE,
+			pixel_format: PixelFormat::RGBA8888,
+			screen_tint: ScreenTint::Off,
+			frame_blending: FrameBlending::Off,
+			pixel_grid: None,
+
+			colors: Vec::new(),
+			frame: Vec::new(),
+			previous_frame: Vec::new(),
+			pixels: Vec::new(),
+			width: 0,
+			height: 0,
+		};
+		framebuffer.set_color_correction(ColorCorrection::Off);
+		framebuffer
+	}
+
+	pub fn set_palette(&mut self, palette: [[u8; 3]; 4]) {
+		self.palette = palette;
+	}
+
+	pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+		self.pixel_format = pixel_format;
+	}
+
+	pub fn set_color_correction(&mut self, color_correction: ColorCorrection) {
+		self.colors = (0..0x8000).map(|rgb| color_correction.correct(rgb)).collect();
+	}
+
+	pub fn set_screen_tint(&mut self, screen_tint: ScreenTint) {
+		self.screen_tint = screen_tint;
+	}
+
+	pub fn set_frame_blending(&mut self, frame_blending: FrameBlending) {
+		self.frame_blending = frame_blending;
+		self.previous_frame.clear();
+	}
+
+	pub fn set_pixel_grid(&mut self, pixel_grid: Option<usize>) {
+		self.pixel_grid = pixel_grid.filter(|&scale| scale >= 2);
+	}
+
+	// Draws a screen (either the Gameboy screen, or the SGB border with it)
+	pub fn update<const W: usize, const H: usize>(&mut self, screen: &[[Color; W]; H]) {
+		self.frame.clear();
+		for row in screen.iter() {
+			for color in row.iter() {
+				let rgb = match color {
+					Color::Logical(logical) => match logical {
+						LogicalColor::White => self.palette[0],
+						LogicalColor::LightGray => self.palette[1],
+						LogicalColor::DarkGray => self.palette[2],
+						LogicalColor::Black => self.palette[3],
+					},
+					Color::RGB(rgb) => self.colors[(rgb & 0x7FFF) as usize],
+				};
+				self.frame.push(self.screen_tint.tint(rgb));
+			}
+		}
+
+		if self.frame_blending != FrameBlending::Off {
+			if self.previous_frame.len() != self.frame.len() {
+				self.previous_frame.clone_from(&self.frame);
+			}
+			for (rgb, previous) in self.frame.iter_mut().zip(self.previous_frame.iter_mut()) {
+				let blended = [0, 1, 2].map(|i| (rgb[i] as u16 + previous[i] as u16).div_ceil(2) as u8);
+				// Mixing keeps the previous frame, ghosting keeps the previous output
+				*previous = match self.frame_blending {
+					FrameBlending::Mix => *rgb,
+					_ => blended,
+				};
+				*rgb = blended;
+			}
+		}
+
+		let scale = self.pixel_grid.unwrap_or(1);
+		self.width = W * scale;
+		self.height = H * scale;
+		self.pixels.resize(self.width * self.height, 0);
+		for (y, row) in self.pixels.chunks_exact_mut(self.width).enumerate() {
+			for (x, pixel) in row.iter_mut().enumerate() {
+				let mut rgb = self.frame[(y / scale) * W + x / scale];
+				if scale > 1 && (x % scale == scale - 1 || y % scale == scale - 1) {
+					rgb = rgb.map(|channel| ((channel as u16 * GRID_BRIGHTNESS) >> 8) as u8);
+				}
+				let [r, g, b] = rgb;
+				*pixel = u32::from_ne_bytes(match self.pixel_format {
+					PixelFormat::RGBA8888 => [r, g, b, 0xFF],
+					PixelFormat::BGRA8888 => [b, g, r, 0xFF],
+				});
+			}
+		}
+	}
+
+	pub fn pixels(&self) -> &[u32] {
+		&self.pixels
+	}
+
+	pub fn size(&self) -> (usize, usize) {
+		(self.width, self.height)
+	}
+}
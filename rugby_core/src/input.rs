@@ -63,3 +63,121 @@ impl EmulatorInput {
         }
     }
 }
+
+// Presses and releases A and B every few frames while their turbo buttons are held
+#[derive(Clone, Copy)]
+pub struct Turbo {
+	pub rate: usize, // How many frames the buttons stay pressed, and then released
+	frame: usize,
+}
+
+impl Turbo {
+	pub fn new(rate: usize) -> Self {
+		Turbo {
+			rate,
+			frame: 0,
+		}
+	}
+
+	// Returns the input of the next frame
+	pub fn apply(&mut self, input: Input, turbo_a: bool, turbo_b: bool) -> Input {
+		let pressed = (self.frame / self.rate.max(1)).is_multiple_of(2);
+		self.frame = self.frame.wrapping_add(1);
+		Input {
+			a: input.a || (turbo_a && pressed),
+			b: input.b || (turbo_b && pressed),
+			..input
+		}
+	}
+}
+
+// A recorded sequence of inputs, one for every frame
+#[derive(Clone, Default)]
+#[derive(Serialize, Deserialize)]
+pub struct InputMacro {
+	pub frames: Vec<Input>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MacroState {
+	Idle,
+	Recording(usize), // Into a slot
+	Playing(usize, usize), // A slot, from a frame
+}
+
+// Slots of macros, that are recorded from the inputs of every frame and played back on top of them
+pub struct Macros {
+	pub macros: Vec<InputMacro>,
+	state: MacroState,
+}
+
+impl Macros {
+	pub fn new(slots: usize) -> Self {
+		Macros {
+			macros: vec![InputMacro::default(); slots],
+			state: MacroState::Idle,
+		}
+	}
+
+	// Starts recording over a slot, or stops recording it
+	pub fn toggle_recording(&mut self, slot: usize) {
+		if self.state == MacroState::Recording(slot) {
+			self.state = MacroState::Idle;
+			return;
+		}
+		self.macros[slot].frames.clear();
+		self.state = MacroState::Recording(slot);
+	}
+
+	// Plays a slot from the start, or stops it if it's already playing
+	pub fn toggle_playing(&mut self, slot: usize) {
+		self.state = match self.state {
+			MacroState::Playing(playing, _) if playing == slot => MacroState::Idle,
+			_ if self.macros[slot].frames.is_empty() => MacroState::Idle,
+			_ => MacroState::Playing(slot, 0),
+		};
+	}
+
+	pub fn recording(&self) -> Option<usize> {
+		match self.state {
+			MacroState::Recording(slot) => Some(slot),
+			_ => None,
+		}
+	}
+
+	pub fn playing(&self) -> Option<usize> {
+		match self.state {
+			MacroState::Playing(slot, _) => Some(slot),
+			_ => None,
+		}
+	}
+
+	// Returns the input of the next frame, with the buttons of the macro being played held too
+	pub fn apply(&mut self, input: Input) -> Input {
+		match self.state {
+			MacroState::Idle => input,
+			MacroState::Recording(slot) => {
+				self.macros[slot].frames.push(input);
+				input
+			},
+			MacroState::Playing(slot, frame) => {
+				let frames = &self.macros[slot].frames;
+				let recorded = frames[frame];
+				self.state = match frame + 1 < frames.len() {
+					true => MacroState::Playing(slot, frame + 1),
+					false => MacroState::Idle,
+				};
+				Input {
+					up: input.up || recorded.up,
+					down: input.down || recorded.down,
+					left: input.left || recorded.left,
+					right: input.right || recorded.right,
+					a: input.a || recorded.a,
+					b: input.b || recorded.b,
+					start: input.start || recorded.start,
+					select: input.select || recorded.select,
+				}
+			},
+		}
+	}
+}
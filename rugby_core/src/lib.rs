@@ -1,12 +1,23 @@
+pub mod cheats;
 pub mod emulator;
+pub mod filter;
 pub mod color;
+pub mod colorization;
 pub mod input;
+pub mod model;
+pub mod movie;
+pub mod ram_search;
 mod apu;
 mod bus;
 mod cartridge;
 mod cpu;
+mod dma;
+mod framebuffer;
 mod mmu;
 mod gb_mode;
 mod ppu;
+mod rewind;
 mod save_state;
+mod serial;
+mod sgb;
 mod timer;
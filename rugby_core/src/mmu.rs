@@ -1,9 +1,11 @@
-// use std::fs::OpenOptions;
-// use std::io::Write;
 use crate::cartridge::Cartridge;
+use crate::cheats::{patch_rom, CheatCode};
 use crate::gb_mode::GBMode;
 use crate::input::Input;
+use crate::model::Model;
 use crate::save_state::MMUState;
+use crate::serial::Serial;
+use crate::sgb::Sgb;
 use crate::timer::Timer;
 
 const MEMORY_SIZE: usize = 65536;
@@ -21,8 +23,9 @@ pub struct MMU {
 	input: Input,
 	prev_p1: u8,
 	pub joypad_interrupt: bool,
-	serial_buffer: [u8; 100], // TODO: properly implement
-	serial_file_path: String,
+	pub serial: Serial,
+	pub sgb: Option<Box<Sgb>>, // Only on the Super Gameboy
+	pub game_genie: Vec<CheatCode>, // Patch the values read from the ROM
 
 	// For Gameboy Color
 	gb_mode: GBMode,
@@ -31,7 +34,6 @@ pub struct MMU {
 
 impl MMU {
 	pub fn new(cartridge: Box<dyn Cartridge>) -> Self {
-		let serial_file_path = "output.txt";
 		MMU {
 			cartridge,
 			wram: [0; 8 * WRAM_BANK_SIZE],
@@ -43,25 +45,50 @@ impl MMU {
 			input: Input::new(),
 			prev_p1: 0xCF,
 			joypad_interrupt: false,
-			serial_buffer: [0; 100],
-			serial_file_path: serial_file_path.to_string(),
+			serial: Serial::new(),
+			sgb: None,
+			game_genie: Vec::new(),
 
 			gb_mode: GBMode::DMG,
 			svbk: 0xF8,
 		}
 	}
 
-	pub fn initialize(&mut self, gb_mode: GBMode)  {
+	// Sets the registers like the boot ROM of the model leaves them
+	pub fn initialize(&mut self, gb_mode: GBMode, model: Model)  {
+		self.io_registers = [0xFF; 128]; // Unused registers read as 0xFF
 		self.io_registers[0x00] = 0xCF; // P1
-		self.io_registers[0x02] = 0x7E; // SC
-		self.io_registers[0x04] = 0xAB; // DIV
 		self.io_registers[0x07] = 0xF8; // TAC
 		self.io_registers[0x0F] = 0xE1; // IF
+		if model.is_color() {
+			self.io_registers[0x72] = 0x00;
+			self.io_registers[0x73] = 0x00;
+			self.io_registers[0x75] = 0x8F;
+			self.io_registers[0x76] = 0x00; // PCM12
+			self.io_registers[0x77] = 0x00; // PCM34
+			if gb_mode == GBMode::CGB {
+				self.io_registers[0x56] = 0x3E; // RP
+				self.io_registers[0x74] = 0x00;
+			}
+		}
 		self.ie_register = 0; // IE
 
 		self.gb_mode = gb_mode;
 		self.svbk = 0xF8;
-		self.timer.initialize();
+		self.timer.initialize(model.timer_counter());
+		self.serial.initialize(gb_mode);
+		// The CGB boot ROM leaves the internal clock selected, so SC reads 0x7F
+		if model.is_color() {
+			self.serial.set_sc(0x03);
+		}
+		self.sgb = match model {
+			Model::SGB => {
+				// Commands are only accepted from games with the SGB flag, and the new licensee code
+				let commands_enabled = self.cartridge.read(0x0146) == 0x03 && self.cartridge.read(0x014B) == 0x33;
+				Some(Box::new(Sgb::new(commands_enabled)))
+			},
+			_ => None,
+		};
 	}
 
 	// Get 8-bit value from memory at a specific address
@@ -70,7 +97,13 @@ impl MMU {
 			panic!("MMU::get_byte(): Out of memory at address: {:04X}", address);
 		}
 		match address {
-			0x0000..=0x7FFF => self.cartridge.read(address),
+			0x0000..=0x7FFF => {
+				let value = self.cartridge.read(address);
+				match self.game_genie.is_empty() {
+					true => value,
+					false => patch_rom(&self.game_genie, address, value),
+				}
+			},
 			0xA000..=0xBFFF => self.cartridge.read(address),
 			0xC000..=0xCFFF => {
 				self.wram[address as usize - 0xC000]
@@ -107,11 +140,16 @@ impl MMU {
 					0xFF00 =>  {
 						self.io_registers[0x00]
 					}
+					0xFF01 => self.serial.sb,
+					0xFF02 => self.serial.get_sc(),
 					0xFF04 => self.timer.div,
 					0xFF05 => self.timer.tima,
 					0xFF06 => self.timer.tma,
 					0xFF07 => self.timer.tac,
-					0xFF70 => self.svbk,
+					0xFF70 => match self.gb_mode {
+						GBMode::DMG => 0xFF,
+						GBMode::CGB => self.svbk,
+					},
 					0xFF0F => self.io_registers[0x0F] | 0xE0,
 					_ => self.io_registers[address as usize - 0xFF00],
 				}
@@ -147,20 +185,14 @@ impl MMU {
 			0xFF00..=0xFF7F => {
 				match address {
 					0xFF00 => {
+						if let Some(sgb) = &mut self.sgb {
+							sgb.write_p1(value, self.io_registers[0x00]);
+						}
 						self.io_registers[0x00] = value;
 						self.update_p1();
 					}
-					0xFF01 => self.serial_buffer[0] = value,
-					0xFF02 => {
-						// TODO: Serial
-						// let mut file = OpenOptions::new()
-						// 	.create(true).write(true).append(true)
-						// 	.open(&self.serial_file_path).unwrap();
-						// if let Err(e) = write!(file, "{}",
-						// 					   self.serial_buffer[0] as char) {
-						// 	eprintln!("Writing error: {}", e.to_string())
-						// }
-					},
+					0xFF01 => self.serial.sb = value,
+					0xFF02 => self.serial.set_sc(value),
 					0xFF04 => self.timer.reset_timer(),
 					0xFF05 => self.timer.tima = value,
 					0xFF06 => self.timer.tma = value,
@@ -175,34 +207,51 @@ impl MMU {
 		}
 	}
 
+	// Writes to a WRAM bank of the Gameboy Color, whichever bank is selected
+	pub fn write_wram_bank(&mut self, bank: u8, address: u16, value: u8) {
+		let index = match (self.gb_mode, bank & 0x07) {
+			(GBMode::DMG, _) | (GBMode::CGB, 0) => 1,
+			(GBMode::CGB, bank) => bank as usize,
+		};
+		if let 0xD000..=0xDFFF = address {
+			self.wram[WRAM_BANK_SIZE * index + (address as usize - 0xD000)] = value;
+		}
+	}
+
 	pub fn update_p1(&mut self) {
 		let mut input_byte = self.io_registers[0x00];
+		// Only the joypad of the first player is connected
+		let input = match &self.sgb {
+			Some(sgb) if !sgb.first_player() => Input::new(),
+			_ => self.input,
+		};
 		let bit4 = MMU::get_bit(self.io_registers[0x00], 4);
 		let bit5 = MMU::get_bit(self.io_registers[0x00], 5);
 		if bit5 == 1 && bit4 == 1 {
-			input_byte = MMU::set_bit(input_byte, 0, 1);
-			input_byte = MMU::set_bit(input_byte, 1, 1);
-			input_byte = MMU::set_bit(input_byte, 2, 1);
-			input_byte = MMU::set_bit(input_byte, 3, 1);
+			let joypad_id = match &self.sgb {
+				Some(sgb) => sgb.joypad_id(),
+				None => 0x0F,
+			};
+			input_byte = (input_byte & 0xF0) | joypad_id;
 		} else if bit4 == 0 && bit5 == 0 {
-			let bit0 = MMU::reverse_flag(self.input.right) & MMU::reverse_flag(self.input.a);
+			let bit0 = MMU::reverse_flag(input.right) & MMU::reverse_flag(input.a);
 			input_byte = MMU::set_bit(input_byte, 0, bit0);
-			let bit1 = MMU::reverse_flag(self.input.left) & MMU::reverse_flag(self.input.b);
+			let bit1 = MMU::reverse_flag(input.left) & MMU::reverse_flag(input.b);
 			input_byte = MMU::set_bit(input_byte, 1, bit1);
-			let bit2 = MMU::reverse_flag(self.input.up) & MMU::reverse_flag(self.input.select);
+			let bit2 = MMU::reverse_flag(input.up) & MMU::reverse_flag(input.select);
 			input_byte = MMU::set_bit(input_byte, 2, bit2);
-			let bit3 = MMU::reverse_flag(self.input.down) & MMU::reverse_flag(self.input.start);
+			let bit3 = MMU::reverse_flag(input.down) & MMU::reverse_flag(input.start);
 			input_byte = MMU::set_bit(input_byte, 3, bit3);
 		} else if bit4 == 0 {
-			input_byte = MMU::set_bit(input_byte, 0, MMU::reverse_flag(self.input.right)); 
-			input_byte = MMU::set_bit(input_byte, 1, MMU::reverse_flag(self.input.left));
-			input_byte = MMU::set_bit(input_byte, 2, MMU::reverse_flag(self.input.up));
-			input_byte = MMU::set_bit(input_byte, 3, MMU::reverse_flag(self.input.down)); 
+			input_byte = MMU::set_bit(input_byte, 0, MMU::reverse_flag(input.right)); 
+			input_byte = MMU::set_bit(input_byte, 1, MMU::reverse_flag(input.left));
+			input_byte = MMU::set_bit(input_byte, 2, MMU::reverse_flag(input.up));
+			input_byte = MMU::set_bit(input_byte, 3, MMU::reverse_flag(input.down)); 
 		} else if bit5 == 0 {
-			input_byte = MMU::set_bit(input_byte, 0, MMU::reverse_flag(self.input.a));
-			input_byte = MMU::set_bit(input_byte, 1, MMU::reverse_flag(self.input.b));
-			input_byte = MMU::set_bit(input_byte, 2, MMU::reverse_flag(self.input.select));
-			input_byte = MMU::set_bit(input_byte, 3, MMU::reverse_flag(self.input.start));
+			input_byte = MMU::set_bit(input_byte, 0, MMU::reverse_flag(input.a));
+			input_byte = MMU::set_bit(input_byte, 1, MMU::reverse_flag(input.b));
+			input_byte = MMU::set_bit(input_byte, 2, MMU::reverse_flag(input.select));
+			input_byte = MMU::set_bit(input_byte, 3, MMU::reverse_flag(input.start));
 		}
 		input_byte = input_byte | 0b11000000;
 		self.io_registers[0x00] = input_byte;
@@ -256,15 +305,18 @@ impl MMU {
 			input: self.input.clone(),
 			prev_p1: self.prev_p1,
 			joypad_interrupt: self.joypad_interrupt,
-			serial_buffer: self.serial_buffer.clone(),
-			serial_file_path: self.serial_file_path.clone(),
+			serial: self.serial,
+			sgb_state: self.sgb.as_ref().map(|sgb| sgb.create_state()),
 			gb_mode: self.gb_mode,
 			svbk: self.svbk,
 		}
 	}
 
-	// Loads an MMUState to the MMU
-	pub fn load_state(&mut self, mmu_state: MMUState) {
+	// Loads an MMUState to the MMU. The SGB is loaded first, since its state can be invalid
+	pub fn load_state(&mut self, mmu_state: MMUState) -> Result<(), String> {
+		if let (Some(sgb), Some(sgb_state)) = (&mut self.sgb, mmu_state.sgb_state) {
+			sgb.load_state(sgb_state)?;
+		}
 		self.wram = mmu_state.wram.clone();
 		self.io_registers = mmu_state.io_registers.clone();
 		self.hram = mmu_state.hram.clone();
@@ -273,9 +325,9 @@ impl MMU {
 		self.input = mmu_state.input.clone();
 		self.prev_p1 = mmu_state.prev_p1;
 		self.joypad_interrupt = mmu_state.joypad_interrupt;
-		self.serial_buffer = mmu_state.serial_buffer.clone();
-		self.serial_file_path = mmu_state.serial_file_path.clone();
+		self.serial = mmu_state.serial;
 		self.gb_mode = mmu_state.gb_mode;
 		self.svbk = mmu_state.svbk;
+		Ok(())
 	}
 }
@@ -0,0 +1,82 @@
+use serde::{Serialize, Deserialize};
+
+use crate::gb_mode::GBMode;
+
+// The console that is emulated, which decides the mode games run in and the
+// state that the boot ROM leaves behind
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum Model {
+	DMG0, // Early Game Boy revision
+	DMG, // Game Boy (DMG-B)
+	MGB, // Game Boy Pocket
+	SGB, // Super Game Boy
+	CGB, // Game Boy Color
+	AGB, // Game Boy Advance
+}
+
+pub const MODELS: [Model; 6] = [Model::DMG0, Model::DMG, Model::MGB, Model::SGB, Model::CGB, Model::AGB];
+
+impl Model {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Model::DMG0 => "Game Boy (DMG-0)",
+			Model::DMG => "Game Boy (DMG-B)",
+			Model::MGB => "Game Boy Pocket",
+			Model::SGB => "Super Game Boy",
+			Model::CGB => "Game Boy Color",
+			Model::AGB => "Game Boy Advance",
+		}
+	}
+
+	// The model picked when none is selected, from the CGB flag in the header
+	pub fn automatic(cgb_flag: u8) -> Self {
+		match cgb_flag {
+			0x80 | 0xC0 => Model::CGB,
+			_ => Model::DMG,
+		}
+	}
+
+	pub fn is_color(&self) -> bool {
+		matches!(self, Model::CGB | Model::AGB)
+	}
+
+	// Returns the mode a game runs in. Only color models run games in CGB mode,
+	// and older models run games that support both in DMG mode.
+	pub fn gb_mode(&self, cgb_flag: u8) -> GBMode {
+		match (self.is_color(), cgb_flag) {
+			(true, 0x80 | 0xC0) => GBMode::CGB,
+			_ => GBMode::DMG,
+		}
+	}
+
+	// Returns the registers (A, F, B, C, D, E, H, L, and SP) after the boot ROM
+	pub fn cpu_registers(&self, gb_mode: GBMode, header_checksum: u8) -> [u8; 10] {
+		// The boot ROM of the DMG and the MGB leave H and C set, unless the header checksum is 0
+		let flags = match header_checksum {
+			0 => 0x80,
+			_ => 0xB0,
+		};
+		match (self, gb_mode) {
+			(Model::DMG0, _) => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03, 0xFF, 0xFE],
+			(Model::DMG, _) => [0x01, flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D, 0xFF, 0xFE],
+			(Model::MGB, _) => [0xFF, flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D, 0xFF, 0xFE],
+			(Model::SGB, _) => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60, 0xFF, 0xFE],
+			(Model::CGB, GBMode::CGB) => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D, 0xFF, 0xFE],
+			(Model::CGB, GBMode::DMG) => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C, 0xFF, 0xFE],
+			// Games can tell a GBA apart by bit 0 of B
+			(Model::AGB, GBMode::CGB) => [0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D, 0xFF, 0xFE],
+			(Model::AGB, GBMode::DMG) => [0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7C, 0xFF, 0xFE],
+		}
+	}
+
+	// Returns the internal counter of the timer (DIV is its upper byte) after the boot ROM
+	pub fn timer_counter(&self) -> u16 {
+		match self {
+			Model::DMG0 => 0x1830,
+			Model::DMG | Model::MGB => 0xABCC,
+			Model::SGB => 0xD85C,
+			Model::CGB | Model::AGB => 0x1EA0,
+		}
+	}
+}
@@ -0,0 +1,279 @@
+use crate::input::Input;
+use crate::model::{Model, MODELS};
+
+// Movies are text files, so they can be diffed and edited by hand. After a
+// header with one field per line, every frame is a line like |U...A...|,
+// with a letter for every held button and a dot for every released one:
+// Up, Down, Left, Right, A, B, Start (S) and Select (s)
+const MAGIC: &str = "RugbyMovie";
+const VERSION: u32 = 2;
+const BUTTONS: [char; 8] = ['U', 'D', 'L', 'R', 'A', 'B', 'S', 's'];
+
+// Where a movie starts from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+	PowerOn, // The emulator is reset, with the cartridge RAM it had at the time
+	SaveState,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MovieMode {
+	Recording, // The input of every frame is recorded, replacing the frames after it
+	Playing, // The recorded input replaces the input of every frame
+	Finished, // Played to the end in read-only mode
+}
+
+// A recording of the input of every frame, starting from a save state
+#[derive(Clone)]
+pub struct Movie {
+	pub title: String, // From the header of the ROM
+	pub rom_checksum: u32, // CRC-32 of the whole ROM
+	pub anchor: Anchor,
+	// The settings that change emulation, which must be the same for playback
+	pub model: Model,
+	pub fifo_renderer: bool,
+	pub cheats: String, // The enabled cheat codes, joined with +
+	pub rerecords: u32, // How many times a save state was loaded while recording
+	pub frames: Vec<Input>,
+	pub(crate) state: String, // The save state the movie starts from
+}
+
+impl Movie {
+	pub fn to_text(&self) -> String {
+		let anchor = match self.anchor {
+			Anchor::PowerOn => "PowerOn",
+			Anchor::SaveState => "SaveState",
+		};
+		let mut text = format!("{} {}\n", MAGIC, VERSION);
+		text += &format!("Title {}\n", self.title);
+		text += &format!("Checksum {:08X}\n", self.rom_checksum);
+		text += &format!("Anchor {}\n", anchor);
+		text += &format!("Model {}\n", self.model.name());
+		text += &format!("Renderer {}\n", if self.fifo_renderer {"FIFO"} else {"Scanline"});
+		text += &format!("Cheats {}\n", self.cheats);
+		text += &format!("Rerecords {}\n", self.rerecords);
+		text += &format!("State {}\n", self.state);
+		text += &format!("Input |{}|\n", BUTTONS.iter().collect::<String>());
+		for input in &self.frames {
+			let buttons = [input.up, input.down, input.left, input.right,
+						   input.a, input.b, input.start, input.select];
+			let line: String = buttons.iter().zip(BUTTONS)
+				.map(|(&pressed, letter)| if pressed {letter} else {'.'})
+				.collect();
+			text += &format!("|{}|\n", line);
+		}
+		text
+	}
+
+	pub fn from_text(text: &str) -> Result<Movie, String> {
+		let mut lines = text.lines();
+		match lines.next().and_then(|line| line.split_once(' ')) {
+			Some((MAGIC, version)) if version.trim().parse() == Ok(VERSION) => {},
+			_ => return Err("Not a movie, or a movie of an unsupported version".to_string()),
+		}
+
+		let mut model = None;
+		let mut movie = Movie {
+			title: String::new(),
+			rom_checksum: 0,
+			anchor: Anchor::PowerOn,
+			model: Model::DMG,
+			fifo_renderer: false,
+			cheats: String::new(),
+			rerecords: 0,
+			frames: Vec::new(),
+			state: String::new(),
+		};
+		for (number, line) in lines.enumerate() {
+			if let Some(buttons) = line.strip_prefix('|') {
+				movie.frames.push(parse_frame(buttons).ok_or(format!("Invalid frame on line {}", number + 2))?);
+				continue;
+			}
+			let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+			match field {
+				"Title" => movie.title = value.to_string(),
+				"Checksum" => movie.rom_checksum = u32::from_str_radix(value, 16).map_err(|e| e.to_string())?,
+				"Anchor" => movie.anchor = match value {
+					"PowerOn" => Anchor::PowerOn,
+					"SaveState" => Anchor::SaveState,
+					_ => return Err(format!("Unknown anchor {}", value)),
+				},
+				"Model" => model = Some(*MODELS.iter().find(|model| model.name() == value)
+					.ok_or(format!("Unknown model {}", value))?),
+				"Renderer" => movie.fifo_renderer = match value {
+					"FIFO" => true,
+					"Scanline" => false,
+					_ => return Err(format!("Unknown renderer {}", value)),
+				},
+				"Cheats" => movie.cheats = value.to_string(),
+				"Rerecords" => movie.rerecords = value.parse().map_err(|_| "Invalid rerecord count".to_string())?,
+				"State" => movie.state = value.to_string(),
+				// The names of the buttons, and fields from newer versions
+				_ => {},
+			}
+		}
+		if movie.state.is_empty() {
+			return Err("The movie has no starting state".to_string());
+		}
+		movie.model = model.ok_or("The movie has no model".to_string())?;
+		Ok(movie)
+	}
+}
+
+// Parses the buttons of a frame, after the first |
+fn parse_frame(buttons: &str) -> Option<Input> {
+	let buttons: Vec<bool> = buttons.trim_end().strip_suffix('|')?.chars().map(|c| c != '.').collect();
+	if buttons.len() != BUTTONS.len() {
+		return None;
+	}
+	Some(Input {
+		up: buttons[0],
+		down: buttons[1],
+		left: buttons[2],
+		right: buttons[3],
+		a: buttons[4],
+		b: buttons[5],
+		start: buttons[6],
+		select: buttons[7],
+	})
+}
+
+// CRC-32 (as used by zip and PNG) of the ROM, to check that a movie is played with the same game
+pub fn crc32(data: &[u8]) -> u32 {
+	let mut crc = 0xFFFFFFFFu32;
+	for &byte in data {
+		crc ^= byte as u32;
+		for _ in 0..8 {
+			crc = match crc & 1 {
+				1 => (crc >> 1) ^ 0xEDB88320,
+				_ => crc >> 1,
+			};
+		}
+	}
+	!crc
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::cheats::Cheat;
+	use crate::emulator::Emulator;
+
+	// A ROM without a mapper that only runs NOPs
+	fn rom(title: &str) -> Vec<u8> {
+		let mut rom = vec![0; 0x8000];
+		rom[0x0134..0x0134 + title.len()].copy_from_slice(title.as_bytes());
+		rom
+	}
+
+	// The emulator needs a larger stack than the test threads have, without optimizations
+	fn with_large_stack(test: impl FnOnce() + Send + 'static) {
+		std::thread::Builder::new().stack_size(64 * 1024 * 1024).spawn(test).unwrap().join().unwrap();
+	}
+
+	fn emulator(rom: Vec<u8>) -> Emulator {
+		let mut gb = Emulator::new(None, None, Box::new(|_| {}));
+		gb.load(Some(rom), None);
+		gb
+	}
+
+	fn recorded_movie(gb: &mut Emulator) -> Movie {
+		gb.start_movie_recording(Anchor::PowerOn);
+		for frame in 0..5 {
+			let mut input = Input::new();
+			input.a = frame % 2 == 0;
+			input.select = frame == 3;
+			gb.run(input, None);
+		}
+		gb.stop_movie().unwrap()
+	}
+
+	#[test]
+	fn text_round_trip() {
+		with_large_stack(|| {
+			let mut gb = emulator(rom("ROUNDTRIP"));
+			gb.set_fifo_renderer(true);
+			gb.set_cheats(&[Cheat { name: "Lives".to_string(), code: "01ff11c1".to_string(), enabled: true }]);
+			let movie = recorded_movie(&mut gb);
+			let text = movie.to_text();
+			assert!(text.contains("|....A...|\n") && text.contains("|.......s|\n"));
+
+			let parsed = Movie::from_text(&text).unwrap();
+			assert_eq!(parsed.title, "ROUNDTRIP");
+			assert_eq!(parsed.rom_checksum, movie.rom_checksum);
+			assert_eq!(parsed.anchor, Anchor::PowerOn);
+			assert_eq!(parsed.model, Model::DMG);
+			assert!(parsed.fifo_renderer);
+			assert_eq!(parsed.cheats, "01FF11C1");
+			assert_eq!(parsed.frames.len(), 5);
+			assert_eq!(parsed.state, movie.state);
+			assert_eq!(parsed.to_text(), text);
+		});
+	}
+
+	#[test]
+	fn rejects_invalid_headers() {
+		with_large_stack(|| {
+			let text = recorded_movie(&mut emulator(rom("HEADER"))).to_text();
+			let invalid = [
+				text.replacen("RugbyMovie", "RugbyMoovie", 1),
+				text.replacen("RugbyMovie 2", "RugbyMovie 1", 1),
+				text.replacen("Checksum ", "Checksum X", 1),
+				text.replacen("Anchor PowerOn", "Anchor Reset", 1),
+				text.replacen("Model Game Boy (DMG-B)", "Model Virtual Boy", 1),
+				text.lines().filter(|line| !line.starts_with("Model")).collect::<Vec<_>>().join("\n"),
+				text.lines().filter(|line| !line.starts_with("State")).collect::<Vec<_>>().join("\n"),
+				text.replacen("|....A...|", "|....A..|", 1),
+			];
+			for text in invalid {
+				assert!(Movie::from_text(&text).is_err());
+			}
+		});
+	}
+
+	#[test]
+	fn rejects_another_rom() {
+		with_large_stack(|| {
+			let movie = recorded_movie(&mut emulator(rom("FIRST")));
+			let mut gb = emulator(rom("SECOND"));
+			assert!(gb.start_movie_playback(movie.clone(), true).is_err());
+			assert!(gb.movie().is_none());
+			assert!(emulator(rom("FIRST")).start_movie_playback(movie, true).is_ok());
+		});
+	}
+
+	#[test]
+	fn rejects_other_settings() {
+		with_large_stack(|| {
+			let movie = recorded_movie(&mut emulator(rom("SETTINGS")));
+			let mut gb = emulator(rom("SETTINGS"));
+			gb.set_model(Some(Model::CGB));
+			assert!(gb.start_movie_playback(movie.clone(), true).is_err());
+			gb.set_model(None);
+			gb.set_fifo_renderer(true);
+			assert!(gb.start_movie_playback(movie.clone(), true).is_err());
+			gb.set_fifo_renderer(false);
+			gb.set_cheats(&[Cheat { name: "Lives".to_string(), code: "01FF11C1".to_string(), enabled: true }]);
+			assert!(gb.start_movie_playback(movie.clone(), true).is_err());
+			gb.set_cheats(&[]);
+			assert!(gb.start_movie_playback(movie, true).is_ok());
+		});
+	}
+
+	#[test]
+	fn rejects_states_from_outside_the_movie() {
+		with_large_stack(|| {
+			let mut gb = emulator(rom("STATES"));
+			let outside = gb.get_save_string();
+			let movie = recorded_movie(&mut gb);
+			gb.start_movie_playback(movie, false).unwrap();
+			gb.run(Input::new(), None);
+			let inside = gb.get_save_string();
+			gb.run(Input::new(), None);
+			assert!(gb.load_state_string(&outside).is_err());
+			assert_eq!(gb.movie_status(), Some((MovieMode::Playing, 2)));
+			assert!(gb.load_state_string(&inside).is_ok());
+			assert_eq!(gb.movie_status(), Some((MovieMode::Recording, 1)));
+		});
+	}
+}
@@ -1,7 +1,10 @@
+pub mod fifo;
+
 use serde::{Serialize, Deserialize};
 use crate::color::Color;
 use crate::color::LogicalColor;
 use crate::gb_mode::GBMode;
+use crate::ppu::fifo::PixelFifo;
 use crate::save_state::PPUState;
 
 const OAM_SEARCH_DOTS: u16 = 80;
@@ -30,6 +33,8 @@ pub struct Object {
     x_position: u8,
     tile_index: u8,
     attributes: u8,
+    #[serde(default)]
+    oam_index: u8, // Used for object priority in CGB mode
 }
 
 pub struct PPU {
@@ -73,6 +78,12 @@ pub struct PPU {
 	pub obpi: u8,
 	pub opri: u8,
 	pub entered_hblank: bool,
+	pub compatibility_mode: bool, // A DMG game on a CGB, colored through the first CGB palettes
+
+	// Renders mode 3 dot by dot with a pixel FIFO, instead of a whole line at once
+	fifo_renderer: bool,
+	next_fifo_renderer: bool, // Applied at the next line, so a line is never drawn by both renderers
+	fifo: PixelFifo,
 }
 
 impl PPU {
@@ -118,6 +129,11 @@ impl PPU {
 			obpi: 0xD0,
 			opri: 0x00,
 			entered_hblank: false,
+			compatibility_mode: false,
+
+			fifo_renderer: false,
+			next_fifo_renderer: false,
+			fifo: PixelFifo::new(),
         }
     }
 
@@ -141,6 +157,7 @@ impl PPU {
 		self.obpi = 0xD0;
 		self.opri = 0x00; // TODO Monochrome games in GBC
 		self.entered_hblank = false;
+		self.compatibility_mode = false;
     }
     
     // Each dot lasts for 1 T-Cycle
@@ -175,10 +192,18 @@ impl PPU {
 			Mode::OAMSearch => {
 				if self.current_clock >= OAM_SEARCH_DOTS-1 {
 					self.mode = Mode::PixelTransfer;
+					if self.fifo_renderer {
+						self.start_pixel_fifo();
+					}
 				}
 			},
 			Mode::PixelTransfer => {
-				if self.current_clock >= OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS - 1 {
+				// Mode 3 has a variable length with the pixel FIFO
+				let finished = match self.fifo_renderer {
+					true => self.fifo.done,
+					false => self.current_clock >= OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS - 1,
+				};
+				if finished {
 					self.mode = Mode::HBlank;
 					self.entered_hblank = true;
 				}   
@@ -212,7 +237,9 @@ impl PPU {
 				}  
 			},
 		}
-
+		if self.current_clock == 0 {
+			self.fifo_renderer = self.next_fifo_renderer;
+		}
 	}
 	
     // Returns the screen buffer
@@ -325,6 +352,19 @@ impl PPU {
         self.oam[address] = value;
     }
 
+	// Enters compatibility mode, and writes the BG, OBJ0 and OBJ1 palettes like the CGB boot ROM does
+	pub fn set_compatibility_palettes(&mut self, palettes: [[u16; 4]; 3]) {
+		self.compatibility_mode = true;
+		for (i, &color) in palettes[0].iter().enumerate() {
+			self.bg_cram[2 * i] = color as u8;
+			self.bg_cram[2 * i + 1] = (color >> 8) as u8;
+		}
+		for (i, &color) in palettes[1].iter().chain(palettes[2].iter()).enumerate() {
+			self.obj_cram[2 * i] = color as u8;
+			self.obj_cram[2 * i + 1] = (color >> 8) as u8;
+		}
+	}
+
 	pub fn set_palette(&mut self, address: usize, value: u8) {
 		match address {
 			0xFF69 => {
@@ -370,14 +410,16 @@ impl PPU {
                 1 => 16,
                 _ => unreachable!("PPU::scan_objects()"),
             };
-            if x_position > 0 && y_position <= self.ly + 16
-                && (y_position + sprite_height) > self.ly + 16
+            // The X position isn't checked, objects off the screen still count toward the limit
+            if y_position <= self.ly + 16
+                && (y_position as u16 + sprite_height) > self.ly as u16 + 16
                 && self.object_buffer.len() < 10 {
                     let obj = Object {
                         y_position,
                         x_position,
                         tile_index,
                         attributes,
+                        oam_index: i as u8,
                     };
                     self.object_buffer.push(obj);
                 }
@@ -386,12 +428,14 @@ impl PPU {
 
     // Mode 3
     fn pixel_transfer(&mut self) {
-    
+		if self.fifo_renderer {
+			self.pixel_fifo_dot();
+		}
     }
 
     // Mode 0
     fn hblank(&mut self) {
-        if self.current_clock == OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS {
+        if !self.fifo_renderer && self.current_clock == OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS {
             self.draw_scanline();
         } 
     }
@@ -619,6 +663,9 @@ impl PPU {
 			GBMode::DMG => {
 				let shift_amount = index * 2;
 				let color_bits = (self.bgp >> shift_amount) & 0b11;
+				if self.compatibility_mode {
+					return PPU::cram_color(&self.bg_cram, 0, color_bits);
+				}
 
 				match color_bits {
 					0b00 => Color::Logical(LogicalColor::White),
@@ -639,6 +686,13 @@ impl PPU {
 
     }
 
+	// Get a color from one of the palettes in color RAM
+	fn cram_color(cram: &[u8; 64], color_palette: u8, index: u8) -> Color {
+		let palette_address = (8 * color_palette + 2 * index) as usize;
+		let full_byte = ((cram[palette_address + 1] as u16) << 8) | cram[palette_address] as u16;
+		Color::RGB(full_byte)
+	}
+
 	// Get the proper color from object palettes
 	fn get_obp_color(&self, index: u8, obj_attributes: u8) -> Color {
 		match self.gb_mode {
@@ -648,6 +702,9 @@ impl PPU {
 					0 => (self.obp0 >> shift_amount) & 0b11,
 					_ => (self.obp1 >> shift_amount) & 0b11,
 				};
+				if self.compatibility_mode {
+					return PPU::cram_color(&self.obj_cram, PPU::get_bit(obj_attributes, 4), color_bits);
+				}
 				match color_bits {
 					0b00 => Color::Logical(LogicalColor::White),
 					0b01 => Color::Logical(LogicalColor::LightGray),
@@ -739,6 +796,15 @@ impl PPU {
         new_value
     }
 
+	// Switches between the pixel FIFO renderer and the scanline renderer
+	pub fn set_fifo_renderer(&mut self, enabled: bool) {
+		self.next_fifo_renderer = enabled;
+	}
+
+	pub fn fifo_renderer(&self) -> bool {
+		self.next_fifo_renderer
+	}
+
 	// Toggles the visibility of a given layer 
 	pub fn toggle_layer(&mut self, i: usize) {
 		self.active_layers[i] = !self.active_layers[i];
@@ -758,7 +824,6 @@ impl PPU {
 			window_line_counter: self.window_line_counter,
 			window_in_frame: self.window_in_frame,
 			window_in_line: self.window_in_line,
-			object_buffer: self.object_buffer.clone(),
 			bgp: self.bgp,
 			obp0: self.obp0,
 			obp1: self.obp1,
@@ -783,6 +848,9 @@ impl PPU {
 			obpi: self.obpi,
 			opri: self.opri,
 			entered_hblank: self.entered_hblank,
+			compatibility_mode: self.compatibility_mode,
+			fifo: self.fifo,
+			object_buffer: self.object_buffer.clone(),
 		}
 	}
 
@@ -821,5 +889,7 @@ impl PPU {
 		self.obpi = ppu_state.obpi;
 		self.opri = ppu_state.opri;
 		self.entered_hblank = ppu_state.entered_hblank;
+		self.compatibility_mode = ppu_state.compatibility_mode;
+		self.fifo = ppu_state.fifo;
 	}
 }
@@ -0,0 +1,688 @@
+use serde::{Serialize, Deserialize};
+use crate::color::Color;
+use crate::color::LogicalColor;
+use crate::gb_mode::GBMode;
+use crate::ppu::{PPU, GB_WIDTH, VRAM_BANK_SIZE};
+
+const FIFO_SIZE: usize = 16;
+const OBJECT_FETCH_DOTS: u8 = 6;
+// The background fetcher finishes reading its tile before an object is fetched
+const OBJECT_WAIT_DOTS: u8 = 5;
+
+// A single pixel waiting in one of the FIFOs
+#[derive(Clone, Copy, Default)]
+#[derive(Serialize, Deserialize)]
+pub struct FifoPixel {
+	color: u8, // 2-bit color index, 0 is transparent for objects
+	attributes: u8, // The CGB tile attributes for the background, the OAM attributes for objects
+	oam_index: u8, // Used for object priority in CGB mode
+}
+
+// A fixed-size queue of pixels (so save states have a fixed size)
+#[derive(Clone, Copy)]
+#[derive(Serialize, Deserialize)]
+pub struct Fifo {
+	pixels: [FifoPixel; FIFO_SIZE],
+	head: u8,
+	len: u8,
+}
+
+impl Fifo {
+	fn new() -> Self {
+		Fifo {
+			pixels: [FifoPixel::default(); FIFO_SIZE],
+			head: 0,
+			len: 0,
+		}
+	}
+
+	fn push(&mut self, pixel: FifoPixel) {
+		let index = (self.head + self.len) as usize % FIFO_SIZE;
+		self.pixels[index] = pixel;
+		self.len += 1;
+	}
+
+	fn pop(&mut self) -> Option<FifoPixel> {
+		if self.len == 0 {
+			return None;
+		}
+		let pixel = self.pixels[self.head as usize];
+		self.head = (self.head + 1) % FIFO_SIZE as u8;
+		self.len -= 1;
+		Some(pixel)
+	}
+
+	// Returns a mutable reference to the i-th pixel from the front
+	fn get_mut(&mut self, i: usize) -> &mut FifoPixel {
+		&mut self.pixels[(self.head as usize + i) % FIFO_SIZE]
+	}
+
+	fn clear(&mut self) {
+		self.head = 0;
+		self.len = 0;
+	}
+}
+
+// The state of the pixel FIFO renderer during mode 3
+#[derive(Clone, Copy)]
+#[derive(Serialize, Deserialize)]
+pub struct PixelFifo {
+	bg_fifo: Fifo,
+	obj_fifo: Fifo,
+
+	// Background fetcher, each step takes 2 dots: tile number, low data, high data, then push
+	fetcher_dot: u8,
+	fetcher_x: u8, // Tile column, relative to the start of the background or the window
+	tile_number: u8,
+	tile_attributes: u8,
+	data_low: u8,
+	data_high: u8,
+	first_fetch: bool, // The first fetch of every line is done twice
+	fetching_window: bool,
+
+	// Object fetcher
+	object_fetch: Option<u8>, // Index of the object in the object buffer
+	object_wait: u8, // Dots left for the background fetcher before the object is fetched
+	object_dots: u8,
+	objects_fetched: u16, // One bit for each object in the object buffer
+
+	lx: u8, // The next pixel on the screen
+	discard: u8, // Pixels left to discard at the start of the line, for SCX
+	pub done: bool,
+}
+
+impl PixelFifo {
+	pub fn new() -> Self {
+		PixelFifo {
+			bg_fifo: Fifo::new(),
+			obj_fifo: Fifo::new(),
+			fetcher_dot: 0,
+			fetcher_x: 0,
+			tile_number: 0,
+			tile_attributes: 0,
+			data_low: 0,
+			data_high: 0,
+			first_fetch: true,
+			fetching_window: false,
+			object_fetch: None,
+			object_wait: 0,
+			object_dots: 0,
+			objects_fetched: 0,
+			lx: 0,
+			discard: 0,
+			done: false,
+		}
+	}
+}
+
+impl PPU {
+	// Resets the FIFOs and the fetchers at the start of mode 3
+	pub fn start_pixel_fifo(&mut self) {
+		self.fifo = PixelFifo::new();
+		self.fifo.discard = self.scx & 0b111;
+		if PPU::get_bit(self.lcdc, 5) == 1 && !self.window_in_frame && self.ly == self.wy {
+			self.window_in_frame = true;
+			self.window_line_counter = 0;
+		}
+		self.window_in_line = false;
+	}
+
+	// Runs the pixel FIFO for a single dot of mode 3
+	pub fn pixel_fifo_dot(&mut self) {
+		if self.fifo.done {
+			return;
+		}
+
+		// Objects pause the output while they are being fetched
+		if let Some(index) = self.fifo.object_fetch {
+			// The background fetcher has to finish its current tile first
+			if self.fifo.object_wait > 0 {
+				self.fifo.object_wait -= 1;
+				self.background_fetcher_dot();
+				return;
+			}
+			self.fifo.object_dots += 1;
+			if self.fifo.object_dots >= OBJECT_FETCH_DOTS {
+				self.fetch_object(index as usize);
+				self.fifo.object_fetch = None;
+			}
+			return;
+		}
+
+		// Switch to the window as soon as we reach it
+		let window_enabled = PPU::get_bit(self.lcdc, 5) == 1 && self.window_in_frame && self.wx <= 166;
+		if window_enabled && !self.fifo.fetching_window && self.fifo.lx + 7 >= self.wx {
+			self.fifo.fetching_window = true;
+			self.fifo.fetcher_x = 0;
+			self.fifo.fetcher_dot = 0;
+			self.fifo.bg_fifo.clear();
+			self.window_in_line = true;
+		}
+
+		self.background_fetcher_dot();
+		if self.fifo.bg_fifo.len == 0 {
+			return;
+		}
+		if self.fifo.discard > 0 {
+			self.fifo.bg_fifo.pop();
+			self.fifo.discard -= 1;
+			return;
+		}
+
+		// An object starting at this pixel is fetched instead of shifting the pixel out
+		if PPU::get_bit(self.lcdc, 1) == 1 {
+			let next_object = self.object_buffer.iter().enumerate().position(|(i, obj)| {
+				self.fifo.objects_fetched & (1 << i) == 0 && obj.x_position <= self.fifo.lx + 8
+			});
+			if let Some(index) = next_object {
+				self.start_object_fetch(index);
+				return;
+			}
+		}
+
+		let bg_pixel = self.fifo.bg_fifo.pop().unwrap();
+		let obj_pixel = self.fifo.obj_fifo.pop();
+		self.output_fifo_pixel(bg_pixel, obj_pixel);
+		self.fifo.lx += 1;
+		if self.fifo.lx as usize >= GB_WIDTH {
+			self.fifo.done = true;
+			if self.window_in_line {
+				self.window_line_counter = self.window_line_counter.wrapping_add(1);
+			}
+		}
+	}
+
+	// Pauses the output for an object, with a penalty of 6 to 11 dots
+	fn start_object_fetch(&mut self, index: usize) {
+		let x_position = self.object_buffer[index].x_position;
+		// How far into the background tile the object's first pixel is, the fetcher is
+		// only as far as that pixel, so it may have to finish the tile first
+		let tile_x = match x_position {
+			0 => 0,
+			1..=7 => x_position.wrapping_add(self.scx) & 0b111,
+			_ => self.fifo.fetcher_dot,
+		};
+		self.fifo.objects_fetched |= 1 << index;
+		self.fifo.object_fetch = Some(index as u8);
+		self.fifo.object_wait = OBJECT_WAIT_DOTS.saturating_sub(tile_x);
+		// This dot is the first of the fetch
+		self.fifo.object_dots = 1;
+	}
+
+	// Advances the background fetcher by a single dot
+	fn background_fetcher_dot(&mut self) {
+		self.fifo.fetcher_dot += 1;
+		match self.fifo.fetcher_dot {
+			2 => self.fetch_tile_number(),
+			4 => self.fifo.data_low = self.fetch_tile_data(0),
+			6 => {
+				self.fifo.data_high = self.fetch_tile_data(1);
+				if self.fifo.first_fetch {
+					// The first tile is fetched again, for a 6 dot penalty
+					self.fifo.first_fetch = false;
+					self.fifo.fetcher_dot = 0;
+				}
+			},
+			7.. => {
+				// Push the 8 pixels only when the FIFO is empty, otherwise keep trying
+				if self.fifo.bg_fifo.len == 0 {
+					self.push_background_pixels();
+					self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+					self.fifo.fetcher_dot = 0;
+				} else {
+					self.fifo.fetcher_dot = 7;
+				}
+			},
+			_ => (),
+		}
+	}
+
+	fn fetch_tile_number(&mut self) {
+		let window = self.fifo.fetching_window;
+		let tile_map = match window {
+			true => PPU::get_bit(self.lcdc, 6),
+			false => PPU::get_bit(self.lcdc, 3),
+		};
+		let bg_map_start = match tile_map {
+			0 => 0x1800,
+			_ => 0x1C00,
+		};
+		let (tile_row, tile_column) = match window {
+			true => (self.window_line_counter as usize / 8, self.fifo.fetcher_x as usize & 0x1F),
+			false => (self.ly.wrapping_add(self.scy) as usize / 8,
+					  ((self.scx / 8) as usize + self.fifo.fetcher_x as usize) & 0x1F),
+		};
+		let tile_index_address = bg_map_start + 32 * tile_row + tile_column;
+		self.fifo.tile_number = self.vram[tile_index_address];
+		self.fifo.tile_attributes = match self.gb_mode {
+			GBMode::DMG => 0,
+			GBMode::CGB => self.vram[VRAM_BANK_SIZE + tile_index_address],
+		};
+	}
+
+	// Fetches the low (0) or high (1) byte of the current tile's row
+	fn fetch_tile_data(&self, byte: usize) -> u8 {
+		let mut pixel_y = match self.fifo.fetching_window {
+			true => self.window_line_counter as usize % 8,
+			false => self.ly.wrapping_add(self.scy) as usize % 8,
+		};
+		if PPU::get_bit(self.fifo.tile_attributes, 6) == 1 {
+			pixel_y = 7 - pixel_y;
+		}
+		let tile_address = match PPU::get_bit(self.lcdc, 4) {
+			0 => (0x1000 + (self.fifo.tile_number as i8 as i16 * 16)) as usize,
+			_ => self.fifo.tile_number as usize * 16,
+		} + pixel_y * 2 + byte;
+		let bank = PPU::get_bit(self.fifo.tile_attributes, 3) as usize;
+		self.vram[VRAM_BANK_SIZE * bank + tile_address]
+	}
+
+	fn push_background_pixels(&mut self) {
+		let window = self.fifo.fetching_window;
+		let layer_active = match window {
+			true => self.active_layers[1],
+			false => self.active_layers[0],
+		};
+		let x_flip = PPU::get_bit(self.fifo.tile_attributes, 5) == 1;
+		for i in 0..8 {
+			let bit = if x_flip { i } else { 7 - i };
+			let color = match !layer_active {
+				true => 0,
+				false => ((self.fifo.data_high >> bit) & 0x1) << 1 | ((self.fifo.data_low >> bit) & 0x1),
+			};
+			self.fifo.bg_fifo.push(FifoPixel {
+				color,
+				attributes: self.fifo.tile_attributes,
+				oam_index: 0,
+			});
+		}
+	}
+
+	// Fetches a row of an object, and mixes it into the object FIFO
+	fn fetch_object(&mut self, index: usize) {
+		let object = self.object_buffer[index];
+		let tall = PPU::get_bit(self.lcdc, 2) == 1;
+		let mut pixel_y = (self.ly + 16 - object.y_position) as usize;
+		if PPU::get_bit(object.attributes, 6) == 1 {
+			pixel_y = if tall { 15 - pixel_y } else { 7 - pixel_y };
+		}
+		let tile_index = if tall { object.tile_index & 0xFE } else { object.tile_index };
+		let tile_address = 16 * tile_index as usize + 2 * pixel_y;
+		let bank = match self.gb_mode {
+			GBMode::DMG => 0,
+			GBMode::CGB => PPU::get_bit(object.attributes, 3) as usize,
+		};
+		let low_byte = self.vram[VRAM_BANK_SIZE * bank + tile_address];
+		let high_byte = self.vram[VRAM_BANK_SIZE * bank + tile_address + 1];
+
+		// Objects partially off the left edge of the screen skip their first pixels
+		let skip = (self.fifo.lx + 8).saturating_sub(object.x_position) as usize;
+		while (self.fifo.obj_fifo.len as usize) < 8 {
+			self.fifo.obj_fifo.push(FifoPixel::default());
+		}
+		let x_flip = PPU::get_bit(object.attributes, 5) == 1;
+		for i in skip..8 {
+			let bit = if x_flip { i } else { 7 - i };
+			let color = ((high_byte >> bit) & 0x1) << 1 | ((low_byte >> bit) & 0x1);
+			let pixel = self.fifo.obj_fifo.get_mut(i - skip);
+			// In DMG mode the object fetched first wins, in CGB mode the one first in OAM
+			let replace = pixel.color == 0
+				|| (self.gb_mode == GBMode::CGB && object.oam_index < pixel.oam_index);
+			if replace && color != 0 {
+				*pixel = FifoPixel {
+					color,
+					attributes: object.attributes,
+					oam_index: object.oam_index,
+				};
+			}
+		}
+	}
+
+	// LCDC bits 0 and 1 apply to the pixels as they are shifted out
+	fn output_fifo_pixel(&mut self, bg_pixel: FifoPixel, obj_pixel: Option<FifoPixel>) {
+		let objects_enabled = PPU::get_bit(self.lcdc, 1) == 1 && self.active_layers[2];
+		let obj_result = match obj_pixel {
+			Some(pixel) if pixel.color != 0 && objects_enabled => Some((pixel.color, pixel.attributes)),
+			_ => None,
+		};
+		// The background is disabled in DMG mode if LCDC bit 0 == 0
+		let bg_color = match PPU::get_bit(self.lcdc, 0) == 0 && self.gb_mode == GBMode::DMG {
+			true => 0,
+			false => bg_pixel.color,
+		};
+		let mut final_color = self.get_final_color(bg_color, bg_pixel.attributes, obj_result);
+		if PPU::get_bit(self.lcdc, 7) == 0 {
+			final_color = Color::Logical(LogicalColor::White);
+		}
+		self.screen_buffer[self.ly as usize][self.fifo.lx as usize] = final_color;
+	}
+}
+
+
+
+// Mode 3 timings from the Pan Docs, as the acid2 and Mealybug Tearoom ROMs expect them
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::ppu::{Mode, GB_HEIGHT};
+
+	fn fifo_ppu() -> PPU {
+		let mut ppu = PPU::new();
+		ppu.initialize(GBMode::DMG);
+		ppu.fifo_renderer = true;
+		ppu.next_fifo_renderer = true;
+		ppu
+	}
+
+	// Runs until the next mode 3 starts
+	fn run_to_mode_3(ppu: &mut PPU) {
+		while ppu.mode != Mode::PixelTransfer {
+			ppu.dot();
+		}
+	}
+
+	// Returns the length of the next mode 3 in dots
+	fn mode_3_dots(ppu: &mut PPU) -> usize {
+		run_to_mode_3(ppu);
+		let mut dots = 0;
+		while ppu.mode == Mode::PixelTransfer {
+			ppu.dot();
+			dots += 1;
+		}
+		dots
+	}
+
+	// Runs mode 3 until the given pixel is the next one to be shifted out
+	fn run_to_pixel(ppu: &mut PPU, lx: u8) {
+		run_to_mode_3(ppu);
+		while ppu.fifo.lx < lx {
+			ppu.dot();
+		}
+	}
+
+	fn is_white(color: Color) -> bool {
+		matches!(color, Color::Logical(LogicalColor::White))
+	}
+
+	// Fills the first tile, used by the whole background, with color 3
+	fn black_background(ppu: &mut PPU) {
+		ppu.vram[..16].fill(0xFF);
+	}
+
+	// The penalty for a single object, from the Pan Docs
+	fn object_penalty(x_position: u8, scx: u8) -> usize {
+		let tile_x = (x_position as usize + scx as usize) % 8;
+		match x_position {
+			0 => 11,
+			_ => 6 + 5usize.saturating_sub(tile_x),
+		}
+	}
+
+	#[test]
+	fn mode_3_lasts_172_dots_and_the_fine_scroll() {
+		for scx in 0..16 {
+			let mut ppu = fifo_ppu();
+			ppu.scx = scx;
+			assert_eq!(mode_3_dots(&mut ppu), 172 + (scx % 8) as usize, "SCX = {}", scx);
+		}
+	}
+
+	#[test]
+	fn the_window_adds_6_dots() {
+		for wx in [8, 9, 50, 100, 166] {
+			let mut ppu = fifo_ppu();
+			ppu.lcdc |= 0b0010_0000;
+			ppu.wx = wx;
+			assert_eq!(mode_3_dots(&mut ppu), 178, "WX = {}", wx);
+		}
+		let mut ppu = fifo_ppu();
+		ppu.lcdc |= 0b0010_0000;
+		ppu.wx = 167;
+		assert_eq!(mode_3_dots(&mut ppu), 172);
+	}
+
+	#[test]
+	fn the_window_line_counter_only_counts_lines_with_the_window() {
+		let mut ppu = fifo_ppu();
+		ppu.lcdc |= 0b0010_0000;
+		ppu.wx = 7;
+		mode_3_dots(&mut ppu);
+		assert_eq!(ppu.window_line_counter, 1);
+		ppu.wx = 200;
+		mode_3_dots(&mut ppu);
+		assert_eq!(ppu.window_line_counter, 1);
+		ppu.wx = 80;
+		mode_3_dots(&mut ppu);
+		assert_eq!(ppu.window_line_counter, 2);
+	}
+
+	#[test]
+	fn object_penalties() {
+		for scx in 0..8 {
+			for x_position in 0..=167 {
+				let mut ppu = fifo_ppu();
+				ppu.lcdc |= 0b0000_0010;
+				ppu.scx = scx;
+				ppu.oam[..2].copy_from_slice(&[16, x_position]);
+				let penalty = mode_3_dots(&mut ppu) - 172 - scx as usize;
+				assert_eq!(penalty, object_penalty(x_position, scx), "X = {}, SCX = {}", x_position, scx);
+			}
+		}
+	}
+
+	#[test]
+	fn objects_in_the_same_tile_wait_once() {
+		let mut ppu = fifo_ppu();
+		ppu.lcdc |= 0b0000_0010;
+		ppu.oam[..8].copy_from_slice(&[16, 8, 0, 0, 16, 10, 0, 0]);
+		assert_eq!(mode_3_dots(&mut ppu) - 172, 11 + 6);
+	}
+
+	#[test]
+	fn at_most_10_objects_per_line() {
+		let mut ppu = fifo_ppu();
+		ppu.lcdc |= 0b0000_0010;
+		for object in ppu.oam.chunks_mut(4).take(11) {
+			object.copy_from_slice(&[16, 8, 0, 0]);
+		}
+		assert_eq!(mode_3_dots(&mut ppu) - 172, 11 + 9 * 6);
+	}
+
+	#[test]
+	fn palette_writes_apply_to_the_next_pixel() {
+		let mut ppu = fifo_ppu();
+		black_background(&mut ppu);
+		run_to_pixel(&mut ppu, 80);
+		ppu.bgp = 0x00;
+		mode_3_dots(&mut ppu);
+		assert!(!is_white(ppu.screen_buffer[0][79]));
+		assert!(is_white(ppu.screen_buffer[0][80]));
+	}
+
+	#[test]
+	fn background_enable_applies_to_the_next_pixel() {
+		let mut ppu = fifo_ppu();
+		black_background(&mut ppu);
+		run_to_pixel(&mut ppu, 84);
+		ppu.lcdc &= !0b0000_0001;
+		mode_3_dots(&mut ppu);
+		assert!(!is_white(ppu.screen_buffer[0][83]));
+		assert!(is_white(ppu.screen_buffer[0][84]));
+	}
+
+	#[test]
+	fn scroll_writes_apply_to_the_next_tile() {
+		let mut ppu = fifo_ppu();
+		// Tile 1 is black, and only used by the second half of the first map row
+		ppu.vram[16..32].fill(0xFF);
+		ppu.vram[0x1800 + 16..0x1800 + 32].fill(1);
+		run_to_pixel(&mut ppu, 1);
+		ppu.scx = 128;
+		mode_3_dots(&mut ppu);
+		// The first tile was already fetched, the next one is fetched with the new SCX
+		assert!(is_white(ppu.screen_buffer[0][7]));
+		assert!(!is_white(ppu.screen_buffer[0][8]));
+	}
+
+	// Pseudo-random bytes, so every tile and palette is different and asymmetric
+	fn noise(seed: u32) -> impl FnMut() -> u8 {
+		let mut state = seed;
+		move || {
+			state = state.wrapping_mul(1103515245).wrapping_add(12345);
+			(state >> 16) as u8
+		}
+	}
+
+	// Renders a frame of a static scene with both renderers, which must draw the same pixels.
+	// The scanline renderer is the reference, since the scene doesn't change during mode 3.
+	fn compare_renderers(gb_mode: GBMode, scene: impl Fn(&mut PPU)) {
+		let frames = [false, true].map(|fifo| {
+			let mut ppu = PPU::new();
+			ppu.initialize(gb_mode);
+			ppu.fifo_renderer = fifo;
+			ppu.next_fifo_renderer = fifo;
+			scene(&mut ppu);
+			while ppu.mode != Mode::VBlank {
+				ppu.dot();
+			}
+			ppu.screen_buffer.map(|line| line.map(|color| color.to_bits()))
+		});
+		for y in 0..GB_HEIGHT {
+			for x in 0..GB_WIDTH {
+				assert_eq!(frames[0][y][x], frames[1][y][x], "Pixel ({}, {})", x, y);
+			}
+		}
+	}
+
+	// A background of random tiles and maps, with 40 objects placed the way dmg-acid2
+	// and cgb-acid2 stack them: overlapping, flipped, behind the background, and
+	// partially off the edges of the screen
+	fn acid_scene(ppu: &mut PPU, seed: u32) {
+		let mut random = noise(seed);
+		for byte in ppu.vram.iter_mut() {
+			*byte = random();
+		}
+		ppu.bgp = 0xE4;
+		ppu.obp0 = 0xD2;
+		ppu.obp1 = 0x1B;
+		for i in 0..64 {
+			ppu.bg_cram[i] = random() & if i % 2 == 1 { 0x7F } else { 0xFF };
+			ppu.obj_cram[i] = random() & if i % 2 == 1 { 0x7F } else { 0xFF };
+		}
+		let x_positions = [0, 1, 4, 7, 8, 8, 9, 12, 12, 16, 20, 24, 60, 61, 62, 63, 64, 65, 66, 67,
+			68, 69, 70, 100, 100, 104, 150, 159, 160, 161, 164, 167, 168, 30, 30, 31, 40, 48, 120, 121];
+		for (i, object) in ppu.oam.chunks_mut(4).enumerate() {
+			let y_position = match i % 4 {
+				0 => 16 + (i as u8 * 3) % 136,
+				1 => 8 + (i as u8 * 5) % 16, // Partially above the screen
+				2 => 150 + i as u8 % 16, // Partially below the screen
+				_ => 40, // More than 10 objects on the same lines
+			};
+			object.copy_from_slice(&[y_position, x_positions[i], random(), random()]);
+		}
+	}
+
+	#[test]
+	fn dmg_scenes_match_the_scanline_renderer() {
+		for seed in 0..4 {
+			compare_renderers(GBMode::DMG, |ppu| {
+				acid_scene(ppu, seed);
+				ppu.lcdc = 0b1000_0011;
+			});
+			// Tall objects, which ignore bit 0 of the tile index
+			compare_renderers(GBMode::DMG, |ppu| {
+				acid_scene(ppu, seed);
+				ppu.lcdc = 0b1000_0111;
+			});
+			// The background is disabled, and objects are always on top
+			compare_renderers(GBMode::DMG, |ppu| {
+				acid_scene(ppu, seed);
+				ppu.lcdc = 0b1000_0010;
+			});
+		}
+	}
+
+	#[test]
+	fn dmg_windows_match_the_scanline_renderer() {
+		for (seed, (wx, wy)) in [(7, 0), (8, 30), (50, 100), (166, 10), (80, 143)].into_iter().enumerate() {
+			compare_renderers(GBMode::DMG, |ppu| {
+				acid_scene(ppu, seed as u32);
+				// Signed tile data, and different maps for the background and the window
+				ppu.lcdc = 0b1110_0111;
+				ppu.scx = 13 * seed as u8;
+				ppu.scy = 250 - 7 * seed as u8;
+				ppu.wx = wx;
+				ppu.wy = wy;
+			});
+		}
+	}
+
+	#[test]
+	fn cgb_scenes_match_the_scanline_renderer() {
+		for seed in 0..4 {
+			// Attributes choose the palette, the bank and the flip of every background tile,
+			// and the priority over objects
+			compare_renderers(GBMode::CGB, |ppu| {
+				acid_scene(ppu, seed);
+				ppu.lcdc = 0b1001_0011;
+			});
+			compare_renderers(GBMode::CGB, |ppu| {
+				acid_scene(ppu, seed);
+				ppu.lcdc = 0b1111_1111;
+				ppu.scx = 3;
+				ppu.wx = 40;
+				ppu.wy = 20;
+			});
+			// LCDC bit 0 takes away the priority of the background
+			compare_renderers(GBMode::CGB, |ppu| {
+				acid_scene(ppu, seed);
+				ppu.lcdc = 0b1001_0010;
+			});
+		}
+	}
+
+	#[test]
+	fn objects_with_the_same_x_are_drawn_in_oam_order() {
+		for gb_mode in [GBMode::DMG, GBMode::CGB] {
+			let mut ppu = fifo_ppu();
+			ppu.initialize(gb_mode);
+			ppu.obp0 = 0xE4;
+			ppu.obp1 = 0x1B;
+			let mut random = noise(1);
+			ppu.obj_cram = [0; 64].map(|_| random());
+			ppu.lcdc |= 0b0000_0010;
+			// Two objects of color 1 at the same X and one at a lower X, in different palettes
+			ppu.vram[16..32].copy_from_slice(&[0xFF, 0x00].repeat(8));
+			ppu.oam[..12].copy_from_slice(&[16, 20, 1, 0x10, 16, 20, 1, 0x01, 16, 16, 1, 0x02]);
+			mode_3_dots(&mut ppu);
+			match gb_mode {
+				// The lowest X wins, then the first in OAM
+				GBMode::DMG => {
+					assert!(matches!(ppu.screen_buffer[0][12], Color::Logical(LogicalColor::LightGray)));
+					assert!(matches!(ppu.screen_buffer[0][16], Color::Logical(LogicalColor::DarkGray)));
+				},
+				// The first in OAM wins
+				GBMode::CGB => {
+					assert_eq!(ppu.screen_buffer[0][12].to_bits(), PPU::cram_color(&ppu.obj_cram, 0, 1).to_bits());
+					assert_ne!(ppu.screen_buffer[0][12].to_bits(), PPU::cram_color(&ppu.obj_cram, 2, 1).to_bits());
+					assert_eq!(ppu.screen_buffer[0][16].to_bits(), PPU::cram_color(&ppu.obj_cram, 0, 1).to_bits());
+				},
+			}
+		}
+	}
+
+	#[test]
+	fn switching_renderers_waits_for_the_next_line() {
+		let mut ppu = fifo_ppu();
+		ppu.bgp = 0x00;
+		run_to_pixel(&mut ppu, 80);
+		ppu.set_fifo_renderer(false);
+		// The line is finished by the pixel FIFO
+		while ppu.mode == Mode::PixelTransfer {
+			ppu.dot();
+		}
+		assert!(ppu.screen_buffer[0].iter().all(|&color| is_white(color)));
+		run_to_mode_3(&mut ppu);
+		assert!(!ppu.fifo_renderer);
+	}
+}
@@ -0,0 +1,104 @@
+// The memory that is searched: cartridge RAM, WRAM and HRAM, in the banks
+// selected at the time
+pub const SEARCH_RANGES: [(u16, u16); 3] = [(0xA000, 0xBFFF), (0xC000, 0xDFFF), (0xFF80, 0xFFFE)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueSize {
+	Byte,
+	Word, // Little-endian, like the CPU
+}
+
+// Keeps the candidates whose value, compared to the last search, is...
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchFilter {
+	Unchanged,
+	Changed,
+	Increased,
+	Decreased,
+	Equal(i32), // ...a specific value
+}
+
+pub const SEARCH_FILTERS: [SearchFilter; 4] = [SearchFilter::Unchanged, SearchFilter::Changed,
+											   SearchFilter::Increased, SearchFilter::Decreased];
+
+impl SearchFilter {
+	pub fn name(&self) -> &'static str {
+		match self {
+			SearchFilter::Unchanged => "Unchanged",
+			SearchFilter::Changed => "Changed",
+			SearchFilter::Increased => "Increased",
+			SearchFilter::Decreased => "Decreased",
+			SearchFilter::Equal(_) => "Equal to",
+		}
+	}
+}
+
+// Narrows down the addresses of a value (like lives or money) by filtering
+// them as the value changes. Memory is read through a function, like
+// Emulator::peek, so searching doesn't need to borrow the emulator
+pub struct RamSearch {
+	pub size: ValueSize,
+	pub signed: bool,
+	candidates: Vec<(u16, i32)>, // Every address still matching, with its value at the last search
+}
+
+impl RamSearch {
+	// Starts a new search, with every address as a candidate
+	pub fn new(size: ValueSize, signed: bool, read: impl Fn(u16) -> u8) -> Self {
+		let mut ram_search = RamSearch {
+			size,
+			signed,
+			candidates: Vec::new(),
+		};
+		let last_offset = match size {
+			ValueSize::Byte => 0,
+			ValueSize::Word => 1,
+		};
+		for (start, end) in SEARCH_RANGES {
+			for address in start..=(end - last_offset) {
+				let value = ram_search.value(address, &read);
+				ram_search.candidates.push((address, value));
+			}
+		}
+		ram_search
+	}
+
+	// Removes the candidates that don't match, and remembers the current value of the others
+	pub fn filter(&mut self, filter: SearchFilter, read: impl Fn(u16) -> u8) {
+		let candidates = std::mem::take(&mut self.candidates);
+		self.candidates = candidates.into_iter().filter_map(|(address, previous)| {
+			let value = self.value(address, &read);
+			let matches = match filter {
+				SearchFilter::Unchanged => value == previous,
+				SearchFilter::Changed => value != previous,
+				SearchFilter::Increased => value > previous,
+				SearchFilter::Decreased => value < previous,
+				SearchFilter::Equal(expected) => value == expected,
+			};
+			matches.then_some((address, value))
+		}).collect();
+	}
+
+	// Returns every candidate, with its value at the last search
+	pub fn candidates(&self) -> &[(u16, i32)] {
+		&self.candidates
+	}
+
+	// Returns the bytes of a value, with the size of the search, to write it to memory
+	pub fn bytes(&self, value: i32) -> Vec<u8> {
+		match self.size {
+			ValueSize::Byte => vec![value as u8],
+			ValueSize::Word => (value as u16).to_le_bytes().to_vec(),
+		}
+	}
+
+	// Returns the current value at an address, with the size and signedness of the search
+	pub fn value(&self, address: u16, read: impl Fn(u16) -> u8) -> i32 {
+		match (self.size, self.signed) {
+			(ValueSize::Byte, false) => read(address) as i32,
+			(ValueSize::Byte, true) => read(address) as i8 as i32,
+			(ValueSize::Word, false) => u16::from_le_bytes([read(address), read(address + 1)]) as i32,
+			(ValueSize::Word, true) => i16::from_le_bytes([read(address), read(address + 1)]) as i32,
+		}
+	}
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+
+use crate::input::Input;
+
+pub const DEFAULT_REWIND_CAPACITY: usize = 64 * 1024 * 1024; // 64 MiB, well over a minute for most games
+const ENTRY_OVERHEAD: usize = 64; // Rough size of the bookkeeping of a single entry
+
+// A segment stored as the XOR against the same segment of the next (newer)
+// snapshot. Bytes past the end of the newer segment are stored as they are.
+// The result is then run-length encoded, since most bytes XOR to zero.
+struct Delta {
+	len: usize,
+	data: Vec<u8>,
+}
+
+impl Delta {
+	// Creates a delta that rebuilds the old segment from the new one
+	fn encode(old: &[u8], new: &[u8]) -> Self {
+		let mut xor = old.to_vec();
+		for (byte, new_byte) in xor.iter_mut().zip(new) {
+			*byte ^= new_byte;
+		}
+		Delta {
+			len: old.len(),
+			data: rle_encode(&xor),
+		}
+	}
+
+	// Rebuilds the old segment from the new one
+	fn apply(&self, new: &[u8]) -> Vec<u8> {
+		let mut old = rle_decode(&self.data, self.len);
+		for (byte, new_byte) in old.iter_mut().zip(new) {
+			*byte ^= new_byte;
+		}
+		old
+	}
+}
+
+struct RewindEntry {
+	input: Input, // The input of the frame that followed this snapshot
+	segments: Vec<Delta>,
+	size: usize,
+}
+
+// Holds the emulator's history for rewinding, inside a fixed memory budget.
+// The newest snapshot is kept whole as the keyframe, and every older snapshot
+// is stored as a delta against the one after it, so popping is cheap and the
+// oldest entries can be dropped at any time.
+pub struct RewindBuffer {
+	keyframe: Option<(Input, Vec<Vec<u8>>)>,
+	entries: VecDeque<RewindEntry>,
+	capacity: usize, // In bytes
+	memory_usage: usize,
+}
+
+impl RewindBuffer {
+	pub fn new(capacity: usize) -> Self {
+		RewindBuffer {
+			keyframe: None,
+			entries: VecDeque::new(),
+			capacity,
+			memory_usage: 0,
+		}
+	}
+
+	// Pushes a new snapshot, made of multiple segments, and the input used for the next frame
+	pub fn push(&mut self, input: Input, segments: Vec<Vec<u8>>) {
+		if let Some((old_input, old_segments)) = self.keyframe.take() {
+			let deltas: Vec<Delta> = old_segments.iter().zip(&segments)
+				.map(|(old, new)| Delta::encode(old, new)).collect();
+			let size = deltas.iter().map(|delta| delta.data.len()).sum::<usize>() + ENTRY_OVERHEAD;
+			self.memory_usage += size;
+			self.entries.push_back(RewindEntry {
+				input: old_input,
+				segments: deltas,
+				size,
+			});
+		}
+		self.keyframe = Some((input, segments));
+
+		// Drop the oldest entries until we fit in the memory budget
+		while self.memory_usage + self.keyframe_size() > self.capacity {
+			match self.entries.pop_front() {
+				Some(entry) => self.memory_usage -= entry.size,
+				None => break,
+			}
+		}
+	}
+
+	// Pops the newest snapshot, along with the input that was used for the frame after it
+	pub fn pop(&mut self) -> Option<(Input, Vec<Vec<u8>>)> {
+		let (input, segments) = self.keyframe.take()?;
+		if let Some(entry) = self.entries.pop_back() {
+			self.memory_usage -= entry.size;
+			let old_segments = entry.segments.iter().zip(&segments)
+				.map(|(delta, new)| delta.apply(new)).collect();
+			self.keyframe = Some((entry.input, old_segments));
+		}
+		Some((input, segments))
+	}
+
+	// Returns the newest snapshot without removing it, along with the input that was used for the frame after it
+	pub fn newest(&self) -> Option<(Input, &[Vec<u8>])> {
+		self.keyframe.as_ref().map(|(input, segments)| (*input, segments.as_slice()))
+	}
+
+	pub fn clear(&mut self) {
+		self.keyframe = None;
+		self.entries.clear();
+		self.memory_usage = 0;
+	}
+
+	// Changes the memory budget, dropping the oldest entries if needed
+	pub fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		while self.memory_usage + self.keyframe_size() > self.capacity {
+			match self.entries.pop_front() {
+				Some(entry) => self.memory_usage -= entry.size,
+				None => break,
+			}
+		}
+	}
+
+	// Returns the number of snapshots (frames) that can be rewound
+	pub fn len(&self) -> usize {
+		match self.keyframe {
+			Some(_) => self.entries.len() + 1,
+			None => 0,
+		}
+	}
+
+	// Returns the memory currently used, in bytes
+	pub fn memory_usage(&self) -> usize {
+		self.memory_usage + self.keyframe_size()
+	}
+
+	fn keyframe_size(&self) -> usize {
+		match &self.keyframe {
+			Some((_, segments)) => segments.iter().map(|segment| segment.len()).sum(),
+			None => 0,
+		}
+	}
+}
+
+// Encodes the data as pairs of (zero run, literal run), followed by the literals
+fn rle_encode(data: &[u8]) -> Vec<u8> {
+	let mut output = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let zeros_start = i;
+		while i < data.len() && data[i] == 0 {
+			i += 1;
+		}
+		let literals_start = i;
+		while i < data.len() && data[i] != 0 {
+			i += 1;
+		}
+		write_varint(&mut output, literals_start - zeros_start);
+		write_varint(&mut output, i - literals_start);
+		output.extend_from_slice(&data[literals_start..i]);
+	}
+	output
+}
+
+fn rle_decode(data: &[u8], len: usize) -> Vec<u8> {
+	let mut output = Vec::with_capacity(len);
+	let mut i = 0;
+	while i < data.len() {
+		let zeros = read_varint(data, &mut i);
+		let literals = read_varint(data, &mut i);
+		output.resize(output.len() + zeros, 0);
+		output.extend_from_slice(&data[i..i + literals]);
+		i += literals;
+	}
+	output.resize(len, 0);
+	output
+}
+
+// LEB128 variable-length integers
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+	while value >= 0x80 {
+		output.push((value as u8 & 0x7F) | 0x80);
+		value >>= 7;
+	}
+	output.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+	let mut value = 0;
+	let mut shift = 0;
+	loop {
+		let byte = data[*i];
+		*i += 1;
+		value |= ((byte & 0x7F) as usize) << shift;
+		if byte & 0x80 == 0 {
+			return value;
+		}
+		shift += 7;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn input(a: bool) -> Input {
+		let mut input = Input::new();
+		input.a = a;
+		input
+	}
+
+	// A snapshot that changes a little every frame, and grows on some frames
+	fn snapshot(frame: usize) -> Vec<Vec<u8>> {
+		let mut segment: Vec<u8> = (0..300).map(|i| (i % 7) as u8).collect();
+		segment[frame % 300] = frame as u8 ^ 0xFF;
+		segment[(frame * 31) % 300] = 0;
+		segment.resize(300 + frame % 3, 0xAA);
+		vec![segment, vec![frame as u8; 4], Vec::new()]
+	}
+
+	#[test]
+	fn varint_round_trip() {
+		let values = [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 123_456_789, usize::MAX];
+		let mut data = Vec::new();
+		for value in values {
+			write_varint(&mut data, value);
+		}
+		let mut i = 0;
+		for value in values {
+			assert_eq!(read_varint(&data, &mut i), value);
+		}
+		assert_eq!(i, data.len());
+	}
+
+	#[test]
+	fn rle_round_trip() {
+		let cases: [Vec<u8>; 6] = [
+			vec![],
+			vec![0; 1000],
+			vec![0xFF; 1000],
+			vec![0, 0, 1, 2, 0, 3, 0, 0, 0],
+			[vec![5], vec![0; 200], vec![9]].concat(), // A zero run longer than a 1-byte varint
+			(0..5000).map(|i| if i % 3 == 0 { 0 } else { (i % 251) as u8 }).collect(),
+		];
+		for data in cases {
+			assert_eq!(rle_decode(&rle_encode(&data), data.len()), data);
+		}
+	}
+
+	#[test]
+	fn delta_round_trip() {
+		let old = vec![1, 2, 3, 4, 5, 6];
+		for new in [vec![1, 2, 3, 4, 5, 6], vec![1, 0, 3, 9], vec![], vec![6, 5, 4, 3, 2, 1, 0, 7]] {
+			assert_eq!(Delta::encode(&old, &new).apply(&new), old);
+		}
+	}
+
+	#[test]
+	fn pops_every_snapshot_in_reverse() {
+		let mut buffer = RewindBuffer::new(usize::MAX);
+		for frame in 0..100 {
+			buffer.push(input(frame % 2 == 0), snapshot(frame));
+		}
+		assert_eq!(buffer.len(), 100);
+		for frame in (0..100).rev() {
+			let newest = buffer.newest().map(|(input, segments)| (input.a, segments.to_vec()));
+			assert_eq!(newest, Some((frame % 2 == 0, snapshot(frame))));
+			let (popped_input, segments) = buffer.pop().unwrap();
+			assert_eq!(popped_input.a, frame % 2 == 0);
+			assert_eq!(segments, snapshot(frame));
+		}
+		assert!(buffer.pop().is_none());
+		assert_eq!(buffer.memory_usage(), 0);
+	}
+
+	#[test]
+	fn drops_the_oldest_snapshots_over_capacity() {
+		let mut buffer = RewindBuffer::new(4096);
+		for frame in 0..1000 {
+			buffer.push(input(false), snapshot(frame));
+			assert!(buffer.memory_usage() <= 4096);
+		}
+		let kept = buffer.len();
+		assert!(kept > 1 && kept < 1000);
+		for frame in (1000 - kept..1000).rev() {
+			assert_eq!(buffer.pop().unwrap().1, snapshot(frame));
+		}
+		assert_eq!(buffer.len(), 0);
+	}
+}
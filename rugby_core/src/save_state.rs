@@ -4,7 +4,11 @@ use serde_big_array::BigArray;
 use crate::input::Input;
 use crate::gb_mode::GBMode;
 use crate::timer::Timer;
+use crate::serial::Serial;
+use crate::sgb::{Mask, Transfer};
+use crate::dma::{Hdma, OamDma};
 use crate::ppu::{Object, Mode};
+use crate::ppu::fifo::PixelFifo;
 use crate::apu::channels::PulseChannel;
 use crate::apu::channels::WaveChannel;
 use crate::apu::channels::NoiseChannel;
@@ -14,13 +18,26 @@ pub struct EmulatorState {
 	pub cpu_state: CPUState,
 	pub bus_state: BusState,
 	pub cartridge_json: String,
+	#[serde(default)]
+	pub movie_frame: Option<usize>, // The frame of the movie when the state was saved, if there was one
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct BusState {
+	#[serde(default = "OamDma::new")]
+	pub oam_dma: OamDma,
+	#[serde(default = "Hdma::new")]
+	pub hdma: Hdma,
+	#[serde(default)]
+	pub key1: u8,
+	#[serde(default)]
+	pub double_speed: bool,
+	#[serde(default)]
+	pub speed_switch_cycles: u16,
 	pub mmu_state: MMUState,
-	pub ppu_state: PPUState,
 	pub apu_state: APUState,
+	// Kept last, since its length changes between frames
+	pub ppu_state: PPUState,
 }
 
 // Missing the lookup table
@@ -32,6 +49,8 @@ pub struct CPUState {
 	pub ime: u8,
 	pub ime_scheduled: bool,
 	pub halt_mode: bool,
+	#[serde(default)]
+	pub stop_mode: bool,
 	pub rtc_oscillator: u64,
 
 	pub gb_mode: GBMode,
@@ -52,14 +71,36 @@ pub struct MMUState {
 	pub input: Input,
 	pub prev_p1: u8,
 	pub joypad_interrupt: bool,
-	#[serde(with = "BigArray")]
-	pub serial_buffer: [u8; 100],
-	pub serial_file_path: String,
+	#[serde(default = "Serial::new")]
+	pub serial: Serial,
+	#[serde(default)]
+	pub sgb_state: Option<SGBState>,
 
 	pub gb_mode: GBMode,
 	pub svbk: u8,
 }
 
+// Missing the screen and the border
+#[derive(Serialize, Deserialize)]
+pub struct SGBState {
+	pub commands_enabled: bool,
+	pub packets: Vec<u8>,
+	pub bits_received: usize,
+	pub ready_for_pulse: bool,
+	pub ready_for_write: bool,
+	pub ready_for_stop: bool,
+	pub palettes: [[u16; 4]; 4],
+	pub system_palettes: Vec<[u16; 4]>,
+	pub attributes: [[u8; 20]; 18],
+	pub attribute_files: Vec<u8>,
+	pub border_tiles: Vec<u8>,
+	pub border_map: Vec<u8>,
+	pub mask: Mask,
+	pub transfer: Option<(Transfer, u8)>,
+	pub player_count: u8,
+	pub current_player: u8,
+}
+
 // Missing screen
 #[derive(Serialize, Deserialize)]
 pub struct PPUState {
@@ -74,7 +115,6 @@ pub struct PPUState {
     pub window_line_counter: u8,
     pub window_in_frame: bool,
     pub window_in_line: bool,
-    pub object_buffer: Vec<Object>,
     pub bgp: u8,
     pub obp0: u8,
     pub obp1: u8,
@@ -101,6 +141,13 @@ pub struct PPUState {
 	pub obpi: u8,
 	pub opri: u8,
 	pub entered_hblank: bool,
+	#[serde(default)]
+	pub compatibility_mode: bool,
+	#[serde(default = "PixelFifo::new")]
+	pub fifo: PixelFifo,
+
+	// Kept last, since its length changes between frames
+	pub object_buffer: Vec<Object>,
 }
 
 // Missing the callback function
@@ -119,7 +166,7 @@ pub struct APUState {
 	pub nr52: u8, 
 	pub div_apu: u8,
 	pub prev_div_apu: u8,
-	pub capacitor: f32,
+	pub capacitors: [f32; 2],
 	pub internal_cycles: u16,
 	pub is_mute: bool,
 }
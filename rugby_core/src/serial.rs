@@ -0,0 +1,78 @@
+use serde::{Serialize, Deserialize};
+
+use crate::gb_mode::GBMode;
+
+// The serial port, without a link cable. A transfer with the internal clock
+// shifts in 1s, as if nothing was connected. It is clocked by the same
+// internal counter as the timer, so it also runs twice as fast in double speed.
+#[derive(Clone, Copy)]
+#[derive(Serialize, Deserialize)]
+pub struct Serial {
+	pub sb: u8,
+	sc: u8,
+	bits_left: u8,
+	previous_bit: u8,
+	gb_mode: GBMode,
+	pub serial_interrupt: bool,
+}
+
+impl Serial {
+	pub fn new() -> Self {
+		Serial {
+			sb: 0,
+			sc: 0,
+			bits_left: 0,
+			previous_bit: 0,
+			gb_mode: GBMode::DMG,
+			serial_interrupt: false,
+		}
+	}
+
+	pub fn initialize(&mut self, gb_mode: GBMode) {
+		self.sb = 0;
+		self.sc = 0;
+		self.bits_left = 0;
+		self.previous_bit = 0;
+		self.gb_mode = gb_mode;
+		self.serial_interrupt = false;
+	}
+
+	pub fn get_sc(&self) -> u8 {
+		match self.gb_mode {
+			GBMode::DMG => self.sc | 0x7E,
+			GBMode::CGB => self.sc | 0x7C,
+		}
+	}
+
+	pub fn set_sc(&mut self, value: u8) {
+		// Bit 1 selects the fast clock, only on the Gameboy Color
+		self.sc = match self.gb_mode {
+			GBMode::DMG => value & 0x81,
+			GBMode::CGB => value & 0x83,
+		};
+		// Only transfers with the internal clock ever finish
+		self.bits_left = match self.sc & 0x81 {
+			0x81 => 8,
+			_ => 0,
+		};
+	}
+
+	// Update the transfer, given the internal counter of the timer. This is called every M-Cycle
+	pub fn tick(&mut self, counter: u16) {
+		// 8192 Hz, or 262144 Hz with the fast clock
+		let clock_bit = match self.sc & 0x02 {
+			0 => 8,
+			_ => 3,
+		};
+		let bit = ((counter >> clock_bit) & 0x01) as u8;
+		if self.bits_left > 0 && self.previous_bit == 1 && bit == 0 {
+			self.sb = (self.sb << 1) | 0x01;
+			self.bits_left -= 1;
+			if self.bits_left == 0 {
+				self.sc &= 0x7F;
+				self.serial_interrupt = true;
+			}
+		}
+		self.previous_bit = bit;
+	}
+}
@@ -0,0 +1,718 @@
+use serde::{Serialize, Deserialize};
+
+use crate::color::{Color, LogicalColor};
+use crate::ppu::{GB_WIDTH, GB_HEIGHT};
+use crate::save_state::SGBState;
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+// Where the game screen is drawn inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+const PACKET_BITS: usize = 16 * 8;
+const MAX_PACKETS: usize = 7;
+const TRANSFER_SIZE: usize = 4096;
+const TRANSFER_FRAMES: u8 = 3; // Frames until the SNES reads the data of a VRAM transfer from the screen
+const ATTRIBUTE_FILES: usize = 45;
+const ATTRIBUTE_FILE_SIZE: usize = 90; // 20x18 tiles, 4 per byte
+const TILES_X: usize = GB_WIDTH / 8;
+const TILES_Y: usize = GB_HEIGHT / 8;
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+// Commands sent by the game in packets
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// The data that a VRAM transfer copies from the screen
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum Transfer {
+	BorderTiles(usize), // The first or the second half of the tiles
+	BorderMap,
+	SystemPalettes,
+	AttributeFiles,
+}
+
+// How the game screen is masked, while the game prepares it
+#[derive(Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize)]
+pub enum Mask {
+	Cancel,
+	Freeze,
+	Black,
+	Color0,
+}
+
+// The Super Game Boy. The game sends it commands in packets through P1, and
+// it colors the screen with 4 palettes picked for every 8x8 tile, and draws a
+// border around it. Data that is too large for packets (border tiles and
+// palettes) is sent by displaying it on the screen.
+pub struct Sgb {
+	commands_enabled: bool, // Only games with the SGB flag can send commands
+
+	// Packet transfer
+	packets: [u8; MAX_PACKETS * PACKET_BITS / 8],
+	bits_received: usize,
+	ready_for_pulse: bool,
+	ready_for_write: bool,
+	ready_for_stop: bool,
+
+	palettes: [[u16; 4]; 4],
+	system_palettes: Vec<[u16; 4]>,
+	attributes: [[u8; TILES_X]; TILES_Y],
+	attribute_files: Vec<u8>,
+	border_tiles: Vec<u8>, // 256 tiles in the 4bpp format of the SNES
+	border_map: Vec<u8>, // 32x32 entries, and then the 4 palettes of the border
+	mask: Mask,
+	transfer: Option<(Transfer, u8)>,
+
+	player_count: u8,
+	current_player: u8,
+
+	screen: [[Color; GB_WIDTH]; GB_HEIGHT],
+	frame: [[Color; SGB_WIDTH]; SGB_HEIGHT], // The screen inside the border
+}
+
+impl Sgb {
+	pub fn new(commands_enabled: bool) -> Self {
+		let mut sgb = Sgb {
+			commands_enabled,
+
+			packets: [0; MAX_PACKETS * PACKET_BITS / 8],
+			bits_received: 0,
+			ready_for_pulse: true, // The boot ROM leaves P14 and P15 high
+			ready_for_write: false,
+			ready_for_stop: false,
+
+			palettes: [DEFAULT_PALETTE; 4],
+			system_palettes: vec![[0; 4]; 512],
+			attributes: [[0; TILES_X]; TILES_Y],
+			attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+			border_tiles: vec![0; 2 * TRANSFER_SIZE],
+			border_map: vec![0; TRANSFER_SIZE],
+			mask: Mask::Cancel,
+			transfer: None,
+
+			player_count: 1,
+			current_player: 0,
+
+			screen: [[Color::Logical(LogicalColor::White); GB_WIDTH]; GB_HEIGHT],
+			frame: [[Color::RGB(0); SGB_WIDTH]; SGB_HEIGHT],
+		};
+		sgb.render_border();
+		sgb
+	}
+
+	// Receives a bit of a packet, from the P14 and P15 bits written to P1.
+	// A packet starts with both low, then every bit is a pulse of one of
+	// them (P14 for 0, P15 for 1), and it ends with a 0.
+	pub fn write_p1(&mut self, value: u8, previous_value: u8) {
+		// With more than one player, P15 going high selects the next joypad
+		if self.player_count > 1 && value & 0x20 != 0 && previous_value & 0x20 == 0 {
+			self.current_player = (self.current_player + 1) % self.player_count;
+		}
+		if !self.commands_enabled {
+			return;
+		}
+
+		let packet_count = match self.packets[0] & 0x07 {
+			0 => 1,
+			count => count as usize,
+		};
+		match value & 0x30 {
+			0x30 => self.ready_for_pulse = true,
+			0x00 => {
+				if !self.ready_for_pulse {
+					return;
+				}
+				self.ready_for_write = true;
+				self.ready_for_pulse = false;
+				// A reset pulse in the middle of a packet starts over
+				if !self.bits_received.is_multiple_of(PACKET_BITS) || self.bits_received == 0 || self.ready_for_stop {
+					self.clear_packets();
+				}
+			},
+			bit => {
+				if !self.ready_for_pulse || !self.ready_for_write {
+					return;
+				}
+				self.ready_for_pulse = false;
+				if self.ready_for_stop {
+					// The stop bit must be a 0
+					if bit == 0x20 && self.bits_received == packet_count * PACKET_BITS {
+						self.execute_command();
+						self.clear_packets();
+					} else if bit == 0x10 {
+						self.clear_packets();
+					}
+					self.ready_for_write = false;
+					self.ready_for_stop = false;
+				} else if self.bits_received < MAX_PACKETS * PACKET_BITS {
+					if bit == 0x10 {
+						self.packets[self.bits_received / 8] |= 1 << (self.bits_received % 8);
+					}
+					self.bits_received += 1;
+					self.ready_for_stop = self.bits_received.is_multiple_of(PACKET_BITS);
+				}
+			},
+		}
+	}
+
+	// Returns the lower bits of P1 when no buttons are selected, which are
+	// the ID of the current joypad with more than one player
+	pub fn joypad_id(&self) -> u8 {
+		0x0F - self.current_player
+	}
+
+	// Returns whether the buttons of the first player are read
+	pub fn first_player(&self) -> bool {
+		self.current_player == 0
+	}
+
+	fn clear_packets(&mut self) {
+		self.packets = [0; MAX_PACKETS * PACKET_BITS / 8];
+		self.bits_received = 0;
+		self.ready_for_stop = false;
+	}
+
+	fn execute_command(&mut self) {
+		let data = self.packets;
+		match data[0] >> 3 {
+			PAL01 => self.set_palette_pair(&data, 0, 1),
+			PAL23 => self.set_palette_pair(&data, 2, 3),
+			PAL03 => self.set_palette_pair(&data, 0, 3),
+			PAL12 => self.set_palette_pair(&data, 1, 2),
+			ATTR_BLK => self.attribute_blocks(&data),
+			ATTR_LIN => self.attribute_lines(&data),
+			ATTR_DIV => self.attribute_division(&data),
+			ATTR_CHR => self.attribute_characters(&data),
+			PAL_SET => {
+				for i in 0..4 {
+					let index = (data[1 + 2 * i] as usize | (data[2 + 2 * i] as usize) << 8) & 0x1FF;
+					self.palettes[i] = self.system_palettes[index];
+				}
+				// Color 0 is shared by all palettes
+				for i in 1..4 {
+					self.palettes[i][0] = self.palettes[0][0];
+				}
+				if data[9] & 0x80 != 0 {
+					self.apply_attribute_file(data[9] as usize & 0x3F);
+				}
+				if data[9] & 0x40 != 0 {
+					self.mask = Mask::Cancel;
+				}
+				self.render_border();
+			},
+			PAL_TRN => self.transfer = Some((Transfer::SystemPalettes, TRANSFER_FRAMES)),
+			MLT_REQ => {
+				self.player_count = match data[1] & 0x03 {
+					1 => 2,
+					3 => 4,
+					_ => 1,
+				};
+				self.current_player = 0;
+			},
+			CHR_TRN => self.transfer = Some((Transfer::BorderTiles(data[1] as usize & 0x01), TRANSFER_FRAMES)),
+			PCT_TRN => self.transfer = Some((Transfer::BorderMap, TRANSFER_FRAMES)),
+			ATTR_TRN => self.transfer = Some((Transfer::AttributeFiles, TRANSFER_FRAMES)),
+			ATTR_SET => {
+				self.apply_attribute_file(data[1] as usize & 0x3F);
+				if data[1] & 0x40 != 0 {
+					self.mask = Mask::Cancel;
+				}
+			},
+			MASK_EN => {
+				self.mask = match data[1] & 0x03 {
+					0 => Mask::Cancel,
+					1 => Mask::Freeze,
+					2 => Mask::Black,
+					_ => Mask::Color0,
+				};
+			},
+			// Sound, SNES code and the other commands are ignored
+			_ => (),
+		}
+	}
+
+	// PAL01, PAL23, PAL03 and PAL12 set color 0 of every palette, and colors 1-3 of two of them
+	fn set_palette_pair(&mut self, data: &[u8], first: usize, second: usize) {
+		let color = |i: usize| data[1 + 2 * i] as u16 | (data[2 + 2 * i] as u16) << 8;
+		for palette in self.palettes.iter_mut() {
+			palette[0] = color(0);
+		}
+		for i in 1..4 {
+			self.palettes[first][i] = color(i);
+			self.palettes[second][i] = color(i + 3);
+		}
+		self.render_border();
+	}
+
+	// ATTR_BLK colors the inside, the outside, and the border of rectangles
+	fn attribute_blocks(&mut self, data: &[u8]) {
+		let count = (data[1] & 0x1F).min(18) as usize;
+		for block in data[2..].chunks(6).take(count) {
+			let control = block[0] & 0x07;
+			let inside_palette = block[1] & 0x03;
+			let outside_palette = (block[1] >> 4) & 0x03;
+			// If only the inside or the outside is changed, the border is changed along with it
+			let border_palette = match control {
+				0x01 => Some(inside_palette),
+				0x04 => Some(outside_palette),
+				_ if control & 0x02 != 0 => Some((block[1] >> 2) & 0x03),
+				_ => None,
+			};
+			let (x1, y1, x2, y2) = (block[2] as usize, block[3] as usize, block[4] as usize, block[5] as usize);
+			for y in 0..TILES_Y {
+				for x in 0..TILES_X {
+					let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+						(control & 0x01 != 0).then_some(inside_palette)
+					} else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+						border_palette
+					} else {
+						(control & 0x04 != 0).then_some(outside_palette)
+					};
+					if let Some(palette) = palette {
+						self.attributes[y][x] = palette;
+					}
+				}
+			}
+		}
+	}
+
+	// ATTR_LIN colors whole rows or columns
+	fn attribute_lines(&mut self, data: &[u8]) {
+		let count = data[1] as usize;
+		for &line in data[2..].iter().take(count) {
+			let index = (line & 0x1F) as usize;
+			let palette = (line >> 5) & 0x03;
+			if line & 0x80 != 0 {
+				if index < TILES_Y {
+					self.attributes[index] = [palette; TILES_X];
+				}
+			} else if index < TILES_X {
+				for row in self.attributes.iter_mut() {
+					row[index] = palette;
+				}
+			}
+		}
+	}
+
+	// ATTR_DIV splits the screen in two, with a line in between
+	fn attribute_division(&mut self, data: &[u8]) {
+		let after_palette = data[1] & 0x03;
+		let before_palette = (data[1] >> 2) & 0x03;
+		let line_palette = (data[1] >> 4) & 0x03;
+		let horizontal = data[1] & 0x40 != 0;
+		let line = data[2] as usize;
+		for y in 0..TILES_Y {
+			for x in 0..TILES_X {
+				let position = if horizontal { y } else { x };
+				self.attributes[y][x] = match position.cmp(&line) {
+					std::cmp::Ordering::Less => before_palette,
+					std::cmp::Ordering::Equal => line_palette,
+					std::cmp::Ordering::Greater => after_palette,
+				};
+			}
+		}
+	}
+
+	// ATTR_CHR colors tiles one by one, from a starting position
+	fn attribute_characters(&mut self, data: &[u8]) {
+		let mut x = data[1] as usize;
+		let mut y = data[2] as usize;
+		let count = (data[3] as usize | (data[4] as usize) << 8).min(TILES_X * TILES_Y);
+		let vertical = data[5] & 0x01 != 0;
+		for i in 0..count {
+			if x >= TILES_X || y >= TILES_Y {
+				break;
+			}
+			let Some(&byte) = data.get(6 + i / 4) else {
+				break;
+			};
+			self.attributes[y][x] = (byte >> (6 - 2 * (i % 4))) & 0x03;
+			if vertical {
+				y += 1;
+				if y == TILES_Y {
+					y = 0;
+					x += 1;
+				}
+			} else {
+				x += 1;
+				if x == TILES_X {
+					x = 0;
+					y += 1;
+				}
+			}
+		}
+	}
+
+	fn apply_attribute_file(&mut self, file: usize) {
+		if file >= ATTRIBUTE_FILES {
+			return;
+		}
+		let bytes = &self.attribute_files[file * ATTRIBUTE_FILE_SIZE..(file + 1) * ATTRIBUTE_FILE_SIZE];
+		for i in 0..TILES_X * TILES_Y {
+			self.attributes[i / TILES_X][i % TILES_X] = (bytes[i / 4] >> (6 - 2 * (i % 4))) & 0x03;
+		}
+	}
+
+	// Colors a frame from the PPU, and finishes a VRAM transfer that is
+	// waiting for the data to be displayed. This is called after every frame.
+	pub fn update(&mut self, screen: &[[Color; GB_WIDTH]; GB_HEIGHT]) {
+		if let Some((transfer, frames)) = self.transfer {
+			match frames {
+				0 | 1 => {
+					self.transfer = None;
+					self.finish_transfer(transfer, screen);
+				},
+				_ => self.transfer = Some((transfer, frames - 1)),
+			}
+		}
+
+		match self.mask {
+			Mask::Cancel => {
+				for (y, row) in self.screen.iter_mut().enumerate() {
+					for (x, color) in row.iter_mut().enumerate() {
+						let palette = self.attributes[y / 8][x / 8] as usize;
+						*color = Color::RGB(self.palettes[palette][Sgb::shade(&screen[y][x])]);
+					}
+				}
+			},
+			Mask::Freeze => (),
+			Mask::Black => self.screen = [[Color::RGB(0); GB_WIDTH]; GB_HEIGHT],
+			Mask::Color0 => self.screen = [[Color::RGB(self.palettes[0][0]); GB_WIDTH]; GB_HEIGHT],
+		}
+		self.draw_screen();
+	}
+
+	fn draw_screen(&mut self) {
+		for (y, row) in self.screen.iter().enumerate() {
+			self.frame[SCREEN_Y + y][SCREEN_X..SCREEN_X + GB_WIDTH].copy_from_slice(row);
+		}
+	}
+
+	fn finish_transfer(&mut self, transfer: Transfer, screen: &[[Color; GB_WIDTH]; GB_HEIGHT]) {
+		let data = Sgb::screen_data(screen);
+		match transfer {
+			Transfer::BorderTiles(half) => {
+				self.border_tiles[half * TRANSFER_SIZE..(half + 1) * TRANSFER_SIZE].copy_from_slice(&data);
+			},
+			Transfer::BorderMap => self.border_map.copy_from_slice(&data),
+			Transfer::SystemPalettes => {
+				for (palette, bytes) in self.system_palettes.iter_mut().zip(data.chunks(8)) {
+					for i in 0..4 {
+						palette[i] = bytes[2 * i] as u16 | (bytes[2 * i + 1] as u16) << 8;
+					}
+				}
+			},
+			Transfer::AttributeFiles => {
+				let size = ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE;
+				self.attribute_files.copy_from_slice(&data[..size]);
+			},
+		}
+		self.render_border();
+	}
+
+	// Rebuilds the tile data that is displayed: the first 256 tiles of the
+	// screen, left to right and top to bottom, from the shades of their pixels
+	fn screen_data(screen: &[[Color; GB_WIDTH]; GB_HEIGHT]) -> Vec<u8> {
+		let mut data = vec![0; TRANSFER_SIZE];
+		for tile in 0..TRANSFER_SIZE / 16 {
+			let (tile_x, tile_y) = (tile % TILES_X, tile / TILES_X);
+			for row in 0..8 {
+				let mut low = 0;
+				let mut high = 0;
+				for column in 0..8 {
+					let shade = Sgb::shade(&screen[tile_y * 8 + row][tile_x * 8 + column]);
+					low |= ((shade & 0x01) as u8) << (7 - column);
+					high |= ((shade >> 1) as u8) << (7 - column);
+				}
+				data[tile * 16 + 2 * row] = low;
+				data[tile * 16 + 2 * row + 1] = high;
+			}
+		}
+		data
+	}
+
+	fn shade(color: &Color) -> usize {
+		match color {
+			Color::Logical(LogicalColor::White) => 0,
+			Color::Logical(LogicalColor::LightGray) => 1,
+			Color::Logical(LogicalColor::DarkGray) => 2,
+			Color::Logical(LogicalColor::Black) => 3,
+			Color::RGB(_) => 0,
+		}
+	}
+
+	// Draws the border from its tile map, with transparent pixels showing
+	// color 0. The game screen is then drawn over it.
+	fn render_border(&mut self) {
+		let backdrop = self.palettes[0][0];
+		for tile_y in 0..SGB_HEIGHT / 8 {
+			for tile_x in 0..SGB_WIDTH / 8 {
+				let offset = 2 * (tile_y * 32 + tile_x);
+				let entry = self.border_map[offset] as u16 | (self.border_map[offset + 1] as u16) << 8;
+				let tile = (entry & 0xFF) as usize * 32;
+				let palette = ((entry >> 10) & 0x03) as usize;
+				let flip_x = entry & 0x4000 != 0;
+				let flip_y = entry & 0x8000 != 0;
+				for row in 0..8 {
+					let tile_row = if flip_y { 7 - row } else { row };
+					let planes = [
+						self.border_tiles[tile + 2 * tile_row],
+						self.border_tiles[tile + 2 * tile_row + 1],
+						self.border_tiles[tile + 16 + 2 * tile_row],
+						self.border_tiles[tile + 16 + 2 * tile_row + 1],
+					];
+					for column in 0..8 {
+						let bit = if flip_x { column } else { 7 - column };
+						let index = planes.iter().enumerate()
+							.fold(0, |index, (i, plane)| index | ((plane >> bit) & 0x01) << i) as usize;
+						let color = match index {
+							0 => backdrop,
+							_ => {
+								let offset = 0x800 + 32 * palette + 2 * index;
+								self.border_map[offset] as u16 | (self.border_map[offset + 1] as u16) << 8
+							},
+						};
+						self.frame[tile_y * 8 + row][tile_x * 8 + column] = Color::RGB(color);
+					}
+				}
+			}
+		}
+		self.draw_screen();
+	}
+
+	pub fn get_screen(&self) -> &[[Color; GB_WIDTH]; GB_HEIGHT] {
+		&self.screen
+	}
+
+	// Replaces the colored screen, e.g. when a snapshot is restored
+	pub fn set_screen(&mut self, screen: &[[Color; GB_WIDTH]; GB_HEIGHT]) {
+		self.screen = *screen;
+		self.draw_screen();
+	}
+
+	pub fn get_frame(&self) -> &[[Color; SGB_WIDTH]; SGB_HEIGHT] {
+		&self.frame
+	}
+
+	// Creates an SGB state from the SGB
+	pub fn create_state(&self) -> SGBState {
+		SGBState {
+			commands_enabled: self.commands_enabled,
+			packets: self.packets.to_vec(),
+			bits_received: self.bits_received,
+			ready_for_pulse: self.ready_for_pulse,
+			ready_for_write: self.ready_for_write,
+			ready_for_stop: self.ready_for_stop,
+			palettes: self.palettes,
+			system_palettes: self.system_palettes.clone(),
+			attributes: self.attributes,
+			attribute_files: self.attribute_files.clone(),
+			border_tiles: self.border_tiles.clone(),
+			border_map: self.border_map.clone(),
+			mask: self.mask,
+			transfer: self.transfer,
+			player_count: self.player_count,
+			current_player: self.current_player,
+		}
+	}
+
+	// Loads an SGBState to the SGB. Fails without changing anything if the
+	// data in the state doesn't have the right size
+	pub fn load_state(&mut self, sgb_state: SGBState) -> Result<(), String> {
+		let sizes = [
+			(sgb_state.packets.len(), self.packets.len()),
+			(sgb_state.system_palettes.len(), self.system_palettes.len()),
+			(sgb_state.attribute_files.len(), self.attribute_files.len()),
+			(sgb_state.border_tiles.len(), self.border_tiles.len()),
+			(sgb_state.border_map.len(), self.border_map.len()),
+		];
+		if sizes.iter().any(|(size, expected)| size != expected) {
+			return Err("Invalid Super Game Boy state".to_string());
+		}
+		self.commands_enabled = sgb_state.commands_enabled;
+		self.packets.copy_from_slice(&sgb_state.packets);
+		self.bits_received = sgb_state.bits_received;
+		self.ready_for_pulse = sgb_state.ready_for_pulse;
+		self.ready_for_write = sgb_state.ready_for_write;
+		self.ready_for_stop = sgb_state.ready_for_stop;
+		self.palettes = sgb_state.palettes;
+		self.system_palettes = sgb_state.system_palettes;
+		self.attributes = sgb_state.attributes;
+		self.attribute_files = sgb_state.attribute_files;
+		self.border_tiles = sgb_state.border_tiles;
+		self.border_map = sgb_state.border_map;
+		self.mask = sgb_state.mask;
+		self.transfer = sgb_state.transfer;
+		self.player_count = sgb_state.player_count;
+		self.current_player = sgb_state.current_player;
+		self.render_border();
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Sends packets to the SGB like a game writing to P1: a reset pulse, the
+	// 128 bits of every packet, and a stop bit after every packet
+	fn send(sgb: &mut Sgb, packets: &[[u8; 16]]) {
+		let mut previous_value = 0x30;
+		let mut write = |sgb: &mut Sgb, value: u8| {
+			sgb.write_p1(value, previous_value);
+			previous_value = value;
+		};
+		for packet in packets {
+			write(sgb, 0x00);
+			write(sgb, 0x30);
+			for i in 0..PACKET_BITS {
+				write(sgb, if packet[i / 8] & (1 << (i % 8)) != 0 { 0x10 } else { 0x20 });
+				write(sgb, 0x30);
+			}
+			write(sgb, 0x20);
+			write(sgb, 0x30);
+		}
+	}
+
+	fn packet(bytes: &[u8]) -> [u8; 16] {
+		let mut packet = [0; 16];
+		packet[..bytes.len()].copy_from_slice(bytes);
+		packet
+	}
+
+	#[test]
+	fn sets_palette_pairs() {
+		let mut sgb = Sgb::new(true);
+		send(&mut sgb, &[packet(&[PAL01 << 3 | 1, 0x11, 0x11, 0x01, 0, 0x02, 0, 0x03, 0, 0x04, 0, 0x05, 0, 0x06, 0])]);
+		assert_eq!(sgb.palettes[0], [0x1111, 0x01, 0x02, 0x03]);
+		assert_eq!(sgb.palettes[1], [0x1111, 0x04, 0x05, 0x06]);
+		assert_eq!(sgb.palettes[2], [0x1111, DEFAULT_PALETTE[1], DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]]);
+	}
+
+	#[test]
+	fn ignores_commands_of_games_without_the_sgb_flag() {
+		let mut sgb = Sgb::new(false);
+		send(&mut sgb, &[packet(&[PAL01 << 3 | 1, 0x11, 0x11])]);
+		assert_eq!(sgb.palettes[0], DEFAULT_PALETTE);
+	}
+
+	#[test]
+	fn discards_packets_with_a_wrong_stop_bit() {
+		let mut sgb = Sgb::new(true);
+		let mut previous_value = 0x30;
+		for value in [0x00, 0x30].into_iter()
+			.chain((0..PACKET_BITS).flat_map(|i| [if i == 3 { 0x10 } else { 0x20 }, 0x30]))
+			.chain([0x10, 0x30]) {
+			sgb.write_p1(value, previous_value);
+			previous_value = value;
+		}
+		assert_eq!(sgb.palettes[0], DEFAULT_PALETTE);
+		assert_eq!(sgb.bits_received, 0);
+	}
+
+	#[test]
+	fn executes_multi_packet_attribute_blocks() {
+		let mut sgb = Sgb::new(true);
+		let first = packet(&[
+			ATTR_BLK << 3 | 2, 3,
+			0x01, 0x01, 0, 0, 2, 2, // Inside (and border) of the top left corner to palette 1
+			0x02, 0x08, 10, 10, 12, 12, // Border of a square to palette 2
+			0x01, 0x03, // The third block continues in the next packet
+		]);
+		let second = packet(&[4, 4, 8, 8]);
+
+		// Nothing happens until every packet of the command is received
+		send(&mut sgb, &[first]);
+		assert_eq!(sgb.attributes, [[0; TILES_X]; TILES_Y]);
+		send(&mut sgb, &[second]);
+
+		assert_eq!(sgb.attributes[0][0], 1);
+		assert_eq!(sgb.attributes[2][1], 1);
+		assert_eq!(sgb.attributes[10][10], 2);
+		assert_eq!(sgb.attributes[12][11], 2);
+		assert_eq!(sgb.attributes[11][11], 0);
+		assert_eq!(sgb.attributes[4][8], 3);
+		assert_eq!(sgb.attributes[6][6], 3);
+		assert_eq!(sgb.attributes[9][9], 0);
+		assert_eq!(sgb.attributes[17][19], 0);
+	}
+
+	#[test]
+	fn sets_system_palettes_and_attribute_files() {
+		let mut sgb = Sgb::new(true);
+		for i in 0..5 {
+			sgb.system_palettes[0x100 + i] = [i as u16 * 4, i as u16 * 4 + 1, i as u16 * 4 + 2, i as u16 * 4 + 3];
+		}
+		// Palette 3 on the left half of the screen
+		for row in 0..TILES_Y {
+			let offset = 2 * ATTRIBUTE_FILE_SIZE + row * TILES_X / 4;
+			sgb.attribute_files[offset..offset + 3].fill(0xFF);
+			sgb.attribute_files[offset + 3..offset + 5].fill(0x00);
+		}
+
+		send(&mut sgb, &[packet(&[PAL_SET << 3 | 1, 0x01, 0x01, 0x02, 0x01, 0x03, 0x01, 0x04, 0x01, 0x80 | 2])]);
+		assert_eq!(sgb.palettes[0], [4, 5, 6, 7]);
+		// Color 0 of the first palette is shared by all of them
+		assert_eq!(sgb.palettes[1], [4, 9, 10, 11]);
+		assert_eq!(sgb.palettes[3], [4, 17, 18, 19]);
+		assert_eq!(sgb.attributes[0][0], 3);
+		assert_eq!(sgb.attributes[17][11], 3);
+		assert_eq!(sgb.attributes[17][12], 0);
+	}
+
+	#[test]
+	fn switches_joypads_after_a_multiplayer_request() {
+		let mut sgb = Sgb::new(true);
+		send(&mut sgb, &[packet(&[MLT_REQ << 3 | 1, 0x01])]);
+		assert_eq!(sgb.player_count, 2);
+		assert_eq!(sgb.joypad_id(), 0x0F);
+
+		// P15 going high selects the next joypad
+		sgb.write_p1(0x10, 0x30);
+		sgb.write_p1(0x30, 0x10);
+		assert_eq!(sgb.joypad_id(), 0x0E);
+		assert!(!sgb.first_player());
+		sgb.write_p1(0x10, 0x30);
+		sgb.write_p1(0x30, 0x10);
+		assert!(sgb.first_player());
+
+		send(&mut sgb, &[packet(&[MLT_REQ << 3 | 1, 0x00])]);
+		assert_eq!(sgb.player_count, 1);
+	}
+
+	#[test]
+	fn rejects_states_with_the_wrong_size() {
+		let mut sgb = Sgb::new(true);
+		send(&mut sgb, &[packet(&[PAL01 << 3 | 1, 0x11, 0x11])]);
+		let state = sgb.create_state();
+
+		let mut short_packets = sgb.create_state();
+		short_packets.packets.truncate(16);
+		short_packets.palettes = [DEFAULT_PALETTE; 4];
+		assert!(sgb.load_state(short_packets).is_err());
+		assert_eq!(sgb.palettes[0][0], 0x1111);
+
+		let mut long_map = sgb.create_state();
+		long_map.border_map.push(0);
+		assert!(sgb.load_state(long_map).is_err());
+		assert!(sgb.load_state(state).is_ok());
+	}
+}
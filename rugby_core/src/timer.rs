@@ -26,9 +26,9 @@ impl Timer {
 		}
 	}
 
-	pub fn initialize(&mut self) {
-		self.internal_timer = 0xAB00;
-		self.div = 0xAB;
+	pub fn initialize(&mut self, counter: u16) {
+		self.internal_timer = counter;
+		self.div = (counter >> 8) as u8;
 		self.tima = 0;
 		self.tma = 0;
 		self.tac = 0xF8;
@@ -64,6 +64,11 @@ impl Timer {
 		self.previous_and = and_result;
 	}
 
+	// Returns the internal counter, which DIV is the upper byte of
+	pub fn counter(&self) -> u16 {
+		self.internal_timer
+	}
+
 	// Resets the internal timer to 0
 	pub fn reset_timer(&mut self) {
 		self.internal_timer = 0;
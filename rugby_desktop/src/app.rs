@@ -1,27 +1,53 @@
 use eframe::egui;
 use eframe::Storage;
-use egui::{Color32, Frame, InputState, Key, Vec2, ViewportCommand};
+use egui::{Color32, Event, Frame, InputState, Key, Vec2, ViewportCommand};
 use rfd::FileDialog;
-use sdl2::audio::{AudioQueue, AudioSpecDesired};
-use sdl2::TimerSubsystem;
+use sdl2::{AudioSubsystem, TimerSubsystem};
 use std::env;
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::cell::RefCell;
 use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use winit::event_loop::EventLoop;
 
-use rugby_core::color::Color as OutputColor;
-use rugby_core::color::LogicalColor;
+use rugby_core::cheats::{Cheat, cheats_from_text, cheats_to_text};
+use rugby_core::colorization::PALETTE_NAMES;
+use rugby_core::model::{Model, MODELS};
+use rugby_core::movie::{Anchor, Movie};
+use rugby_core::ram_search::{RamSearch, SearchFilter, ValueSize, SEARCH_FILTERS};
 use rugby_core::emulator::Emulator;
-use rugby_core::input::Input;
+use rugby_core::filter::{ColorCorrection, ScreenTint, FrameBlending};
+use rugby_core::filter::{COLOR_CORRECTIONS, SCREEN_TINTS, FRAME_BLENDINGS};
+use rugby_core::input::{Input, InputMacro, Macros, Turbo};
 use rugby_core::input::EmulatorInput;
-use crate::config_builder::get_all_palettes;
+use crate::audio::{AudioOutput, AudioSettings};
+use crate::config_builder::{get_all_palettes, get_audio_settings, get_button_bindings, get_controls, get_deadzone, get_vsync};
+use crate::controls::{Action, Controls, ACTIONS};
+use crate::gamepad::{ButtonBindings, Gamepads};
+use crate::script::Script;
 
 const GB_WIDTH: usize = 160;
 const GB_HEIGHT: usize = 144;
-const MENUBAR_HEIGHT: f32 = 20.0;
+const SGB_WIDTH: usize = 256; // With the Super Gameboy border
+const SGB_HEIGHT: usize = 224;
+const SGB_SCREEN_OFFSET: (usize, usize) = (48, 40); // Where the screen is inside the border
+const MENUBAR_HEIGHT: f32 = 20.0; // Until the menu bar is first drawn and measured
 const RECENT_ROMS_LENGTH: usize = 5;
+const REWIND_CAPACITIES: [usize; 4] = [32, 64, 128, 256]; // In MiB
+const GB_FRAME_RATE: f64 = 4194304.0 / 70224.0; // About 59.73 frames per second
+const VSYNC_TOLERANCE: f64 = 0.005; // As much as the dynamic rate control of the audio can absorb
+const REFRESH_SMOOTHING: f64 = 0.05; // How quickly the measured refresh interval follows the display
+const SPEEDS: [f32; 8] = [0.25, 0.5, 1.0, 1.5, 2.0, 3.0, 4.0, 8.0];
+const UNCAPPED_SPEED: f32 = f32::INFINITY;
+const SAMPLE_RATES: [u32; 5] = [22050, 32000, 44100, 48000, 96000];
+const CHANNEL_NAMES: [&str; 4] = ["Pulse (Sweep)", "Pulse", "Wave", "Noise"];
+const AUDIO_BUFFER_SIZES: [u16; 5] = [256, 512, 1024, 2048, 4096]; // In frames
+const TURBO_RATES: [usize; 6] = [1, 2, 3, 4, 6, 8]; // In frames pressed, then released
+const MACRO_SLOTS: usize = 4;
+const AUDIO_LATENCIES: [u32; 6] = [20, 35, 50, 75, 100, 150]; // In milliseconds
 
 #[derive(Clone, PartialEq)]
 pub struct Palette {
@@ -42,59 +68,108 @@ pub fn run_app() {
 	let sdl_context = sdl2::init().unwrap();
 	let timer_subsystem = sdl_context.timer().unwrap();
 	
-	// Setup the audio and the audio callback
+	// The audio device is opened by the app, with the saved settings
 	let audio_subsystem = sdl_context.audio().expect("Failed to initialize audio");
-	let desired_spec = AudioSpecDesired {
-		freq: Some(44100),
-		channels: Some(2),
-		samples: Some(1024),
+	let vsync = get_vsync();
+
+	// Controllers can be connected at any time
+	let game_controller_subsystem = match sdl_context.game_controller() {
+		Ok(subsystem) => Some(subsystem),
+		Err(e) => {
+			eprintln!("Failed to initialize game controllers, continuing without them: {}", e);
+			None
+		},
 	};
-	let audio_queue: AudioQueue<f32> = audio_subsystem.open_queue(None, &desired_spec)
-		.expect("Failed to create audio queue");
-	audio_queue.resume();
-	let callback = Box::new(move |buffer: &[f32]| {
-		while audio_queue.size() > 1024 * 4 * 2 {
-			std::thread::sleep(Duration::from_millis(1));
-		}
-		let _ = audio_queue.queue_audio(buffer);
-	});
+	let event_pump = sdl_context.event_pump().unwrap();
+	let gamepads = Gamepads::new(game_controller_subsystem, event_pump, get_button_bindings(), get_deadzone());
 
 	let palettes = get_all_palettes();
-	let scale = Box::new(4.0); // Default scale
+	let scale = 4.0; // Default scale
 	
 	let native_options = eframe::NativeOptions {
 		viewport: egui::ViewportBuilder::default()
 			.with_active(true)
-			// 20 is a magic number, the menu bar's height
-			.with_inner_size([(GB_WIDTH as f32 * *scale) / dpi_factor,
-							  ((GB_HEIGHT as f32 * *scale) / dpi_factor) + (MENUBAR_HEIGHT + 1.0 * *scale) as f32])
-			.with_resizable(false),
-		vsync: false, 
+			.with_inner_size([(GB_WIDTH as f32 * scale) / dpi_factor,
+							  ((GB_HEIGHT as f32 * scale) / dpi_factor) + MENUBAR_HEIGHT])
+			.with_min_inner_size([GB_WIDTH as f32 / dpi_factor, GB_HEIGHT as f32 / dpi_factor])
+			.with_resizable(true),
+		vsync,
 		centered: true,
 		persist_window: false,
 		..Default::default()
 	};
 	
-	let _ = eframe::run_native("Rugby", native_options, Box::new(|cc| {
-		let ppp = cc.egui_ctx.native_pixels_per_point();
-		if let Some(value) = ppp {
-			cc.egui_ctx.send_viewport_cmd(ViewportCommand::InnerSize (
-				Vec2::new((GB_WIDTH as f32 * *scale) / value,
-						  (GB_HEIGHT as f32 * *scale) / value + (MENUBAR_HEIGHT + 1.5 * *scale))));
-		}
-		// First argument corresponds to the ROM's file name
-		let first_arg: Option<String> = env::args().nth(1).clone();
-		Box::new(EguiApp::new(cc, palettes, scale, timer_subsystem, first_arg, callback))
+	let _ = eframe::run_native("Rugby", native_options, Box::new(move |cc| {
+		let (rom_arg, script_arg) = parse_args(env::args().skip(1));
+		let startup = Startup {
+			palettes,
+			scale,
+			timer: timer_subsystem,
+			audio_subsystem,
+			gamepads,
+			file_arg: rom_arg,
+			vsync,
+		};
+		let mut app = EguiApp::new(cc, startup);
+		if let Some(path) = script_arg {
+			app.load_script(Path::new(&path));
+		}
+		Box::new(app)
 	})
 	);
 }
 
+// Returns the ROM's file name, and the script's after --script
+fn parse_args(mut args: impl Iterator<Item = String>) -> (Option<String>, Option<String>) {
+	let mut rom = None;
+	let mut script = None;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--script" => script = args.next(),
+			_ => rom = rom.or(Some(arg)),
+		}
+	}
+	(rom, script)
+}
+
+// Parses a value in decimal, or in hexadecimal starting with $ or 0x
+fn parse_value(text: &str) -> Option<i32> {
+	let text = text.trim();
+	let (negative, text) = match text.strip_prefix('-') {
+		Some(text) => (true, text),
+		None => (false, text),
+	};
+	let value = match text.strip_prefix('$').or(text.strip_prefix("0x")) {
+		Some(hex) => i32::from_str_radix(hex, 16).ok()?,
+		None => text.parse().ok()?,
+	};
+	Some(if negative {-value} else {value})
+}
+
+// The SDL subsystems the app uses, and its settings when it starts
+pub struct Startup {
+	pub palettes: Vec<Palette>,
+	pub scale: f32, // The default, until the window is resized
+	pub timer: TimerSubsystem,
+	pub audio_subsystem: AudioSubsystem,
+	pub gamepads: Gamepads,
+	pub file_arg: Option<String>, // The ROM given on the command line
+	pub vsync: bool,
+}
+
 pub struct EguiApp {
 	gb: Emulator,
 	palettes: Vec<Palette>,
 	palette_index: usize,
 
-	scale: f32,
+	scale: f32, // The size of the window, in pixels for every pixel of the screen
+	integer_scaling: bool, // Only scale the screen by whole numbers, otherwise stretch it to fit the window
+	screen_scale: usize, // The whole number scale that the screen was last drawn at, for the pixel grid
+	fullscreen: bool,
+	kiosk_mode: bool, // Hides the menu bar
+	menubar_height: f32,
+	display_size: (usize, usize), // The size of the screen, with the border if there is one
+	screen_texture: Option<egui::TextureHandle>, // Updated with every frame, instead of allocating a new one
 	emulator_playing: bool,
 	exit_program: bool,
 	active_layers: [bool; 3],
@@ -103,7 +178,54 @@ pub struct EguiApp {
 	audio_on: bool,
 	active_channels: [bool; 4],
 	toggle_channels: [bool; 4],
+	band_limited_audio: bool,
+	fifo_renderer: bool,
+	model: Option<Model>, // None picks the model from the game's header
+	colorization_palette: Option<usize>, // None picks the palette from the game's title
+	color_correction: ColorCorrection,
+	screen_tint: ScreenTint,
+	frame_blending: FrameBlending,
+	pixel_grid: bool,
 	show_palette_window: bool,
+	controls: Controls,
+	default_controls: Controls, // From config.toml
+	show_controls_window: bool,
+	rebinding: Option<Action>, // The action that the next key press is bound to
+	gamepads: Gamepads,
+	default_button_bindings: ButtonBindings, // From config.toml
+	rebinding_button: Option<Action>, // The action that the next controller button press is bound to
+	turbo: Turbo,
+	turbo_buttons: (bool, bool), // Whether turbo A and turbo B are held
+	macros: Macros,
+	movie_read_only: bool,
+	cheats: Vec<Cheat>,
+	cheat_path: Option<PathBuf>, // The cheat file of the current ROM
+	show_cheats_window: bool,
+	new_cheat_name: String,
+	new_cheat_code: String,
+	cheat_error: Option<String>, // Why the last code couldn't be added
+	ram_search: Option<RamSearch>,
+	show_ram_search_window: bool,
+	search_size: ValueSize,
+	search_signed: bool,
+	search_value: String,
+	poke_value: String, // Written to an address found with the RAM search
+	script: Option<Script>,
+	rewind_enabled: bool,
+	rewind_capacity: usize, // In MiB
+	speed: f32,
+	fast_forward_speed: f32, // Used while the fast-forward key is held
+	fast_forward: bool,
+	frame_skip: bool, // Only render the last of the frames that run in a single update
+	frame_budget: f32, // Fractional frames left over from the previous update
+	next_frame_time: u64, // Used to pace the emulator when audio is muted
+	vsync: bool, // Whether the display paces the emulator instead of the audio
+	last_update_time: u64, // Used to pace the emulator with vsync
+	refresh_interval: f64, // The average time between display updates, in seconds
+	audio_output: Rc<RefCell<AudioOutput>>,
+	audio_settings: AudioSettings,
+	mono_audio: bool,
+	channel_volumes: [f32; 4], // From 0.0 to 1.0
 	select_save_state: (bool, usize),
 	select_load_state: (bool, usize),
 	timer_subsystem: TimerSubsystem, // Used to count the FPS
@@ -113,9 +235,27 @@ pub struct EguiApp {
 }
 
 impl EguiApp {
-	pub fn new(cc: &eframe::CreationContext<'_>, palettes: Vec<Palette>, scale: Box<f32>, timer: TimerSubsystem,
-			   file_arg: Option<String>, callback: Box<dyn Fn(&[f32])>) -> Self {
-		let gb = match file_arg {
+	pub fn new(cc: &eframe::CreationContext<'_>, startup: Startup) -> Self {
+		let Startup { palettes, scale, timer, audio_subsystem, mut gamepads, file_arg, vsync } = startup;
+		// The settings in config.toml are only the defaults
+		let mut audio_settings = get_audio_settings();
+		let storage = cc.storage.unwrap();
+		audio_settings.sample_rate = eframe::get_value(storage, "sample_rate").unwrap_or(audio_settings.sample_rate);
+		audio_settings.channels = eframe::get_value(storage, "audio_channels").unwrap_or(audio_settings.channels);
+		audio_settings.buffer_size = eframe::get_value(storage, "audio_buffer_size").unwrap_or(audio_settings.buffer_size);
+		audio_settings.latency = eframe::get_value(storage, "audio_latency").unwrap_or(audio_settings.latency);
+		audio_settings.master_volume = eframe::get_value(storage, "master_volume").unwrap_or(audio_settings.master_volume);
+		let mono_audio = eframe::get_value(storage, "mono_audio").unwrap_or(false);
+		let channel_volumes: [f32; 4] = eframe::get_value(storage, "channel_volumes").unwrap_or([1.0; 4]);
+
+		// Setup the audio and the audio callback
+		let audio_output = Rc::new(RefCell::new(AudioOutput::new(audio_subsystem, &audio_settings, vsync)));
+		let callback_output = Rc::clone(&audio_output);
+		let callback = Box::new(move |buffer: &[f32]| {
+			callback_output.borrow().queue_audio(buffer);
+		});
+
+		let mut gb = match &file_arg {
 			Some(s) => {
 				let path_buf = PathBuf::from(s);
 				let mut rom = File::open(path_buf.clone()).expect("Unable to open file {path}");
@@ -125,7 +265,12 @@ impl EguiApp {
 			},
 			None => Emulator::new(None, None, callback),
 		};
-		
+		gb.set_master_volume(audio_settings.master_volume);
+		gb.set_mono_audio(mono_audio);
+		for (i, &volume) in channel_volumes.iter().enumerate() {
+			gb.set_channel_volume(i, volume);
+		}
+
 		let start = timer.performance_counter();
 		let end = timer.performance_counter();
 		let recent_roms = eframe::get_value(cc.storage.unwrap(), "recent_roms").unwrap_or_default();
@@ -137,13 +282,65 @@ impl EguiApp {
 				palette_index = index;
 			}
 		}
+		let rewind_enabled = eframe::get_value(cc.storage.unwrap(), "rewind_enabled").unwrap_or(true);
+		let rewind_capacity = eframe::get_value(cc.storage.unwrap(), "rewind_capacity").unwrap_or(64);
+		gb.set_rewind(rewind_enabled);
+		gb.set_rewind_capacity(rewind_capacity * 1024 * 1024);
+		let fast_forward_speed = eframe::get_value(cc.storage.unwrap(), "fast_forward_speed").unwrap_or(UNCAPPED_SPEED);
+		let frame_skip = eframe::get_value(cc.storage.unwrap(), "frame_skip").unwrap_or(true);
+		let band_limited_audio = eframe::get_value(cc.storage.unwrap(), "band_limited_audio").unwrap_or(true);
+		gb.set_band_limited_audio(band_limited_audio);
+		let fifo_renderer = eframe::get_value(cc.storage.unwrap(), "fifo_renderer").unwrap_or(false);
+		gb.set_fifo_renderer(fifo_renderer);
+		let model = eframe::get_value(cc.storage.unwrap(), "model").unwrap_or(None);
+		let colorization_palette = eframe::get_value(cc.storage.unwrap(), "colorization_palette").unwrap_or(None);
+		let color_correction = eframe::get_value(cc.storage.unwrap(), "color_correction").unwrap_or(ColorCorrection::Off);
+		let screen_tint = eframe::get_value(cc.storage.unwrap(), "screen_tint").unwrap_or(ScreenTint::Off);
+		let frame_blending = eframe::get_value(cc.storage.unwrap(), "frame_blending").unwrap_or(FrameBlending::Off);
+		let pixel_grid = eframe::get_value(cc.storage.unwrap(), "pixel_grid").unwrap_or(false);
+		let default_controls = get_controls();
+		let mut controls = default_controls.clone();
+		let control_names: Vec<(String, Option<String>)> = eframe::get_value(cc.storage.unwrap(), "controls").unwrap_or_default();
+		controls.load_names(&control_names);
+		let default_button_bindings = gamepads.bindings.clone();
+		let button_names: Vec<(String, Option<String>)> = eframe::get_value(cc.storage.unwrap(), "button_bindings").unwrap_or_default();
+		gamepads.bindings.load_names(&button_names);
+		gamepads.deadzone = eframe::get_value(cc.storage.unwrap(), "gamepad_deadzone").unwrap_or(gamepads.deadzone);
+		let turbo_rate = eframe::get_value(cc.storage.unwrap(), "turbo_rate").unwrap_or(2);
+		let mut macros = Macros::new(MACRO_SLOTS);
+		let saved_macros: Vec<InputMacro> = eframe::get_value(cc.storage.unwrap(), "macros").unwrap_or_default();
+		for (slot, input_macro) in saved_macros.into_iter().take(MACRO_SLOTS).enumerate() {
+			macros.macros[slot] = input_macro;
+		}
+		gb.set_palette(palettes[palette_index].colors.map(|(r, g, b)| [r, g, b]));
+		gb.set_color_correction(color_correction);
+		gb.set_screen_tint(screen_tint);
+		gb.set_frame_blending(frame_blending);
+		gb.set_model(model);
+		gb.set_colorization_palette(colorization_palette);
+		if model.is_some() {
+			// The ROM was loaded before the model was selected
+			gb.reset();
+		}
 		
-		EguiApp {
+		let scale = eframe::get_value(cc.storage.unwrap(), "scale").unwrap_or(scale);
+		let integer_scaling = eframe::get_value(cc.storage.unwrap(), "integer_scaling").unwrap_or(true);
+		let fullscreen = eframe::get_value(cc.storage.unwrap(), "fullscreen").unwrap_or(false);
+		let kiosk_mode = eframe::get_value(cc.storage.unwrap(), "kiosk_mode").unwrap_or(false);
+		
+		let mut app = EguiApp {
 			gb,
 			palettes,
 			palette_index,
 
-			scale: *scale,
+			scale,
+			integer_scaling,
+			screen_scale: 0,
+			fullscreen,
+			kiosk_mode,
+			menubar_height: MENUBAR_HEIGHT,
+			display_size: (GB_WIDTH, GB_HEIGHT),
+			screen_texture: None,
 			emulator_playing: true,
 			exit_program: false,
 			active_layers: [true; 3],
@@ -152,56 +349,141 @@ impl EguiApp {
 			audio_on: true,
 			active_channels: [true; 4],
 			toggle_channels: [false; 4],
+			band_limited_audio,
+			fifo_renderer,
+			model,
+			colorization_palette,
+			color_correction,
+			screen_tint,
+			frame_blending,
+			pixel_grid,
 			show_palette_window: false,
+			controls,
+			default_controls,
+			show_controls_window: false,
+			rebinding: None,
+			gamepads,
+			default_button_bindings,
+			rebinding_button: None,
+			turbo: Turbo::new(turbo_rate),
+			turbo_buttons: (false, false),
+			macros,
+			movie_read_only: true,
+			cheats: Vec::new(),
+			cheat_path: None,
+			show_cheats_window: false,
+			new_cheat_name: String::new(),
+			new_cheat_code: String::new(),
+			cheat_error: None,
+			ram_search: None,
+			show_ram_search_window: false,
+			search_size: ValueSize::Byte,
+			search_signed: false,
+			search_value: String::new(),
+			poke_value: String::new(),
+			script: None,
+			rewind_enabled,
+			rewind_capacity,
+			speed: 1.0,
+			fast_forward_speed,
+			fast_forward: false,
+			frame_skip,
+			frame_budget: 0.0,
+			next_frame_time: 0,
+			vsync,
+			last_update_time: 0,
+			refresh_interval: 0.0,
+			audio_output,
+			audio_settings,
+			mono_audio,
+			channel_volumes,
 			select_save_state: (false, 0),
 			select_load_state: (false, 0),
 			timer_subsystem: timer,
 			start,
 			end,
 			recent_roms,
+		};
+		app.match_audio_device();
+		if let Some(path) = file_arg {
+			app.load_cheats(Path::new(&path));
 		}
+		app.resize_window(&cc.egui_ctx);
+		if fullscreen {
+			cc.egui_ctx.send_viewport_cmd(ViewportCommand::Fullscreen(true));
+		}
+		app
     }
 
+	// Opens the audio device with the current settings, and matches the emulator's output to it
+	fn reopen_audio(&mut self) {
+		self.audio_output.borrow_mut().reopen(&self.audio_settings);
+		self.match_audio_device();
+	}
+
+	// The device might not support the settings we asked for, so the emulator uses the actual ones
+	fn match_audio_device(&mut self) {
+		let audio_output = self.audio_output.borrow();
+		self.gb.set_sample_rate(audio_output.sample_rate());
+		self.gb.set_audio_channels(audio_output.channels());
+		self.gb.set_audio_buffer_size(audio_output.buffer_size());
+	}
+
 	// Handle a frame's input and return an object for the emulator
 	fn handle_input(&mut self, input_state: &InputState) -> (Input, EmulatorInput) {
-		let mut input = Input::new();
+		// The keyboard and the first controller both play as the first player
+		let mut input = self.gamepads.input(0);
 		let mut emulator_input = EmulatorInput::new();
 
-		if input_state.key_pressed(Key::Escape) {
-			emulator_input.exit = true;
-		}
-		if input_state.key_down(Key::ArrowUp) {
-			input.up = true;
-		}
-		if input_state.key_down(Key::ArrowDown) {
-			input.down = true;
-		}
-		if input_state.key_down(Key::ArrowLeft) {
-			input.left = true;
-		}
-		if input_state.key_down(Key::ArrowRight) {
-			input.right = true;
-		}
-		if input_state.key_down(Key::A) {
-			input.a = true;
-		}
-		if input_state.key_down(Key::S) {
-			input.b = true;
-		}
-		if input_state.key_down(Key::Z) {
-			input.start = true;
-		}
-		if input_state.key_down(Key::X) {
-			input.select = true;
+		let controls = &self.controls;
+		input.up |= controls.active(Action::Up, input_state);
+		input.down |= controls.active(Action::Down, input_state);
+		input.left |= controls.active(Action::Left, input_state);
+		input.right |= controls.active(Action::Right, input_state);
+		input.a |= controls.active(Action::A, input_state);
+		input.b |= controls.active(Action::B, input_state);
+		input.start |= controls.active(Action::Start, input_state);
+		input.select |= controls.active(Action::Select, input_state);
+		emulator_input.exit = self.hotkey_active(Action::Exit, input_state);
+		emulator_input.save_state = self.hotkey_active(Action::SaveState, input_state);
+		emulator_input.load_state = self.hotkey_active(Action::LoadState, input_state);
+		emulator_input.rewind = self.hotkey_active(Action::Rewind, input_state);
+		emulator_input.prev_bg_map = self.hotkey_active(Action::PrevBgMap, input_state);
+		emulator_input.next_bg_map = self.hotkey_active(Action::NextBgMap, input_state);
+		self.fast_forward = self.hotkey_active(Action::FastForward, input_state);
+		self.turbo_buttons = (controls.active(Action::TurboA, input_state) || self.gamepads.active(Action::TurboA, 0),
+							  controls.active(Action::TurboB, input_state) || self.gamepads.active(Action::TurboB, 0));
+		for i in 0..MACRO_SLOTS {
+			if self.hotkey_active(Action::RecordMacro(i), input_state) {
+				self.macros.toggle_recording(i);
+			}
+			if self.hotkey_active(Action::PlayMacro(i), input_state) {
+				self.macros.toggle_playing(i);
+			}
 		}
-		if input_state.key_pressed(Key::O) {
-			emulator_input.save_state = true;
+
+		// The toggles also update the menus, so they go through the same flags
+		for i in 0..4 {
+			if self.hotkey_active(Action::SaveStateSlot(i), input_state) {
+				self.select_save_state = (true, i);
+			}
+			if self.hotkey_active(Action::LoadStateSlot(i), input_state) {
+				self.select_load_state = (true, i);
+			}
+			if self.hotkey_active(Action::ToggleChannel(i), input_state) {
+				self.active_channels[i] = !self.active_channels[i];
+				self.toggle_channels[i] = true;
+			}
 		}
-		if input_state.key_pressed(Key::P) {
-			emulator_input.load_state = true;
+		for i in 0..3 {
+			if self.hotkey_active(Action::ToggleLayer(i), input_state) {
+				self.active_layers[i] = !self.active_layers[i];
+				self.toggle_layers[i] = true;
+			}
 		}
-		if input_state.key_down(Key::R) {
-			emulator_input.rewind = true;
+		if self.hotkey_active(Action::ToggleMute, input_state) {
+			self.audio_on = !self.audio_on;
+			self.toggle_mute = true;
 		}
 
 		if self.toggle_mute {
@@ -237,6 +519,11 @@ impl EguiApp {
 		(input, emulator_input)
 	}
 
+	// Hotkeys work from the keyboard and from any controller
+	fn hotkey_active(&self, action: Action, input_state: &InputState) -> bool {
+		self.controls.active(action, input_state) || self.gamepads.any_active(action)
+	}
+
 	pub fn start_timer(&mut self) {
 		self.start = self.timer_subsystem.performance_counter();
 	}
@@ -250,30 +537,239 @@ impl EguiApp {
 		println!("FPS: {}", current_fps);
 	}
 
-	// Returns a RGB color from the emulator's logical color
-	fn get_color(&self, color: &OutputColor) -> (u8, u8, u8) {
-		let i = self.palette_index;
-		match color {
-			OutputColor::Logical(logical) => match logical {
-				LogicalColor::White => self.palettes[i].colors[0],
-				LogicalColor::LightGray => self.palettes[i].colors[1],
-				LogicalColor::DarkGray => self.palettes[i].colors[2],
-				LogicalColor::Black => self.palettes[i].colors[3],
-			},
-			OutputColor::RGB(rgb) => {
-				// Simple and fast conversion from 5 bit to 8 bit (but produceds saturated colors)
-				let r_5bit = (rgb & 0x1F) as u8;
-				let r_8bit = (r_5bit << 3) | (r_5bit >> 2);
-				
-				let g_5bit = ((rgb & 0x3E0) >> 5) as u8;
-				let g_8bit = (g_5bit << 3) | (g_5bit >> 2);
-				
-				let b_5bit = ((rgb & 0x7C00) >> 10) as u8;
-				let b_8bit = (b_5bit << 3) | (b_5bit >> 2);
-				(r_8bit, g_8bit, b_8bit)
+	// Returns the current speed multiplier
+	fn current_speed(&self) -> f32 {
+		if self.fast_forward {
+			self.fast_forward_speed
+		} else {
+			self.speed
+		}
+	}
+
+	// Sleeps until the next frame should start, for a given frame rate
+	fn wait_for_next_frame(&mut self, frame_rate: f64) {
+		let frequency = self.timer_subsystem.performance_frequency();
+		let frame_ticks = (frequency as f64 / frame_rate) as u64;
+		let now = self.timer_subsystem.performance_counter();
+		if self.next_frame_time > now {
+			let seconds = (self.next_frame_time - now) as f64 / frequency as f64;
+			std::thread::sleep(Duration::from_secs_f64(seconds));
+		} else if now - self.next_frame_time > 4 * frame_ticks {
+			// We are too far behind (e.g. after a pause), so don't try to catch up
+			self.next_frame_time = now;
+		}
+		self.next_frame_time += frame_ticks;
+	}
+
+	// Returns how many frames to run with vsync, at the frame rate of the Gameboy whatever the refresh rate
+	// of the display. Near 60 Hz every update runs exactly one frame instead, so that no frame is skipped
+	// or shown twice, and the dynamic rate control of the audio absorbs the difference
+	fn vsync_frames(&mut self) -> usize {
+		let frequency = self.timer_subsystem.performance_frequency() as f64;
+		let now = self.timer_subsystem.performance_counter();
+		let seconds = now.saturating_sub(self.last_update_time) as f64 / frequency;
+		self.last_update_time = now;
+		if seconds * GB_FRAME_RATE > 4.0 {
+			// We are too far behind (e.g. after a pause), so don't try to catch up
+			self.frame_budget = 0.0;
+			return 1;
+		}
+		self.refresh_interval += (seconds - self.refresh_interval) * REFRESH_SMOOTHING;
+		if (self.refresh_interval * GB_FRAME_RATE - 1.0).abs() < VSYNC_TOLERANCE {
+			self.frame_budget = 0.0;
+			return 1;
+		}
+		self.frame_budget += (seconds * GB_FRAME_RATE) as f32;
+		let frames = self.frame_budget.floor();
+		self.frame_budget -= frames;
+		frames as usize
+	}
+
+	// Runs as many frames as the current speed requires
+	fn run_frames(&mut self, input: Input, emulator_input: EmulatorInput) {
+		let speed = self.current_speed();
+		// At normal speed the audio callback (or the display) paces the emulator, otherwise audio is muted
+		self.gb.set_audio_output(speed == 1.0);
+		// Rewinding goes back one frame per update at any speed, since a batch of frames would run
+		// forward after the first one
+		if emulator_input.rewind {
+			self.run_frame(input, Some(emulator_input));
+			return;
+		}
+		if speed == 1.0 && !self.vsync {
+			self.frame_budget = 0.0;
+			let fill = self.audio_output.borrow().fill();
+			self.gb.set_audio_buffer_fill(fill);
+			self.run_frame(input, Some(emulator_input));
+			return;
+		}
+
+		if speed == UNCAPPED_SPEED {
+			// Run for about the length of a frame at normal speed
+			let frame_ticks = self.timer_subsystem.performance_frequency() as f64 / GB_FRAME_RATE;
+			let start = self.timer_subsystem.performance_counter();
+			self.run_frame(input, Some(emulator_input));
+			while self.frame_skip &&
+				((self.timer_subsystem.performance_counter() - start) as f64) < frame_ticks {
+				self.run_frame(input, None);
+			}
+			return;
+		}
+
+		let frames = if speed == 1.0 {
+			let fill = self.audio_output.borrow().fill();
+			self.gb.set_audio_buffer_fill(fill);
+			self.vsync_frames()
+		} else if self.frame_skip {
+			self.wait_for_next_frame(GB_FRAME_RATE);
+			self.frame_budget += speed;
+			let frames = self.frame_budget.floor();
+			self.frame_budget -= frames;
+			frames as usize
+		} else {
+			self.wait_for_next_frame(GB_FRAME_RATE * speed as f64);
+			1
+		};
+
+		// Emulator settings (e.g. save states) must be applied exactly once
+		if frames == 0 {
+			self.gb.update_config(emulator_input);
+		}
+		let mut emulator_input = Some(emulator_input);
+		for _ in 0..frames {
+			self.run_frame(input, emulator_input.take());
+		}
+	}
+
+	// Cheats are kept in a file next to the ROM, with the .cht extension
+	fn load_cheats(&mut self, rom_path: &Path) {
+		let cheat_path = rom_path.with_extension("cht");
+		self.cheats = fs::read_to_string(&cheat_path).map(|text| cheats_from_text(&text)).unwrap_or_default();
+		self.cheat_path = Some(cheat_path);
+		self.gb.set_cheats(&self.cheats);
+	}
+
+	// Applies the cheats, and saves them to the cheat file of the ROM
+	fn update_cheats(&mut self) {
+		self.gb.set_cheats(&self.cheats);
+		if let Some(path) = &self.cheat_path {
+			if let Err(e) = fs::write(path, cheats_to_text(&self.cheats)) {
+				eprintln!("Failed to save the cheats: {}", e);
+			}
+		}
+	}
+
+	// Plays a movie file, with the current read-only setting
+	fn play_movie(&mut self) {
+		let Some(path) = FileDialog::new().add_filter("Rugby movie", &["rbm"]).pick_file() else {
+			return;
+		};
+		let movie = fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| Movie::from_text(&text));
+		let result = movie.and_then(|movie| self.gb.start_movie_playback(movie, self.movie_read_only));
+		if let Err(e) = result {
+			eprintln!("Failed to play the movie: {}", e);
+		}
+	}
+
+	fn save_movie(&mut self) {
+		let Some(movie) = self.gb.movie() else {
+			return;
+		};
+		let file_name = format!("{}.rbm", movie.title);
+		if let Some(path) = FileDialog::new().add_filter("Rugby movie", &["rbm"]).set_file_name(file_name).save_file() {
+			if let Err(e) = fs::write(path, movie.to_text()) {
+				eprintln!("Failed to save the movie: {}", e);
+			}
+		}
+	}
+
+	// Turbo buttons and macros change the input of every frame
+	fn frame_input(&mut self, input: Input) -> Input {
+		let (turbo_a, turbo_b) = self.turbo_buttons;
+		let input = self.turbo.apply(input, turbo_a, turbo_b);
+		self.macros.apply(input)
+	}
+
+	// Runs a single frame, with the buttons held by the script, then the callbacks of the script
+	fn run_frame(&mut self, input: Input, emulator_input: Option<EmulatorInput>) {
+		let mut input = self.frame_input(input);
+		if let Some(script) = &self.script {
+			let held = script.input();
+			input.up |= held.up;
+			input.down |= held.down;
+			input.left |= held.left;
+			input.right |= held.right;
+			input.a |= held.a;
+			input.b |= held.b;
+			input.start |= held.start;
+			input.select |= held.select;
+		}
+		self.gb.run(input, emulator_input);
+		if let Some(script) = &mut self.script {
+			if let Err(e) = script.run_callbacks(&mut self.gb) {
+				eprintln!("Script error, stopping the script: {}", e);
+				self.stop_script();
 			}
 		}
 	}
+
+	// Runs a script, which replaces the current one
+	fn load_script(&mut self, path: &Path) {
+		self.stop_script();
+		match Script::load(path, &mut self.gb) {
+			Ok(script) => self.script = Some(script),
+			Err(e) => eprintln!("Failed to load the script: {}", e),
+		}
+	}
+
+	fn stop_script(&mut self) {
+		self.script = None;
+		self.gb.set_watched_addresses(Vec::new());
+	}
+
+	// Fits the window to the screen at the current scale
+	fn resize_window(&self, ctx: &egui::Context) {
+		if self.fullscreen {
+			return;
+		}
+		let pixels_per_point = ctx.native_pixels_per_point().unwrap_or(ctx.pixels_per_point());
+		let menubar_height = match self.kiosk_mode {
+			true => 0.0,
+			false => self.menubar_height,
+		};
+		let (width, height) = self.display_size;
+		ctx.send_viewport_cmd(ViewportCommand::InnerSize(
+			Vec2::new((width as f32 * self.scale) / pixels_per_point,
+					  (height as f32 * self.scale) / pixels_per_point + menubar_height)));
+	}
+
+	fn toggle_fullscreen(&mut self, ctx: &egui::Context) {
+		self.fullscreen = !self.fullscreen;
+		ctx.send_viewport_cmd(ViewportCommand::Fullscreen(self.fullscreen));
+		self.resize_window(ctx);
+	}
+
+	// Returns how many pixels of the window every pixel of the screen takes, to fit in the given size
+	fn fit_scale(&self, available_size: Vec2, pixels_per_point: f32) -> f32 {
+		let (width, height) = self.display_size;
+		let scale = (available_size.x * pixels_per_point / width as f32)
+			.min(available_size.y * pixels_per_point / height as f32);
+		match self.integer_scaling {
+			true => scale.floor().max(1.0),
+			false => scale,
+		}
+	}
+
+	// Sends the colors of the selected palette to the emulator
+	fn apply_palette(&mut self) {
+		let colors = self.palettes[self.palette_index].colors;
+		self.gb.set_palette(colors.map(|(r, g, b)| [r, g, b]));
+	}
+
+	// The pixel grid is drawn at the scale of the screen
+	fn update_pixel_grid(&mut self) {
+		self.gb.set_pixel_grid(self.pixel_grid.then_some(self.screen_scale));
+	}
 }
 
 impl eframe::App for EguiApp {
@@ -282,34 +778,66 @@ impl eframe::App for EguiApp {
 		let mut input = Input::new();
 		let mut emulator_input = EmulatorInput::new();
 
+		self.gamepads.update();
+		if let Some(action) = self.rebinding_button {
+			if let Some(button) = self.gamepads.pressed_button() {
+				self.gamepads.bindings.bind(action, button);
+				self.rebinding_button = None;
+			}
+		}
+		// While rebinding, the next key press goes to the Controls window instead of the emulator
+		if let Some(action) = self.rebinding {
+			let key = ctx.input(|i| i.events.iter().find_map(|event| match event {
+				Event::Key { key, pressed: true, repeat: false, .. } => Some(*key),
+				_ => None,
+			}));
+			if let Some(key) = key {
+				self.controls.bind(action, key);
+				self.rebinding = None;
+			}
+		}
+		// Typing in a text field (like a cheat code) doesn't press any buttons
+		let typing = ctx.wants_keyboard_input();
 		ctx.input(|i| {
-			(input, emulator_input) = self.handle_input(i);
+			if self.rebinding.is_none() && self.rebinding_button.is_none() && !typing {
+				(input, emulator_input) = self.handle_input(i);
+			}
 			if i.viewport().close_requested() {
 				// Save before we exit the program
 				self.gb.save();
 			}
+			// The window can also leave fullscreen on its own
+			if let Some(fullscreen) = i.viewport().fullscreen {
+				self.fullscreen = fullscreen;
+			}
 		});
+		if ctx.input(|i| i.key_pressed(Key::F11)) {
+			self.toggle_fullscreen(ctx);
+		}
+		if ctx.input(|i| i.key_pressed(Key::F10)) {
+			self.kiosk_mode = !self.kiosk_mode;
+		}
 
-		// Run the emulator for a frame
-		let screen = match self.emulator_playing {
-			true => self.gb.run(input, Some(emulator_input)).clone(),
-			false => self.gb.get_screen().clone(),
-		};
+		// Run the emulator for a frame (or more, or less, depending on the speed)
+		if self.emulator_playing {
+			self.run_frames(input, emulator_input);
+		}
 
 
-		let mut buffer: Vec<u8> = Vec::with_capacity(GB_WIDTH * GB_HEIGHT * 4);
-		for y in 0..GB_HEIGHT {
-			for x in 0..GB_WIDTH {
-				let color = screen[y][x];
-				let (r, g, b) = self.get_color(&color);
-				buffer.push(r);
-				buffer.push(g);
-				buffer.push(b);
-			}
+		// The Super Gameboy draws the screen inside a border
+		let display_size = match self.gb.get_sgb_frame() {
+			Some(_) => (SGB_WIDTH, SGB_HEIGHT),
+			None => (GB_WIDTH, GB_HEIGHT),
+		};
+		if display_size != self.display_size {
+			self.display_size = display_size;
+			self.resize_window(ctx);
 		}
+		let (width, height) = display_size;
+		let palette_index = self.palette_index;
 
-		// Menu bar
-		egui::TopBottomPanel::top("Menu bar").show(ctx, |ui| {
+		// Menu bar, hidden in kiosk mode
+		let menubar = egui::TopBottomPanel::top("Menu bar").show_animated(ctx, !self.kiosk_mode, |ui| {
 			egui::menu::bar(ui, |ui| {
 				ui.menu_button("File", |ui| {
 					// Open a ROM
@@ -321,6 +849,7 @@ impl eframe::App for EguiApp {
 						let mut data_buffer = Vec::new();
 						rom.read_to_end(&mut data_buffer).unwrap();
 						self.gb.load(Some(data_buffer), file.clone());
+						self.load_cheats(file.as_ref().unwrap());
 						
 						if !self.recent_roms.contains(&file.clone().unwrap()) {
 							if self.recent_roms.len() > RECENT_ROMS_LENGTH {
@@ -344,28 +873,148 @@ impl eframe::App for EguiApp {
 								rom.read_to_end(&mut data_buffer).unwrap();
 						
 								self.gb.load(Some(data_buffer), Some(rom_path.to_path_buf()));
+								self.load_cheats(rom_path);
 							}
 						}
 					});
+					// Scripts
+					if ui.button("Load Script").clicked() {
+						if let Some(path) = FileDialog::new().add_filter("Rhai script", &["rhai"]).pick_file() {
+							self.load_script(&path);
+						}
+						ui.close_menu();
+					}
+					if ui.add_enabled(self.script.is_some(), egui::Button::new("Stop Script")).clicked() {
+						self.stop_script();
+						ui.close_menu();
+					}
+					// Input movies
+					ui.menu_button("Movie", |ui| {
+						if let (Some((mode, frame)), Some(movie)) = (self.gb.movie_status(), self.gb.movie()) {
+							ui.label(format!("{:?}: frame {} of {}, {} rerecords", mode, frame, movie.frames.len(), movie.rerecords));
+						}
+						if ui.button("Record from Power-On").clicked() {
+							self.gb.start_movie_recording(Anchor::PowerOn);
+							self.movie_read_only = false;
+							ui.close_menu();
+						}
+						if ui.button("Record from Current State").clicked() {
+							self.gb.start_movie_recording(Anchor::SaveState);
+							self.movie_read_only = false;
+							ui.close_menu();
+						}
+						if ui.button("Play").clicked() {
+							self.play_movie();
+							ui.close_menu();
+						}
+						if ui.checkbox(&mut self.movie_read_only, "Read-Only").clicked() {
+							self.gb.set_movie_read_only(self.movie_read_only);
+						}
+						ui.add_enabled_ui(self.gb.movie().is_some(), |ui| {
+							if ui.button("Save As").clicked() {
+								self.save_movie();
+								ui.close_menu();
+							}
+							if ui.button("Stop").clicked() {
+								self.gb.stop_movie();
+								ui.close_menu();
+							}
+						});
+					});
 				});
 				// Options
 				ui.menu_button("Options", |ui| {
 					ui.checkbox(&mut self.emulator_playing, "Pause/Resume");
+					if ui.button("Controls").clicked() {
+						self.show_controls_window = !self.show_controls_window;
+					}
+					if ui.button("Cheats").clicked() {
+						self.show_cheats_window = !self.show_cheats_window;
+					}
+					if ui.button("RAM Search").clicked() {
+						self.show_ram_search_window = !self.show_ram_search_window;
+					}
+					if ui.checkbox(&mut self.rewind_enabled, "Rewind").clicked() {
+						self.gb.set_rewind(self.rewind_enabled);
+					}
+					ui.menu_button("Rewind Buffer", |ui| {
+						for capacity in REWIND_CAPACITIES {
+							if ui.radio_value(&mut self.rewind_capacity,
+											  capacity, format!("{} MiB", capacity)).clicked() {
+								self.gb.set_rewind_capacity(capacity * 1024 * 1024);
+							}
+						}
+						ui.label(format!("{:.1} seconds stored", self.gb.rewind_frames() as f32 / 60.0));
+					});
+					ui.menu_button("Speed", |ui| {
+						for speed in SPEEDS {
+							ui.radio_value(&mut self.speed, speed, format!("{}x", speed));
+						}
+						ui.radio_value(&mut self.speed, UNCAPPED_SPEED, "Uncapped");
+					});
+					ui.menu_button("Fast-forward Speed", |ui| {
+						for speed in SPEEDS.into_iter().filter(|&speed| speed > 1.0) {
+							ui.radio_value(&mut self.fast_forward_speed, speed, format!("{}x", speed));
+						}
+						ui.radio_value(&mut self.fast_forward_speed, UNCAPPED_SPEED, "Uncapped");
+					});
+					ui.checkbox(&mut self.frame_skip, "Frame Skip");
+					ui.menu_button("Turbo Rate", |ui| {
+						for rate in TURBO_RATES {
+							ui.radio_value(&mut self.turbo.rate, rate, format!("{} frames", rate));
+						}
+					});
+					ui.menu_button("Macros", |ui| {
+						for i in 0..MACRO_SLOTS {
+							ui.horizontal(|ui| {
+								ui.label(format!("Macro {}: {} frames", i, self.macros.macros[i].frames.len()));
+								let record_text = match self.macros.recording() == Some(i) {
+									true => "Stop",
+									false => "Record",
+								};
+								if ui.button(record_text).clicked() {
+									self.macros.toggle_recording(i);
+								}
+								let play_text = match self.macros.playing() == Some(i) {
+									true => "Stop",
+									false => "Play",
+								};
+								if ui.button(play_text).clicked() {
+									self.macros.toggle_playing(i);
+								}
+							});
+						}
+					});
+					ui.separator();
+					ui.menu_button("Model", |ui| {
+						if ui.radio_value(&mut self.model, None, "Automatic").clicked() {
+							self.gb.set_model(None);
+							self.gb.reset();
+						}
+						for model in MODELS {
+							if ui.radio_value(&mut self.model, Some(model), model.name()).clicked() {
+								self.gb.set_model(Some(model));
+								self.gb.reset();
+							}
+						}
+					});
 				});
 				// Video
 				ui.menu_button("Video", |ui| {
-					ui.menu_button("Scaling", |ui| {
+					ui.menu_button("Window Size", |ui| {
 						for i in 1..=5 {
 							if ui.radio_value(&mut self.scale,
 											  i as f32, format!("{}x", i)).clicked() {
 								self.scale = i as f32;
-								ctx.send_viewport_cmd(ViewportCommand::InnerSize(
-									Vec2::new((GB_WIDTH as f32 * self.scale) / ctx.pixels_per_point()
-											  , (GB_HEIGHT as f32 * self.scale) / ctx.pixels_per_point()
-											  + (MENUBAR_HEIGHT + 1.5 * self.scale))));
+								self.resize_window(ctx);
 							}
 						}
 					});
+					ui.checkbox(&mut self.integer_scaling, "Integer Scaling");
+					if ui.checkbox(&mut self.fullscreen.clone(), "Fullscreen (F11)").clicked() {
+						self.toggle_fullscreen(ctx);
+					}
+					ui.checkbox(&mut self.kiosk_mode, "Hide Menu Bar (F10)");
 					ui.menu_button("Palettes", |ui| {
 						for i in 0..self.palettes.len() {
 							if ui.radio_value(&mut self.palette_index,
@@ -374,6 +1023,40 @@ impl eframe::App for EguiApp {
 							}
 						}
 					});
+					ui.menu_button("Colorization", |ui| {
+						if ui.radio_value(&mut self.colorization_palette, None, "Automatic").clicked() {
+							self.gb.set_colorization_palette(None);
+						}
+						for (i, name) in PALETTE_NAMES.iter().enumerate() {
+							if ui.radio_value(&mut self.colorization_palette, Some(i), *name).clicked() {
+								self.gb.set_colorization_palette(Some(i));
+							}
+						}
+					});
+					ui.menu_button("Color Correction", |ui| {
+						for color_correction in COLOR_CORRECTIONS {
+							if ui.radio_value(&mut self.color_correction, color_correction, color_correction.name()).clicked() {
+								self.gb.set_color_correction(color_correction);
+							}
+						}
+					});
+					ui.menu_button("Screen Tint", |ui| {
+						for screen_tint in SCREEN_TINTS {
+							if ui.radio_value(&mut self.screen_tint, screen_tint, screen_tint.name()).clicked() {
+								self.gb.set_screen_tint(screen_tint);
+							}
+						}
+					});
+					ui.menu_button("Frame Blending", |ui| {
+						for frame_blending in FRAME_BLENDINGS {
+							if ui.radio_value(&mut self.frame_blending, frame_blending, frame_blending.name()).clicked() {
+								self.gb.set_frame_blending(frame_blending);
+							}
+						}
+					});
+					if ui.checkbox(&mut self.pixel_grid, "Pixel Grid").clicked() {
+						self.update_pixel_grid();
+					}
 					if ui.button("Palette Picker").clicked() {
 						self.show_palette_window = !self.show_palette_window;
 					}
@@ -385,6 +1068,9 @@ impl eframe::App for EguiApp {
 							}
 						}
 					});
+					if ui.checkbox(&mut self.fifo_renderer, "Pixel FIFO Renderer").clicked() {
+						self.gb.set_fifo_renderer(self.fifo_renderer);
+					}
 				});
 				// Audio
 				ui.menu_button("Audio", |ui| {
@@ -399,6 +1085,61 @@ impl eframe::App for EguiApp {
 							}
 						}
 					});
+					ui.menu_button("Mixer", |ui| {
+						for (i, name) in CHANNEL_NAMES.iter().enumerate() {
+							if ui.add(egui::Slider::new(&mut self.channel_volumes[i], 0.0..=1.0)
+									  .text(*name)).changed() {
+								self.gb.set_channel_volume(i, self.channel_volumes[i]);
+							}
+						}
+					});
+					if ui.add(egui::Slider::new(&mut self.audio_settings.master_volume, 0.0..=1.0)
+							  .text("Master Volume")).changed() {
+						self.gb.set_master_volume(self.audio_settings.master_volume);
+					}
+					if ui.checkbox(&mut self.mono_audio, "Mono").clicked() {
+						self.gb.set_mono_audio(self.mono_audio);
+					}
+					if ui.checkbox(&mut self.band_limited_audio, "Band-limited Synthesis").clicked() {
+						self.gb.set_band_limited_audio(self.band_limited_audio);
+					}
+					ui.separator();
+					ui.menu_button("Output Channels", |ui| {
+						for (channels, name) in [(1, "Mono"), (2, "Stereo")] {
+							let selected = self.audio_settings.channels == channels;
+							if ui.selectable_label(selected, name).clicked() {
+								self.audio_settings.channels = channels;
+								self.reopen_audio();
+							}
+						}
+					});
+					ui.menu_button("Sample Rate", |ui| {
+						for sample_rate in SAMPLE_RATES {
+							let selected = self.audio_settings.sample_rate == sample_rate;
+							if ui.selectable_label(selected, format!("{} Hz", sample_rate)).clicked() {
+								self.audio_settings.sample_rate = sample_rate;
+								self.reopen_audio();
+							}
+						}
+					});
+					ui.menu_button("Buffer Size", |ui| {
+						for buffer_size in AUDIO_BUFFER_SIZES {
+							let selected = self.audio_settings.buffer_size == buffer_size;
+							if ui.selectable_label(selected, format!("{} samples", buffer_size)).clicked() {
+								self.audio_settings.buffer_size = buffer_size;
+								self.reopen_audio();
+							}
+						}
+					});
+					ui.menu_button("Latency", |ui| {
+						for latency in AUDIO_LATENCIES {
+							let selected = self.audio_settings.latency == latency;
+							if ui.selectable_label(selected, format!("{} ms", latency)).clicked() {
+								self.audio_settings.latency = latency;
+								self.reopen_audio();
+							}
+						}
+					});
 				});
 				// Save
 				ui.menu_button("Save", |ui| {
@@ -427,16 +1168,44 @@ impl eframe::App for EguiApp {
 			});
 		});
 		
-		egui::CentralPanel::default().frame(Frame::none()).show(ctx, |ui| {
-			let size = [GB_WIDTH as _, GB_HEIGHT as _];
-			let image = egui::ColorImage::from_rgb(size, &buffer);
-		   
-			let texture_handle = ui.ctx().load_texture("Game screen", image, egui::TextureOptions::NEAREST);
-			let scaled_size = Vec2::new((GB_WIDTH as f32 * self.scale) / ctx.pixels_per_point(),
-										(GB_HEIGHT as f32 * self.scale) / ctx.pixels_per_point());
-			ui.centered_and_justified(|ui| {
-				ui.image((texture_handle.id(), scaled_size));
-			});
+		if let Some(menubar) = menubar {
+			self.menubar_height = menubar.response.rect.height();
+		}
+		
+		egui::CentralPanel::default().frame(Frame::none().fill(Color32::BLACK)).show(ctx, |ui| {
+			// Fit the screen in the window, keeping its aspect ratio
+			let scale = self.fit_scale(ui.available_size(), ctx.pixels_per_point());
+			if scale as usize != self.screen_scale {
+				self.screen_scale = scale as usize;
+				self.update_pixel_grid();
+			}
+
+			let (image_width, image_height) = self.gb.framebuffer_size();
+			let image = egui::ColorImage::from_rgba_unmultiplied([image_width, image_height], self.gb.framebuffer());
+			let texture_handle = match &mut self.screen_texture {
+				Some(texture_handle) => {
+					texture_handle.set(image, egui::TextureOptions::NEAREST);
+					texture_handle
+				},
+				None => self.screen_texture.insert(ui.ctx().load_texture("Game screen", image, egui::TextureOptions::NEAREST)),
+			};
+			let scaled_size = Vec2::new((width as f32 * scale) / ctx.pixels_per_point(),
+										(height as f32 * scale) / ctx.pixels_per_point());
+			let image_rect = ui.centered_and_justified(|ui| {
+				ui.image((texture_handle.id(), scaled_size))
+			}).inner.rect;
+
+			// What the script draws is over the screen, inside the border if there is one
+			if let Some(script) = &self.script {
+				let pixel = image_rect.width() / width as f32;
+				let (x, y) = match display_size {
+					(SGB_WIDTH, SGB_HEIGHT) => SGB_SCREEN_OFFSET,
+					_ => (0, 0),
+				};
+				let screen = egui::Rect::from_min_size(image_rect.min + Vec2::new(x as f32, y as f32) * pixel,
+													   Vec2::new(GB_WIDTH as f32, GB_HEIGHT as f32) * pixel);
+				script.draw(ui.painter(), screen);
+			}
 		});
 
 		// Palette window
@@ -463,13 +1232,246 @@ impl eframe::App for EguiApp {
 					}
 				})
 			});
+		if self.palette_index != palette_index {
+			self.apply_palette();
+		}
+
+		// Controls window, a button for every action that waits for a key or
+		// controller button press to rebind it
+		egui::Window::new("Controls")
+			.open(&mut self.show_controls_window)
+			.show(ctx, |ui| {
+				for (i, name) in self.gamepads.names().iter().enumerate() {
+					ui.label(format!("Player {}: {}", i + 1, name));
+				}
+				ui.add(egui::Slider::new(&mut self.gamepads.deadzone, 0.05..=0.95).text("Stick Deadzone"));
+				egui::ScrollArea::vertical().show(ui, |ui| {
+					egui::Grid::new("Key bindings").striped(true).show(ui, |ui| {
+						ui.label("");
+						ui.label("Keyboard");
+						ui.label("");
+						ui.label("Controller");
+						ui.end_row();
+						for action in ACTIONS {
+							ui.label(action.name());
+							let key_name = match (self.rebinding == Some(action), self.controls.key(action)) {
+								(true, _) => "Press a key...".to_string(),
+								(false, Some(key)) => key.name().to_string(),
+								(false, None) => "None".to_string(),
+							};
+							let response = ui.button(key_name);
+							// Otherwise the next space or enter would press the button again
+							response.surrender_focus();
+							if response.clicked() {
+								self.rebinding = match self.rebinding == Some(action) {
+									true => None,
+									false => Some(action),
+								};
+								self.rebinding_button = None;
+							}
+							if ui.button("Clear").clicked() {
+								self.controls.set_key(action, None);
+							}
+
+							let button_name = match (self.rebinding_button == Some(action), self.gamepads.bindings.button(action)) {
+								(true, _) => "Press a button...".to_string(),
+								(false, Some(button)) => button.string(),
+								(false, None) => "None".to_string(),
+							};
+							let response = ui.button(button_name);
+							response.surrender_focus();
+							if response.clicked() {
+								self.rebinding_button = match self.rebinding_button == Some(action) {
+									true => None,
+									false => Some(action),
+								};
+								self.rebinding = None;
+							}
+							if ui.button("Clear").clicked() {
+								self.gamepads.bindings.set_button(action, None);
+							}
+							ui.end_row();
+						}
+					});
+				});
+				if ui.button("Reset to Defaults").clicked() {
+					self.controls = self.default_controls.clone();
+					self.gamepads.bindings = self.default_button_bindings.clone();
+				}
+			});
+		if !self.show_controls_window {
+			self.rebinding = None;
+			self.rebinding_button = None;
+		}
+
+		// Cheats window, where GameShark and Game Genie codes are added and toggled
+		let cheats_changed = egui::Window::new("Cheats")
+			.open(&mut self.show_cheats_window)
+			.show(ctx, |ui| {
+				let mut changed = false;
+				let mut removed = None;
+				egui::Grid::new("Cheat list").striped(true).show(ui, |ui| {
+					for (i, cheat) in self.cheats.iter_mut().enumerate() {
+						changed |= ui.checkbox(&mut cheat.enabled, cheat.name.clone()).changed();
+						ui.label(&cheat.code);
+						if ui.button("Delete").clicked() {
+							removed = Some(i);
+						}
+						ui.end_row();
+					}
+				});
+				if let Some(i) = removed {
+					self.cheats.remove(i);
+					changed = true;
+				}
+
+				ui.separator();
+				egui::Grid::new("New cheat").show(ui, |ui| {
+					ui.label("Name");
+					ui.text_edit_singleline(&mut self.new_cheat_name);
+					ui.end_row();
+					ui.label("Code");
+					ui.text_edit_singleline(&mut self.new_cheat_code)
+						.on_hover_text("GameShark (01VVAAAA) or Game Genie (ABC-DEF-GHI) codes, joined with +");
+					ui.end_row();
+				});
+				if ui.button("Add").clicked() {
+					let cheat = Cheat {
+						name: self.new_cheat_name.trim().to_string(),
+						code: self.new_cheat_code.trim().to_ascii_uppercase().replace(' ', ""),
+						enabled: true,
+					};
+					match cheat.codes() {
+						Ok(_) => {
+							self.cheats.push(cheat);
+							self.new_cheat_name.clear();
+							self.new_cheat_code.clear();
+							self.cheat_error = None;
+							changed = true;
+						},
+						Err(e) => self.cheat_error = Some(e),
+					}
+				}
+				if let Some(error) = &self.cheat_error {
+					ui.colored_label(Color32::RED, error);
+				}
+				changed
+			})
+			.and_then(|response| response.inner)
+			.unwrap_or(false);
+		if cheats_changed {
+			self.update_cheats();
+		}
+
+		// RAM search window, to find the address of a value and then change or freeze it
+		let mut frozen = None;
+		egui::Window::new("RAM Search")
+			.open(&mut self.show_ram_search_window)
+			.show(ctx, |ui| {
+				ui.horizontal(|ui| {
+					ui.radio_value(&mut self.search_size, ValueSize::Byte, "8-bit");
+					ui.radio_value(&mut self.search_size, ValueSize::Word, "16-bit");
+					ui.checkbox(&mut self.search_signed, "Signed");
+					if ui.button("New Search").clicked() {
+						self.ram_search = Some(RamSearch::new(self.search_size, self.search_signed,
+															  |address| self.gb.peek(address)));
+					}
+				});
+				let Some(ram_search) = &mut self.ram_search else {
+					ui.label("Start a new search, then filter the addresses as the value changes in game.");
+					return;
+				};
+				ui.horizontal(|ui| {
+					for filter in SEARCH_FILTERS {
+						if ui.button(filter.name()).clicked() {
+							ram_search.filter(filter, |address| self.gb.peek(address));
+						}
+					}
+				});
+				ui.horizontal(|ui| {
+					let search_value = parse_value(&self.search_value);
+					if ui.add_enabled(search_value.is_some(), egui::Button::new("Equal to")).clicked() {
+						ram_search.filter(SearchFilter::Equal(search_value.unwrap()), |address| self.gb.peek(address));
+					}
+					ui.add(egui::TextEdit::singleline(&mut self.search_value).desired_width(60.0));
+					ui.label("New value");
+					ui.add(egui::TextEdit::singleline(&mut self.poke_value).desired_width(60.0))
+						.on_hover_text("In decimal, or in hexadecimal starting with $");
+				});
+				ui.label(format!("{} addresses", ram_search.candidates().len()));
+
+				let poke_value = parse_value(&self.poke_value);
+				let row_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+				let candidates = ram_search.candidates().len();
+				egui::ScrollArea::vertical().max_height(300.0).show_rows(ui, row_height, candidates, |ui, rows| {
+					egui::Grid::new("RAM search candidates").striped(true).show(ui, |ui| {
+						for &(address, previous) in &ram_search.candidates()[rows] {
+							ui.label(format!("{:04X}", address));
+							ui.label(previous.to_string());
+							ui.label(ram_search.value(address, |address| self.gb.peek(address)).to_string());
+							if ui.add_enabled(poke_value.is_some(), egui::Button::new("Poke")).clicked() {
+								for (i, byte) in ram_search.bytes(poke_value.unwrap()).into_iter().enumerate() {
+									self.gb.poke(address + i as u16, byte);
+								}
+							}
+							// Freezing writes the value after every frame, like a GameShark code
+							if ui.add_enabled(poke_value.is_some(), egui::Button::new("Freeze")).clicked() {
+								let codes: Vec<String> = ram_search.bytes(poke_value.unwrap()).into_iter().enumerate()
+									.map(|(i, byte)| {
+										let [low, high] = (address + i as u16).to_le_bytes();
+										format!("01{:02X}{:02X}{:02X}", byte, low, high)
+									})
+									.collect();
+								frozen = Some(Cheat {
+									name: format!("Freeze {:04X}", address),
+									code: codes.join("+"),
+									enabled: true,
+								});
+							}
+							ui.end_row();
+						}
+					});
+				});
+			});
+		if let Some(cheat) = frozen {
+			self.cheats.push(cheat);
+			self.update_cheats();
+		}
 		// self.print_fps();
 		ctx.request_repaint();
 	}
 
 	fn save(&mut self, storage: &mut dyn Storage) {
 		eframe::set_value(storage, "recent_roms", &self.recent_roms);
+		eframe::set_value(storage, "scale", &self.scale);
+		eframe::set_value(storage, "integer_scaling", &self.integer_scaling);
+		eframe::set_value(storage, "fullscreen", &self.fullscreen);
+		eframe::set_value(storage, "kiosk_mode", &self.kiosk_mode);
 		eframe::set_value(storage, "palette", &self.palettes[self.palette_index].name);
+		eframe::set_value(storage, "band_limited_audio", &self.band_limited_audio);
+		eframe::set_value(storage, "fifo_renderer", &self.fifo_renderer);
+		eframe::set_value(storage, "model", &self.model);
+		eframe::set_value(storage, "colorization_palette", &self.colorization_palette);
+		eframe::set_value(storage, "color_correction", &self.color_correction);
+		eframe::set_value(storage, "screen_tint", &self.screen_tint);
+		eframe::set_value(storage, "frame_blending", &self.frame_blending);
+		eframe::set_value(storage, "pixel_grid", &self.pixel_grid);
+		eframe::set_value(storage, "controls", &self.controls.to_names());
+		eframe::set_value(storage, "button_bindings", &self.gamepads.bindings.to_names());
+		eframe::set_value(storage, "gamepad_deadzone", &self.gamepads.deadzone);
+		eframe::set_value(storage, "turbo_rate", &self.turbo.rate);
+		eframe::set_value(storage, "macros", &self.macros.macros);
+		eframe::set_value(storage, "sample_rate", &self.audio_settings.sample_rate);
+		eframe::set_value(storage, "audio_channels", &self.audio_settings.channels);
+		eframe::set_value(storage, "audio_buffer_size", &self.audio_settings.buffer_size);
+		eframe::set_value(storage, "audio_latency", &self.audio_settings.latency);
+		eframe::set_value(storage, "master_volume", &self.audio_settings.master_volume);
+		eframe::set_value(storage, "mono_audio", &self.mono_audio);
+		eframe::set_value(storage, "channel_volumes", &self.channel_volumes);
+		eframe::set_value(storage, "rewind_enabled", &self.rewind_enabled);
+		eframe::set_value(storage, "rewind_capacity", &self.rewind_capacity);
+		eframe::set_value(storage, "fast_forward_speed", &self.fast_forward_speed);
+		eframe::set_value(storage, "frame_skip", &self.frame_skip);
 	}
 
 }
@@ -0,0 +1,102 @@
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use std::time::Duration;
+
+const BYTES_PER_SAMPLE: u32 = 4; // f32 samples
+
+// Settings of the audio output, read from config.toml
+#[derive(Clone)]
+pub struct AudioSettings {
+	pub sample_rate: u32,
+	pub channels: u8, // 1 (mono) or 2 (stereo)
+	pub buffer_size: u16, // In frames, a sample for each channel
+	pub latency: u32, // In milliseconds, how much audio we try to keep queued
+	pub master_volume: f32, // From 0.0 to 1.0
+}
+
+// Wraps the SDL audio queue, so the device can be reopened with other settings
+pub struct AudioOutput {
+	subsystem: AudioSubsystem,
+	queue: AudioQueue<f32>,
+	latency: u32,
+	vsync: bool, // Whether the display paces the emulator instead of the audio
+}
+
+impl AudioOutput {
+	pub fn new(subsystem: AudioSubsystem, settings: &AudioSettings, vsync: bool) -> Self {
+		let queue = AudioOutput::open_queue(&subsystem, settings);
+		AudioOutput {
+			subsystem,
+			queue,
+			latency: settings.latency,
+			vsync,
+		}
+	}
+
+	// Closes the device, and opens it again with new settings
+	pub fn reopen(&mut self, settings: &AudioSettings) {
+		self.queue = AudioOutput::open_queue(&self.subsystem, settings);
+		self.latency = settings.latency;
+	}
+
+	// Queues samples from the emulator. When the audio paces the emulator, this
+	// sleeps until there is no more audio queued than the latency allows
+	pub fn queue_audio(&self, buffer: &[f32]) {
+		let queued = self.queue.size();
+		let target = self.target_size();
+		if queued > target {
+			if self.vsync {
+				// The display paces the emulator, so only drop audio if we are way ahead
+				if queued > 2 * target {
+					return;
+				}
+			} else {
+				let seconds = (queued - target) as f64 / self.bytes_per_second() as f64;
+				std::thread::sleep(Duration::from_secs_f64(seconds));
+			}
+		}
+		let _ = self.queue.queue_audio(buffer);
+	}
+
+	// Returns how full the queue is, from 0.0 to 1.0, where 0.5 is the target
+	pub fn fill(&self) -> f32 {
+		self.queue.size() as f32 / (2 * self.target_size()) as f32
+	}
+
+	// Returns the sample rate the device actually uses
+	pub fn sample_rate(&self) -> u32 {
+		self.queue.spec().freq as u32
+	}
+
+	// Returns the number of channels the device actually uses
+	pub fn channels(&self) -> usize {
+		self.queue.spec().channels as usize
+	}
+
+	pub fn buffer_size(&self) -> usize {
+		self.queue.spec().samples as usize
+	}
+
+	fn bytes_per_second(&self) -> u32 {
+		self.sample_rate() * self.channels() as u32 * BYTES_PER_SAMPLE
+	}
+
+	// The queue size in bytes that matches the latency, but at least 2 device buffers
+	fn target_size(&self) -> u32 {
+		let latency_size = (self.bytes_per_second() as u64 * self.latency as u64 / 1000) as u32;
+		let buffers_size = 2 * self.buffer_size() as u32 * self.channels() as u32 * BYTES_PER_SAMPLE;
+		latency_size.max(buffers_size)
+	}
+
+	fn open_queue(subsystem: &AudioSubsystem, settings: &AudioSettings) -> AudioQueue<f32> {
+		let desired_spec = AudioSpecDesired {
+			freq: Some(settings.sample_rate as i32),
+			channels: Some(settings.channels.clamp(1, 2)),
+			samples: Some(settings.buffer_size),
+		};
+		let queue: AudioQueue<f32> = subsystem.open_queue(None, &desired_spec)
+			.expect("Failed to create audio queue");
+		queue.resume();
+		queue
+	}
+}
@@ -1,5 +1,10 @@
 use config::Config;
+use eframe::egui::Key;
 use crate::app::Palette;
+use crate::audio::AudioSettings;
+use crate::controls::{Controls, ACTIONS};
+use crate::gamepad::ButtonBindings;
+use sdl2::controller::Button;
 
 // Get all palettes from config.toml
 pub fn get_all_palettes() -> Vec<Palette> {
@@ -55,3 +60,78 @@ fn hex_to_rgb(hex: &str) -> Option<(u8, u8, u8)> {
 	let b = u8::from_str_radix(&hex[5..7], 16).unwrap();
 	Some((r, g, b))
 }
+
+// Returns whether the video should be synced to the display instead of the audio
+pub fn get_vsync() -> bool {
+	let config = Config::builder()
+		.add_source(config::File::with_name("config.toml"))
+		.build()
+		.unwrap();
+
+	config.get_bool("frontend.vsync").unwrap_or(false)
+}
+
+// Returns the audio settings from the [audio] section, with a default for any missing value
+pub fn get_audio_settings() -> AudioSettings {
+	let config = Config::builder()
+		.add_source(config::File::with_name("config.toml"))
+		.build()
+		.unwrap();
+
+	let volume = config.get_int("audio.volume").unwrap_or(100).clamp(0, 100);
+	AudioSettings {
+		sample_rate: config.get_int("audio.sample_rate").unwrap_or(44100) as u32,
+		channels: config.get_int("audio.channels").unwrap_or(2).clamp(1, 2) as u8,
+		buffer_size: config.get_int("audio.buffer_size").unwrap_or(1024).clamp(64, 8192) as u16,
+		latency: config.get_int("audio.latency").unwrap_or(50).clamp(0, 1000) as u32,
+		master_volume: volume as f32 / 100.0,
+	}
+}
+
+// Returns the keys bound in the [frontend.controls] section, with the default for any missing action.
+// Anything that isn't the name of a key (like an empty string) unbinds the action
+pub fn get_controls() -> Controls {
+	let config = Config::builder()
+		.add_source(config::File::with_name("config.toml"))
+		.build()
+		.unwrap();
+
+	let mut controls = Controls::default();
+	for action in ACTIONS {
+		let saved_name = action.saved_name(|name| config.get_string(&("frontend.controls.".to_string() + name)).is_ok());
+		let name = "frontend.controls.".to_string() + saved_name.as_str();
+		if let Ok(key_name) = config.get_string(&name) {
+			controls.set_key(action, Key::from_name(&key_name));
+		}
+	}
+	controls
+}
+
+// Returns the controller buttons bound in the [frontend.gamepad] section, with the default for any missing action.
+// Buttons use the SDL names, like "a", "dpup", "leftshoulder" or "start"
+pub fn get_button_bindings() -> ButtonBindings {
+	let config = Config::builder()
+		.add_source(config::File::with_name("config.toml"))
+		.build()
+		.unwrap();
+
+	let mut bindings = ButtonBindings::default();
+	for action in ACTIONS {
+		let saved_name = action.saved_name(|name| config.get_string(&("frontend.gamepad.".to_string() + name)).is_ok());
+		let name = "frontend.gamepad.".to_string() + saved_name.as_str();
+		if let Ok(button_name) = config.get_string(&name) {
+			bindings.set_button(action, Button::from_string(&button_name));
+		}
+	}
+	bindings
+}
+
+// Returns how far the left stick moves before it acts as the D-pad, from 0.0 to 1.0
+pub fn get_deadzone() -> f32 {
+	let config = Config::builder()
+		.add_source(config::File::with_name("config.toml"))
+		.build()
+		.unwrap();
+
+	config.get_float("frontend.gamepad.deadzone").unwrap_or(0.25).clamp(0.05, 0.95) as f32
+}
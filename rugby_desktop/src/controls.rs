@@ -0,0 +1,212 @@
+use eframe::egui::{InputState, Key};
+
+// Everything that can be bound to a key: the buttons of the Gameboy, and the
+// settings of the emulator
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+	Up,
+	Down,
+	Left,
+	Right,
+	A,
+	B,
+	Start,
+	Select,
+	TurboA,
+	TurboB,
+	RecordMacro(usize),
+	PlayMacro(usize),
+	SaveState,
+	LoadState,
+	SaveStateSlot(usize),
+	LoadStateSlot(usize),
+	Rewind,
+	FastForward,
+	ToggleMute,
+	ToggleChannel(usize),
+	ToggleLayer(usize), // 0: Background, 1: Window, 2: Objects
+	PrevBgMap,
+	NextBgMap,
+	Exit,
+}
+
+pub const ACTIONS: [Action; 41] = [
+	Action::Up, Action::Down, Action::Left, Action::Right,
+	Action::A, Action::B, Action::Start, Action::Select,
+	Action::TurboA, Action::TurboB,
+	Action::RecordMacro(0), Action::RecordMacro(1), Action::RecordMacro(2), Action::RecordMacro(3),
+	Action::PlayMacro(0), Action::PlayMacro(1), Action::PlayMacro(2), Action::PlayMacro(3),
+	Action::SaveState, Action::LoadState,
+	Action::SaveStateSlot(0), Action::SaveStateSlot(1), Action::SaveStateSlot(2), Action::SaveStateSlot(3),
+	Action::LoadStateSlot(0), Action::LoadStateSlot(1), Action::LoadStateSlot(2), Action::LoadStateSlot(3),
+	Action::Rewind, Action::FastForward, Action::ToggleMute,
+	Action::ToggleChannel(0), Action::ToggleChannel(1), Action::ToggleChannel(2), Action::ToggleChannel(3),
+	Action::ToggleLayer(0), Action::ToggleLayer(1), Action::ToggleLayer(2),
+	Action::PrevBgMap, Action::NextBgMap, Action::Exit,
+];
+
+impl Action {
+	// The name shown in the Controls window
+	pub fn name(&self) -> String {
+		match self {
+			Action::Up => "Up".to_string(),
+			Action::Down => "Down".to_string(),
+			Action::Left => "Left".to_string(),
+			Action::Right => "Right".to_string(),
+			Action::A => "A".to_string(),
+			Action::B => "B".to_string(),
+			Action::Start => "Start".to_string(),
+			Action::Select => "Select".to_string(),
+			Action::TurboA => "Turbo A".to_string(),
+			Action::TurboB => "Turbo B".to_string(),
+			Action::RecordMacro(i) => format!("Record Macro {}", i),
+			Action::PlayMacro(i) => format!("Play Macro {}", i),
+			Action::SaveState => "Save State".to_string(),
+			Action::LoadState => "Load State".to_string(),
+			Action::SaveStateSlot(i) => format!("Save to State Slot {}", i),
+			Action::LoadStateSlot(i) => format!("Load from State Slot {}", i),
+			Action::Rewind => "Rewind (hold)".to_string(),
+			Action::FastForward => "Fast-forward (hold)".to_string(),
+			Action::ToggleMute => "Toggle Audio".to_string(),
+			Action::ToggleChannel(i) => format!("Toggle Channel {}", i),
+			Action::ToggleLayer(i) => format!("Toggle Layer {}", i),
+			Action::PrevBgMap => "Previous Background Map".to_string(),
+			Action::NextBgMap => "Next Background Map".to_string(),
+			Action::Exit => "Exit".to_string(),
+		}
+	}
+
+	// The name of the key in the [frontend.controls] section of config.toml
+	pub fn config_name(&self) -> String {
+		match self {
+			Action::Up => "up".to_string(),
+			Action::Down => "down".to_string(),
+			Action::Left => "left".to_string(),
+			Action::Right => "right".to_string(),
+			Action::A => "a".to_string(),
+			Action::B => "b".to_string(),
+			Action::Start => "start".to_string(),
+			Action::Select => "select".to_string(),
+			Action::TurboA => "turbo_a".to_string(),
+			Action::TurboB => "turbo_b".to_string(),
+			Action::RecordMacro(i) => format!("record_macro_{}", i),
+			Action::PlayMacro(i) => format!("play_macro_{}", i),
+			Action::SaveState => "save_state".to_string(),
+			Action::LoadState => "load_state".to_string(),
+			Action::SaveStateSlot(i) => format!("save_state_{}", i),
+			Action::LoadStateSlot(i) => format!("load_state_{}", i),
+			Action::Rewind => "rewind".to_string(),
+			Action::FastForward => "fast_forward".to_string(),
+			Action::ToggleMute => "toggle_mute".to_string(),
+			Action::ToggleChannel(i) => format!("toggle_channel_{}", i),
+			Action::ToggleLayer(i) => format!("toggle_layer_{}", i),
+			Action::PrevBgMap => "prev_bg_map".to_string(),
+			Action::NextBgMap => "next_bg_map".to_string(),
+			Action::Exit => "exit".to_string(),
+		}
+	}
+
+	// The name of the action among saved names (from config.toml, or from a previous run). Macros and
+	// channels were numbered from 1 before, so when there is no slot 0, record_macro_1 is the first macro
+	pub fn saved_name(&self, is_saved: impl Fn(&str) -> bool) -> String {
+		let (old_name, first_slot) = match *self {
+			Action::RecordMacro(i) => (format!("record_macro_{}", i + 1), Action::RecordMacro(0)),
+			Action::PlayMacro(i) => (format!("play_macro_{}", i + 1), Action::PlayMacro(0)),
+			Action::ToggleChannel(i) => (format!("toggle_channel_{}", i + 1), Action::ToggleChannel(0)),
+			_ => return self.config_name(),
+		};
+		match is_saved(&first_slot.config_name()) {
+			true => self.config_name(),
+			false => old_name,
+		}
+	}
+
+	// Held actions are active for as long as their key is down, the others
+	// only once when their key is pressed
+	pub fn held(&self) -> bool {
+		matches!(self, Action::Up | Action::Down | Action::Left | Action::Right
+				 | Action::A | Action::B | Action::Start | Action::Select
+				 | Action::TurboA | Action::TurboB
+				 | Action::Rewind | Action::FastForward)
+	}
+
+	pub fn index(&self) -> usize {
+		ACTIONS.iter().position(|action| action == self).unwrap()
+	}
+}
+
+// The key bound to every action, or None if it's unbound
+#[derive(Clone, PartialEq)]
+pub struct Controls {
+	keys: [Option<Key>; ACTIONS.len()],
+}
+
+impl Controls {
+	pub fn key(&self, action: Action) -> Option<Key> {
+		self.keys[action.index()]
+	}
+
+	pub fn set_key(&mut self, action: Action, key: Option<Key>) {
+		self.keys[action.index()] = key;
+	}
+
+	// Binds a key to an action, and unbinds it from any other action
+	pub fn bind(&mut self, action: Action, key: Key) {
+		for bound_key in self.keys.iter_mut() {
+			if *bound_key == Some(key) {
+				*bound_key = None;
+			}
+		}
+		self.set_key(action, Some(key));
+	}
+
+	// Returns whether an action is active this frame
+	pub fn active(&self, action: Action, input_state: &InputState) -> bool {
+		match self.key(action) {
+			Some(key) if action.held() => input_state.key_down(key),
+			Some(key) => input_state.key_pressed(key),
+			None => false,
+		}
+	}
+
+	// The name of the key bound to every action, to persist them between runs
+	pub fn to_names(&self) -> Vec<(String, Option<String>)> {
+		ACTIONS.iter()
+			.map(|action| (action.config_name(), self.key(*action).map(|key| key.name().to_string())))
+			.collect()
+	}
+
+	// Rebinds the actions with a name in the list, and keeps the others
+	pub fn load_names(&mut self, names: &[(String, Option<String>)]) {
+		for action in ACTIONS.iter() {
+			let saved_name = action.saved_name(|saved_name| names.iter().any(|(name, _)| name == saved_name));
+			if let Some((_, key_name)) = names.iter().find(|(name, _)| *name == saved_name) {
+				self.set_key(*action, key_name.as_deref().and_then(Key::from_name));
+			}
+		}
+	}
+}
+
+impl Default for Controls {
+	fn default() -> Self {
+		let mut controls = Controls {
+			keys: [None; ACTIONS.len()],
+		};
+		controls.set_key(Action::Up, Some(Key::ArrowUp));
+		controls.set_key(Action::Down, Some(Key::ArrowDown));
+		controls.set_key(Action::Left, Some(Key::ArrowLeft));
+		controls.set_key(Action::Right, Some(Key::ArrowRight));
+		controls.set_key(Action::A, Some(Key::A));
+		controls.set_key(Action::B, Some(Key::S));
+		controls.set_key(Action::Start, Some(Key::Z));
+		controls.set_key(Action::Select, Some(Key::X));
+		controls.set_key(Action::TurboA, Some(Key::Q));
+		controls.set_key(Action::TurboB, Some(Key::W));
+		controls.set_key(Action::SaveState, Some(Key::O));
+		controls.set_key(Action::LoadState, Some(Key::P));
+		controls.set_key(Action::Rewind, Some(Key::R));
+		controls.set_key(Action::FastForward, Some(Key::Tab));
+		controls.set_key(Action::Exit, Some(Key::Escape));
+		controls
+	}
+}
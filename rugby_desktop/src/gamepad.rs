@@ -0,0 +1,190 @@
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::{EventPump, GameControllerSubsystem};
+
+use rugby_core::input::Input;
+use crate::controls::{Action, ACTIONS};
+
+const AXIS_MAX: f32 = 32767.0;
+
+// The controller button bound to every action, or None if it's unbound
+#[derive(Clone, PartialEq)]
+pub struct ButtonBindings {
+	buttons: [Option<Button>; ACTIONS.len()],
+}
+
+impl ButtonBindings {
+	pub fn button(&self, action: Action) -> Option<Button> {
+		self.buttons[action.index()]
+	}
+
+	pub fn set_button(&mut self, action: Action, button: Option<Button>) {
+		self.buttons[action.index()] = button;
+	}
+
+	// Binds a button to an action, and unbinds it from any other action
+	pub fn bind(&mut self, action: Action, button: Button) {
+		for bound_button in self.buttons.iter_mut() {
+			if *bound_button == Some(button) {
+				*bound_button = None;
+			}
+		}
+		self.set_button(action, Some(button));
+	}
+
+	// The SDL name of the button bound to every action, to persist them between runs
+	pub fn to_names(&self) -> Vec<(String, Option<String>)> {
+		ACTIONS.iter()
+			.map(|action| (action.config_name(), self.button(*action).map(|button| button.string())))
+			.collect()
+	}
+
+	// Rebinds the actions with a name in the list, and keeps the others
+	pub fn load_names(&mut self, names: &[(String, Option<String>)]) {
+		for action in ACTIONS.iter() {
+			let saved_name = action.saved_name(|saved_name| names.iter().any(|(name, _)| name == saved_name));
+			if let Some((_, button_name)) = names.iter().find(|(name, _)| *name == saved_name) {
+				self.set_button(*action, button_name.as_deref().and_then(Button::from_string));
+			}
+		}
+	}
+}
+
+impl Default for ButtonBindings {
+	// The A and B buttons of the Gameboy are on the right and the bottom, like
+	// on Nintendo controllers, and their turbo buttons on the top and the left
+	fn default() -> Self {
+		let mut bindings = ButtonBindings {
+			buttons: [None; ACTIONS.len()],
+		};
+		bindings.set_button(Action::Up, Some(Button::DPadUp));
+		bindings.set_button(Action::Down, Some(Button::DPadDown));
+		bindings.set_button(Action::Left, Some(Button::DPadLeft));
+		bindings.set_button(Action::Right, Some(Button::DPadRight));
+		bindings.set_button(Action::A, Some(Button::B));
+		bindings.set_button(Action::B, Some(Button::A));
+		bindings.set_button(Action::Start, Some(Button::Start));
+		bindings.set_button(Action::Select, Some(Button::Back));
+		bindings.set_button(Action::TurboA, Some(Button::Y));
+		bindings.set_button(Action::TurboB, Some(Button::X));
+		bindings.set_button(Action::Rewind, Some(Button::LeftShoulder));
+		bindings.set_button(Action::FastForward, Some(Button::RightShoulder));
+		bindings
+	}
+}
+
+// Every connected controller, in the order they were connected. The first
+// one is player 1, the next ones are kept for link play
+pub struct Gamepads {
+	subsystem: Option<GameControllerSubsystem>, // None if SDL has no controller support, so no controller connects
+	event_pump: EventPump,
+	controllers: Vec<GameController>,
+	actions: Vec<[bool; ACTIONS.len()]>, // The actions held on every controller
+	previous_actions: Vec<[bool; ACTIONS.len()]>,
+	pressed_button: Option<Button>, // The last button pressed on any controller, for rebinding
+	pub bindings: ButtonBindings,
+	pub deadzone: f32, // From 0.0 to 1.0, how far the left stick moves before it acts as the D-pad
+}
+
+impl Gamepads {
+	pub fn new(subsystem: Option<GameControllerSubsystem>, event_pump: EventPump, bindings: ButtonBindings, deadzone: f32) -> Self {
+		// Controllers that are already connected also send an added event
+		Gamepads {
+			subsystem,
+			event_pump,
+			controllers: Vec::new(),
+			actions: Vec::new(),
+			previous_actions: Vec::new(),
+			pressed_button: None,
+			bindings,
+			deadzone,
+		}
+	}
+
+	// Handles controllers being connected or disconnected, then reads the
+	// actions held on every controller. Called once every update
+	pub fn update(&mut self) {
+		self.pressed_button = None;
+		for event in self.event_pump.poll_iter() {
+			match event {
+				Event::ControllerDeviceAdded { which, .. } => {
+					let Some(subsystem) = &self.subsystem else {
+						continue;
+					};
+					if let Ok(controller) = subsystem.open(which) {
+						// Don't open the same controller twice
+						if !self.controllers.iter().any(|c| c.instance_id() == controller.instance_id()) {
+							self.controllers.push(controller);
+						}
+					}
+				},
+				Event::ControllerDeviceRemoved { which, .. } => {
+					self.controllers.retain(|controller| controller.instance_id() != which);
+				},
+				Event::ControllerButtonDown { button, .. } => {
+					self.pressed_button = Some(button);
+				},
+				_ => {},
+			}
+		}
+
+		self.previous_actions = std::mem::take(&mut self.actions);
+		self.previous_actions.resize(self.controllers.len(), [false; ACTIONS.len()]);
+		self.actions = self.controllers.iter().map(|controller| self.read_actions(controller)).collect();
+	}
+
+	fn read_actions(&self, controller: &GameController) -> [bool; ACTIONS.len()] {
+		let mut actions = [false; ACTIONS.len()];
+		for action in ACTIONS {
+			if let Some(button) = self.bindings.button(action) {
+				actions[action.index()] = controller.button(button);
+			}
+		}
+
+		// The left stick moves like the D-pad past the deadzone
+		let x = controller.axis(Axis::LeftX) as f32 / AXIS_MAX;
+		let y = controller.axis(Axis::LeftY) as f32 / AXIS_MAX;
+		actions[Action::Left.index()] |= x < -self.deadzone;
+		actions[Action::Right.index()] |= x > self.deadzone;
+		actions[Action::Up.index()] |= y < -self.deadzone;
+		actions[Action::Down.index()] |= y > self.deadzone;
+		actions
+	}
+
+	// Returns whether an action is active this frame on the controller of a player
+	pub fn active(&self, action: Action, player: usize) -> bool {
+		let held = self.actions.get(player).is_some_and(|actions| actions[action.index()]);
+		let previous = self.previous_actions.get(player).is_some_and(|actions| actions[action.index()]);
+		match action.held() {
+			true => held,
+			false => held && !previous,
+		}
+	}
+
+	// Returns whether an action is active this frame on any controller, for the settings of the emulator
+	pub fn any_active(&self, action: Action) -> bool {
+		(0..self.controllers.len()).any(|player| self.active(action, player))
+	}
+
+	// Returns the buttons of the Gameboy held on the controller of a player
+	pub fn input(&self, player: usize) -> Input {
+		Input {
+			up: self.active(Action::Up, player),
+			down: self.active(Action::Down, player),
+			left: self.active(Action::Left, player),
+			right: self.active(Action::Right, player),
+			a: self.active(Action::A, player),
+			b: self.active(Action::B, player),
+			start: self.active(Action::Start, player),
+			select: self.active(Action::Select, player),
+		}
+	}
+
+	pub fn pressed_button(&self) -> Option<Button> {
+		self.pressed_button
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		self.controllers.iter().map(|controller| controller.name()).collect()
+	}
+}
@@ -1,5 +1,9 @@
 mod app;
+mod audio;
 mod config_builder;
+mod controls;
+mod gamepad;
+mod script;
 
 fn main() {
 	app::run_app();
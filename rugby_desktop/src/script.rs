@@ -0,0 +1,303 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use eframe::egui::{Align2, Color32, FontId, Painter, Rect, Stroke, Vec2};
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, Map, AST};
+
+use rugby_core::emulator::Emulator;
+use rugby_core::input::Input;
+
+const SAVE_STATE_SLOTS: i64 = 4;
+const MAX_OPERATIONS: u64 = 10_000_000; // Stops a script stuck in a loop instead of freezing the emulator
+const FONT_SIZE: f32 = 8.0; // In Gameboy pixels, the height of a tile
+
+// Something a script draws over the screen, in Gameboy pixels. Shapes are
+// cleared before every frame, so scripts draw them again from their callbacks
+enum Shape {
+	Text { x: f32, y: f32, text: String, color: Color32 },
+	Rect { x: f32, y: f32, width: f32, height: f32, color: Color32, filled: bool },
+}
+
+// Save states are made and loaded once the callbacks are done
+enum Command {
+	SaveState(usize),
+	LoadState(usize),
+}
+
+// What a script sees and changes of the emulator. Memory and registers are
+// read and written right away, save states are made and loaded once the
+// callbacks are done
+struct ScriptContext {
+	// Rhai functions can't borrow the emulator, so while the script runs the
+	// emulator is swapped in here, and an empty one takes its place
+	gb: Emulator,
+	frame: i64, // Frames run since the script was loaded
+	input: Input, // Buttons held during the next frame, on top of the player's
+	commands: Vec<Command>,
+	frame_callbacks: Vec<FnPtr>,
+	write_callbacks: Vec<(u16, FnPtr)>,
+	shapes: Vec<Shape>,
+}
+
+impl ScriptContext {
+	fn new() -> Self {
+		ScriptContext {
+			gb: Emulator::new(None, None, Box::new(|_| {})),
+			frame: 0,
+			input: Input::new(),
+			commands: Vec::new(),
+			frame_callbacks: Vec::new(),
+			write_callbacks: Vec::new(),
+			shapes: Vec::new(),
+		}
+	}
+
+	fn read(&self, address: i64) -> u8 {
+		self.gb.peek(address as u16)
+	}
+
+	fn write(&mut self, address: i64, value: i64) {
+		self.gb.poke(address as u16, value as u8);
+	}
+}
+
+// Swaps the emulator back out of the context when it's dropped, even if a
+// callback panics, so the app never keeps the empty one
+struct SwapBack<'a> {
+	context: &'a RefCell<ScriptContext>,
+	gb: &'a mut Emulator,
+}
+
+impl Drop for SwapBack<'_> {
+	fn drop(&mut self) {
+		std::mem::swap(self.gb, &mut self.context.borrow_mut().gb);
+	}
+}
+
+// A Rhai script that automates the emulator. It runs once when it is loaded,
+// to register the callbacks that run after every frame or after the game
+// writes to an address. Write callbacks don't interrupt the game: the writes
+// are recorded during the frame, and the callbacks are called for them in
+// order after it, so they see the memory at the end of the frame
+pub struct Script {
+	engine: Engine,
+	ast: AST,
+	context: Rc<RefCell<ScriptContext>>,
+}
+
+impl Script {
+	pub fn load(path: &Path, gb: &mut Emulator) -> Result<Self, String> {
+		let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+		let context = Rc::new(RefCell::new(ScriptContext::new()));
+		let engine = create_engine(&context);
+		let ast = engine.compile(source).map_err(|e| e.to_string())?;
+		let script = Script {
+			engine,
+			ast,
+			context,
+		};
+		script.with_emulator(gb, |script| script.engine.run_ast(&script.ast).map_err(|e| e.to_string()))?;
+		script.apply(gb);
+		Ok(script)
+	}
+
+	// Runs f with the emulator available to the script's functions
+	fn with_emulator<T>(&self, gb: &mut Emulator, f: impl FnOnce(&Self) -> T) -> T {
+		std::mem::swap(gb, &mut self.context.borrow_mut().gb);
+		let _swap_back = SwapBack { context: &self.context, gb };
+		f(self)
+	}
+
+	// The buttons the script holds during the next frame
+	pub fn input(&self) -> Input {
+		self.context.borrow().input
+	}
+
+	// Calls the callbacks after a frame: first for every write to a watched
+	// address during the frame, in order, then the frame callbacks
+	pub fn run_callbacks(&mut self, gb: &mut Emulator) -> Result<(), String> {
+		let hits = gb.take_watch_hits();
+		{
+			let mut context = self.context.borrow_mut();
+			context.frame += 1;
+			context.input = Input::new();
+			context.shapes.clear();
+		}
+
+		self.with_emulator(gb, |script| -> Result<(), String> {
+			for (address, value) in hits {
+				let callbacks: Vec<FnPtr> = script.context.borrow().write_callbacks.iter()
+					.filter(|(watched, _)| *watched == address)
+					.map(|(_, callback)| callback.clone())
+					.collect();
+				for callback in callbacks {
+					script.call(&callback, (address as i64, value as i64))?;
+				}
+			}
+			let callbacks = script.context.borrow().frame_callbacks.clone();
+			for callback in callbacks {
+				script.call(&callback, ())?;
+			}
+			Ok(())
+		})?;
+		self.apply(gb);
+		Ok(())
+	}
+
+	fn call(&self, callback: &FnPtr, args: impl FuncArgs) -> Result<(), String> {
+		callback.call::<Dynamic>(&self.engine, &self.ast, args)
+			.map(|_| ())
+			.map_err(|e| e.to_string())
+	}
+
+	// Applies the save states of the callbacks, and watches the addresses they registered
+	fn apply(&self, gb: &mut Emulator) {
+		let mut context = self.context.borrow_mut();
+		for command in context.commands.drain(..) {
+			match command {
+				Command::SaveState(slot) => gb.save_state(Some(slot)),
+				Command::LoadState(slot) => gb.load_state(Some(slot)),
+			}
+		}
+		let mut addresses: Vec<u16> = context.write_callbacks.iter().map(|(address, _)| *address).collect();
+		addresses.sort();
+		addresses.dedup();
+		gb.set_watched_addresses(addresses);
+	}
+
+	// Draws the shapes over the Gameboy screen, which takes up the given rectangle
+	pub fn draw(&self, painter: &Painter, screen: Rect) {
+		let painter = painter.with_clip_rect(screen);
+		let pixel = screen.width() / 160.0;
+		let point = |x: f32, y: f32| screen.min + Vec2::new(x, y) * pixel;
+		for shape in &self.context.borrow().shapes {
+			match shape {
+				Shape::Text { x, y, text, color } => {
+					painter.text(point(*x, *y), Align2::LEFT_TOP, text, FontId::monospace(FONT_SIZE * pixel), *color);
+				},
+				Shape::Rect { x, y, width, height, color, filled } => {
+					let rect = Rect::from_min_size(point(*x, *y), Vec2::new(*width, *height) * pixel);
+					if *filled {
+						painter.rect_filled(rect, 0.0, *color);
+					} else {
+						// Inside the rectangle, so it covers the same pixels as a filled one
+						painter.rect_stroke(rect.shrink(pixel / 2.0), 0.0, Stroke::new(pixel, *color));
+					}
+				},
+			}
+		}
+	}
+}
+
+// Colors are written like 0xRRGGBB
+fn color(value: i64) -> Color32 {
+	let [_, r, g, b] = (value as u32).to_be_bytes();
+	Color32::from_rgb(r, g, b)
+}
+
+// Registers the functions that scripts can call
+fn create_engine(context: &Rc<RefCell<ScriptContext>>) -> Engine {
+	let mut engine = Engine::new();
+	engine.set_max_operations(MAX_OPERATIONS);
+
+	// Memory, read and written like a debugger, with 16-bit values in little-endian
+	let c = Rc::clone(context);
+	engine.register_fn("read8", move |address: i64| {
+		c.borrow().read(address) as i64
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("read16", move |address: i64| {
+		let context = c.borrow();
+		let low = context.read(address);
+		let high = context.read((address as u16).wrapping_add(1) as i64);
+		u16::from_le_bytes([low, high]) as i64
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("write8", move |address: i64, value: i64| {
+		c.borrow_mut().write(address, value);
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("write16", move |address: i64, value: i64| {
+		let mut context = c.borrow_mut();
+		context.write(address, value & 0xFF);
+		context.write((address as u16).wrapping_add(1) as i64, (value >> 8) & 0xFF);
+	});
+
+	let c = Rc::clone(context);
+	engine.register_fn("registers", move || {
+		let r = c.borrow().gb.registers();
+		let mut map = Map::new();
+		for (name, value) in [("a", r.a as i64), ("f", r.f as i64), ("b", r.b as i64), ("c", r.c as i64),
+							  ("d", r.d as i64), ("e", r.e as i64), ("h", r.h as i64), ("l", r.l as i64),
+							  ("sp", r.sp as i64), ("pc", r.pc as i64)] {
+			map.insert(name.into(), Dynamic::from_int(value));
+		}
+		map
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("frame", move || c.borrow().frame);
+
+	// Holds a button during the next frame
+	let c = Rc::clone(context);
+	engine.register_fn("press", move |button: &str| -> Result<(), Box<EvalAltResult>> {
+		let input = &mut c.borrow_mut().input;
+		match button {
+			"up" => input.up = true,
+			"down" => input.down = true,
+			"left" => input.left = true,
+			"right" => input.right = true,
+			"a" => input.a = true,
+			"b" => input.b = true,
+			"start" => input.start = true,
+			"select" => input.select = true,
+			_ => return Err(format!("Unknown button {}", button).into()),
+		}
+		Ok(())
+	});
+
+	// The same 4 slots as the State menu
+	let c = Rc::clone(context);
+	engine.register_fn("save_state", move |slot: i64| -> Result<(), Box<EvalAltResult>> {
+		if !(0..SAVE_STATE_SLOTS).contains(&slot) {
+			return Err(format!("Invalid save state slot {}", slot).into());
+		}
+		c.borrow_mut().commands.push(Command::SaveState(slot as usize));
+		Ok(())
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("load_state", move |slot: i64| -> Result<(), Box<EvalAltResult>> {
+		if !(0..SAVE_STATE_SLOTS).contains(&slot) {
+			return Err(format!("Invalid save state slot {}", slot).into());
+		}
+		c.borrow_mut().commands.push(Command::LoadState(slot as usize));
+		Ok(())
+	});
+
+	let c = Rc::clone(context);
+	engine.register_fn("draw_text", move |x: i64, y: i64, text: &str, value: i64| {
+		c.borrow_mut().shapes.push(Shape::Text { x: x as f32, y: y as f32, text: text.to_string(), color: color(value) });
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("draw_rect", move |x: i64, y: i64, width: i64, height: i64, value: i64| {
+		c.borrow_mut().shapes.push(Shape::Rect { x: x as f32, y: y as f32, width: width as f32, height: height as f32,
+												 color: color(value), filled: false });
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("fill_rect", move |x: i64, y: i64, width: i64, height: i64, value: i64| {
+		c.borrow_mut().shapes.push(Shape::Rect { x: x as f32, y: y as f32, width: width as f32, height: height as f32,
+												 color: color(value), filled: true });
+	});
+
+	// Callbacks are closures or function pointers, like on_frame(|| ...) or on_write(0xC000, Fn("name"))
+	let c = Rc::clone(context);
+	engine.register_fn("on_frame", move |callback: FnPtr| {
+		c.borrow_mut().frame_callbacks.push(callback);
+	});
+	let c = Rc::clone(context);
+	engine.register_fn("on_write", move |address: i64, callback: FnPtr| {
+		c.borrow_mut().write_callbacks.push((address as u16, callback));
+	});
+	engine
+}
@@ -0,0 +1,20 @@
+// Regenerates the C header, include/rugby.h, from the exported functions with
+// the header feature. Otherwise the committed header is left untouched, so
+// building the crate doesn't write to the source directory
+fn main() {
+	#[cfg(feature = "header")]
+	{
+		let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+		let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+		match cbindgen::generate_with_config(&crate_dir, config) {
+			Ok(bindings) => {
+				bindings.write_to_file(format!("{}/include/rugby.h", crate_dir));
+			},
+			Err(e) => println!("cargo:warning=Failed to generate rugby.h: {}", e),
+		}
+		println!("cargo:rerun-if-changed=src/lib.rs");
+		println!("cargo:rerun-if-changed=cbindgen.toml");
+	}
+	#[cfg(not(feature = "header"))]
+	println!("cargo:rerun-if-changed=build.rs");
+}
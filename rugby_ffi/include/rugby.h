@@ -0,0 +1,88 @@
+#ifndef RUGBY_H
+#define RUGBY_H
+
+/* Generated by cbindgen from rugby_ffi/src/lib.rs with cargo build --features header, don't edit it by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The buttons of the Game Boy, combined into a bitmask for rugby_set_input
+#define RUGBY_BUTTON_UP (1 << 0)
+
+#define RUGBY_BUTTON_DOWN (1 << 1)
+
+#define RUGBY_BUTTON_LEFT (1 << 2)
+
+#define RUGBY_BUTTON_RIGHT (1 << 3)
+
+#define RUGBY_BUTTON_A (1 << 4)
+
+#define RUGBY_BUTTON_B (1 << 5)
+
+#define RUGBY_BUTTON_START (1 << 6)
+
+#define RUGBY_BUTTON_SELECT (1 << 7)
+
+// An emulator, created with rugby_create and destroyed with rugby_destroy.
+// It must only be used from one thread at a time.
+typedef struct RugbyEmulator RugbyEmulator;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates an emulator with no ROM loaded, which shows a blank screen.
+RugbyEmulator *rugby_create(void);
+
+// Destroys an emulator. Does nothing if it's NULL.
+void rugby_destroy(RugbyEmulator *emulator);
+
+// Loads a ROM, copied from memory, and starts it. The cartridge RAM is not
+// saved to a file, but it's part of the save states.
+// Returns 0 on success, or -1 if the ROM isn't supported.
+int32_t rugby_load_rom(RugbyEmulator *emulator, const uint8_t *data, size_t size);
+
+// Resets the emulator, keeping the current ROM.
+// Returns 0 on success, or -1 if the emulator crashed.
+int32_t rugby_reset(RugbyEmulator *emulator);
+
+// Sets the buttons held from the next frame on, as a bitmask of RUGBY_BUTTON_*.
+void rugby_set_input(RugbyEmulator *emulator, uint8_t buttons);
+
+// Runs the emulator until the next frame is ready, about 1/60 of a second.
+// Returns 0 on success, or -1 if the emulator crashed, e.g. on an illegal
+// opcode. A crashed emulator doesn't run until it's reset or a state is loaded.
+int32_t rugby_run_frame(RugbyEmulator *emulator);
+
+// Returns the last frame, as width * height RGBA pixels (4 bytes each, row by
+// row), and writes its size. The frame is 160x144, or 256x224 with a Super
+// Game Boy border. The pointer is valid until the next call on the emulator.
+const uint8_t *rugby_framebuffer(const RugbyEmulator *emulator, size_t *width, size_t *height);
+
+// Changes the sample rate of the audio, 44100 Hz by default. Buffered samples are dropped.
+void rugby_set_sample_rate(RugbyEmulator *emulator, uint32_t sample_rate);
+
+// Enables or disables the audio. Emulation is faster without it.
+void rugby_set_audio_enabled(RugbyEmulator *emulator, bool enabled);
+
+// Moves up to max_samples buffered audio samples to a buffer, oldest first.
+// Samples are interleaved stereo floats from -1.0 to 1.0, and at most a
+// second of them is buffered. Returns how many samples were written.
+size_t rugby_drain_audio(RugbyEmulator *emulator, float *buffer, size_t max_samples);
+
+// Writes a save state to a buffer, if it's big enough, and returns its size.
+// Call it with a NULL buffer first to get the size.
+size_t rugby_save_state(RugbyEmulator *emulator, uint8_t *buffer, size_t size);
+
+// Loads a save state written by rugby_save_state, for the same ROM.
+// Returns 0 on success, or -1 if the state is invalid or the emulator crashed.
+int32_t rugby_load_state(RugbyEmulator *emulator, const uint8_t *data, size_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUGBY_H */
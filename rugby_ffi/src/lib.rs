@@ -0,0 +1,241 @@
+// A C API for embedding the emulator, with the header in include/rugby.h. The
+// emulator is an opaque pointer, and everything else is plain data: audio
+// samples are buffered and drained, instead of calling back into the host
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::slice;
+
+use rugby_core::color::PixelFormat;
+use rugby_core::emulator::Emulator;
+use rugby_core::input::Input;
+
+const MAX_BUFFERED_SECONDS: usize = 1; // Samples that aren't drained in time are dropped, oldest first
+
+/// The buttons of the Game Boy, combined into a bitmask for rugby_set_input
+pub const RUGBY_BUTTON_UP: u8 = 1 << 0;
+pub const RUGBY_BUTTON_DOWN: u8 = 1 << 1;
+pub const RUGBY_BUTTON_LEFT: u8 = 1 << 2;
+pub const RUGBY_BUTTON_RIGHT: u8 = 1 << 3;
+pub const RUGBY_BUTTON_A: u8 = 1 << 4;
+pub const RUGBY_BUTTON_B: u8 = 1 << 5;
+pub const RUGBY_BUTTON_START: u8 = 1 << 6;
+pub const RUGBY_BUTTON_SELECT: u8 = 1 << 7;
+
+/// An emulator, created with rugby_create and destroyed with rugby_destroy.
+/// It must only be used from one thread at a time.
+pub struct RugbyEmulator {
+	gb: Emulator,
+	input: Input,
+	samples: Rc<RefCell<Vec<f32>>>, // Interleaved stereo, until they are drained
+	sample_rate: u32,
+	crashed: bool, // The core panicked, so it can't run until it's reset or a state is loaded
+}
+
+impl RugbyEmulator {
+	fn new() -> Self {
+		let samples = Rc::new(RefCell::new(Vec::new()));
+		let callback_samples = Rc::clone(&samples);
+		let callback = Box::new(move |buffer: &[f32]| {
+			callback_samples.borrow_mut().extend_from_slice(buffer);
+		});
+		let mut gb = Emulator::new(None, None, callback);
+		gb.set_pixel_format(PixelFormat::RGBA8888);
+		gb.set_audio_channels(2);
+		let mut emulator = RugbyEmulator {
+			gb,
+			input: Input::new(),
+			samples,
+			sample_rate: 0,
+			crashed: false,
+		};
+		emulator.set_sample_rate(44100);
+		emulator
+	}
+
+	fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.sample_rate = sample_rate;
+		self.gb.set_sample_rate(sample_rate);
+		self.samples.borrow_mut().clear();
+	}
+
+	// Runs f, catching the panics of the core (e.g. on an illegal opcode), which
+	// must not unwind into C, and returns None if it panicked
+	fn guard<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Option<T> {
+		let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+		if result.is_err() {
+			self.crashed = true;
+		}
+		result.ok()
+	}
+
+	fn run_frame(&mut self) {
+		self.gb.run(self.input, None);
+		let max_samples = self.sample_rate as usize * 2 * MAX_BUFFERED_SECONDS;
+		let mut samples = self.samples.borrow_mut();
+		if samples.len() > max_samples {
+			let excess = samples.len() - max_samples;
+			samples.drain(..excess);
+		}
+	}
+}
+
+/// Creates an emulator with no ROM loaded, which shows a blank screen.
+#[no_mangle]
+pub extern "C" fn rugby_create() -> *mut RugbyEmulator {
+	Box::into_raw(Box::new(RugbyEmulator::new()))
+}
+
+/// Destroys an emulator. Does nothing if it's NULL.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_destroy(emulator: *mut RugbyEmulator) {
+	if !emulator.is_null() {
+		drop(Box::from_raw(emulator));
+	}
+}
+
+/// Loads a ROM, copied from memory, and starts it. The cartridge RAM is not
+/// saved to a file, but it's part of the save states.
+/// Returns 0 on success, or -1 if the ROM isn't supported.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_load_rom(emulator: *mut RugbyEmulator, data: *const u8, size: usize) -> i32 {
+	let emulator = &mut *emulator;
+	if data.is_null() || size < 0x150 {
+		return -1;
+	}
+	let rom = slice::from_raw_parts(data, size).to_vec();
+	match panic::catch_unwind(AssertUnwindSafe(|| emulator.gb.load(Some(rom), None))) {
+		Ok(()) => 0,
+		Err(_) => {
+			// The emulator may be left half loaded
+			*emulator = RugbyEmulator::new();
+			-1
+		},
+	}
+}
+
+/// Resets the emulator, keeping the current ROM.
+/// Returns 0 on success, or -1 if the emulator crashed.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_reset(emulator: *mut RugbyEmulator) -> i32 {
+	let emulator = &mut *emulator;
+	match emulator.guard(|emulator| emulator.gb.reset()) {
+		Some(()) => {
+			emulator.crashed = false;
+			0
+		},
+		None => -1,
+	}
+}
+
+/// Sets the buttons held from the next frame on, as a bitmask of RUGBY_BUTTON_*.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_set_input(emulator: *mut RugbyEmulator, buttons: u8) {
+	(*emulator).input = Input {
+		up: buttons & RUGBY_BUTTON_UP != 0,
+		down: buttons & RUGBY_BUTTON_DOWN != 0,
+		left: buttons & RUGBY_BUTTON_LEFT != 0,
+		right: buttons & RUGBY_BUTTON_RIGHT != 0,
+		a: buttons & RUGBY_BUTTON_A != 0,
+		b: buttons & RUGBY_BUTTON_B != 0,
+		start: buttons & RUGBY_BUTTON_START != 0,
+		select: buttons & RUGBY_BUTTON_SELECT != 0,
+	};
+}
+
+/// Runs the emulator until the next frame is ready, about 1/60 of a second.
+/// Returns 0 on success, or -1 if the emulator crashed, e.g. on an illegal
+/// opcode. A crashed emulator doesn't run until it's reset or a state is loaded.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_run_frame(emulator: *mut RugbyEmulator) -> i32 {
+	let emulator = &mut *emulator;
+	if emulator.crashed {
+		return -1;
+	}
+	match emulator.guard(RugbyEmulator::run_frame) {
+		Some(()) => 0,
+		None => -1,
+	}
+}
+
+/// Returns the last frame, as width * height RGBA pixels (4 bytes each, row by
+/// row), and writes its size. The frame is 160x144, or 256x224 with a Super
+/// Game Boy border. The pointer is valid until the next call on the emulator.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_framebuffer(emulator: *const RugbyEmulator, width: *mut usize, height: *mut usize) -> *const u8 {
+	let emulator = &*emulator;
+	let (frame_width, frame_height) = emulator.gb.framebuffer_size();
+	if !width.is_null() {
+		*width = frame_width;
+	}
+	if !height.is_null() {
+		*height = frame_height;
+	}
+	emulator.gb.framebuffer().as_ptr()
+}
+
+/// Changes the sample rate of the audio, 44100 Hz by default. Buffered samples are dropped.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_set_sample_rate(emulator: *mut RugbyEmulator, sample_rate: u32) {
+	if sample_rate > 0 {
+		(*emulator).set_sample_rate(sample_rate);
+	}
+}
+
+/// Enables or disables the audio. Emulation is faster without it.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_set_audio_enabled(emulator: *mut RugbyEmulator, enabled: bool) {
+	let emulator = &mut *emulator;
+	emulator.gb.set_audio_output(enabled);
+	if !enabled {
+		emulator.samples.borrow_mut().clear();
+	}
+}
+
+/// Moves up to max_samples buffered audio samples to a buffer, oldest first.
+/// Samples are interleaved stereo floats from -1.0 to 1.0, and at most a
+/// second of them is buffered. Returns how many samples were written.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_drain_audio(emulator: *mut RugbyEmulator, buffer: *mut f32, max_samples: usize) -> usize {
+	if buffer.is_null() {
+		return 0;
+	}
+	let mut samples = (*emulator).samples.borrow_mut();
+	let count = samples.len().min(max_samples);
+	slice::from_raw_parts_mut(buffer, count).copy_from_slice(&samples[..count]);
+	samples.drain(..count);
+	count
+}
+
+/// Writes a save state to a buffer, if it's big enough, and returns its size.
+/// Call it with a NULL buffer first to get the size.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_save_state(emulator: *mut RugbyEmulator, buffer: *mut u8, size: usize) -> usize {
+	let state = (*emulator).gb.get_save_string();
+	if !buffer.is_null() && size >= state.len() {
+		slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(state.as_bytes());
+	}
+	state.len()
+}
+
+/// Loads a save state written by rugby_save_state, for the same ROM.
+/// Returns 0 on success, or -1 if the state is invalid or the emulator crashed.
+#[no_mangle]
+pub unsafe extern "C" fn rugby_load_state(emulator: *mut RugbyEmulator, data: *const u8, size: usize) -> i32 {
+	let emulator = &mut *emulator;
+	if data.is_null() {
+		return -1;
+	}
+	let Ok(state) = std::str::from_utf8(slice::from_raw_parts(data, size)) else {
+		return -1;
+	};
+	match emulator.guard(|emulator| emulator.gb.load_state_string(state)) {
+		Some(Ok(())) => {
+			emulator.crashed = false;
+			0
+		},
+		Some(Err(_)) | None => -1,
+	}
+}
@@ -0,0 +1,488 @@
+// The exported functions are called by libretro frontends, following libretro.h
+#![allow(clippy::missing_safety_doc)]
+
+mod libretro;
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+use std::sync::OnceLock;
+
+use rugby_core::cheats::Cheat;
+use rugby_core::color::PixelFormat;
+use rugby_core::emulator::Emulator;
+use rugby_core::input::Input;
+use rugby_core::model::{Model, MODELS};
+use crate::libretro::*;
+
+const GB_WIDTH: usize = 160;
+const GB_HEIGHT: usize = 144;
+const SGB_WIDTH: usize = 256; // With the Super Gameboy border
+const SGB_HEIGHT: usize = 224;
+const GB_FRAME_RATE: f64 = 4194304.0 / 70224.0; // About 59.73 frames per second
+const SAMPLE_RATE: u32 = 48000;
+const AUDIO_BUFFER_SIZE: usize = 64; // In frames, small enough to send the samples of every frame right away
+const SERIALIZE_MARGIN: usize = 1024; // For the few parts of a snapshot whose size changes, like the objects of the current line
+const CRASH_MESSAGE_FRAMES: c_uint = 300;
+
+// The palettes of config.toml in the desktop version, with the default first
+const PALETTES: [(&str, [u32; 4]); 10] = [
+	("Hollow", [0xFAFBF6, 0xC6B7BE, 0x565A75, 0x0F0F1B]),
+	("Andrade", [0xE3EEC0, 0xAEBA89, 0x5E6745, 0x202020]),
+	("Moonlight_GB", [0x5FC75D, 0x36868F, 0x203671, 0x0F052D]),
+	("Monochrome", [0xFFFFFF, 0xB8B8B8, 0x686868, 0x000000]),
+	("Nostalgia", [0xD0D058, 0xA0A840, 0x708028, 0x405010]),
+	("BGB", [0xE0F8D0, 0x88C070, 0x346856, 0x081820]),
+	("Ice_Cream_GB", [0xFFF6D3, 0xF9A875, 0xEB6B6F, 0x7C3F58]),
+	("Octo", [0xFFCC00, 0xFF6600, 0x996600, 0x662200]),
+	("Mist_GB", [0xC4F0C2, 0x5AB9A8, 0x1E606E, 0x2D1B00]),
+	("2Bit_Demichrome", [0xE9EFEC, 0xA0A08B, 0x555568, 0x211E20]),
+];
+
+const MODEL_KEY: &CStr = c"rugby_model";
+const PALETTE_KEY: &CStr = c"rugby_palette";
+
+// The functions the frontend gives the core, before it loads a game
+#[derive(Clone, Copy, Default)]
+struct Callbacks {
+	environment: Option<RetroEnvironment>,
+	video_refresh: Option<RetroVideoRefresh>,
+	audio_sample_batch: Option<RetroAudioSampleBatch>,
+	input_poll: Option<RetroInputPoll>,
+	input_state: Option<RetroInputState>,
+}
+
+// The emulator, while a game is loaded
+struct Core {
+	gb: Emulator,
+	samples: Rc<RefCell<Vec<i16>>>, // Interleaved stereo, sent to the frontend after every frame
+	model: Option<Model>,
+	frame_size: (usize, usize),
+	serialize_size: usize, // Frontends expect the same size every time, so it's only measured when the game is reset
+	cheats: Vec<Cheat>,
+	crashed: bool, // The emulator panicked, so it can't run until it's reset or a state is loaded
+}
+
+// The frontend calls the core from a single thread
+thread_local! {
+	static CALLBACKS: Cell<Callbacks> = Cell::new(Callbacks::default());
+	static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn callbacks() -> Callbacks {
+	CALLBACKS.with(|callbacks| callbacks.get())
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+	callbacks().environment.is_some_and(|environment| environment(cmd, data))
+}
+
+// Returns the value of a core option, if the frontend has one
+fn get_variable(key: &CStr) -> Option<String> {
+	let mut variable = RetroVariable {
+		key: key.as_ptr(),
+		value: ptr::null(),
+	};
+	if !environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut RetroVariable as *mut c_void) || variable.value.is_null() {
+		return None;
+	}
+	unsafe { CStr::from_ptr(variable.value) }.to_str().ok().map(String::from)
+}
+
+// Declares the core options, with their description and values like "Model; Auto|Game Boy (DMG-0)|..."
+fn set_variables() {
+	static VALUES: OnceLock<[CString; 2]> = OnceLock::new();
+	let values = VALUES.get_or_init(|| {
+		let models: Vec<&str> = MODELS.iter().map(|model| model.name()).collect();
+		let palettes: Vec<&str> = PALETTES.iter().map(|(name, _)| *name).collect();
+		[
+			CString::new(format!("Model; Auto|{}", models.join("|"))).unwrap(),
+			CString::new(format!("Game Boy palette; {}", palettes.join("|"))).unwrap(),
+		]
+	});
+	let variables = [
+		RetroVariable { key: MODEL_KEY.as_ptr(), value: values[0].as_ptr() },
+		RetroVariable { key: PALETTE_KEY.as_ptr(), value: values[1].as_ptr() },
+		RetroVariable { key: ptr::null(), value: ptr::null() },
+	];
+	environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+}
+
+fn set_input_descriptors() {
+	let descriptor = |id, description: &'static CStr| RetroInputDescriptor {
+		port: 0,
+		device: RETRO_DEVICE_JOYPAD,
+		index: 0,
+		id,
+		description: description.as_ptr(),
+	};
+	let descriptors = [
+		descriptor(RETRO_DEVICE_ID_JOYPAD_UP, c"Up"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_DOWN, c"Down"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_LEFT, c"Left"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_RIGHT, c"Right"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_A, c"A"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_B, c"B"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_START, c"Start"),
+		descriptor(RETRO_DEVICE_ID_JOYPAD_SELECT, c"Select"),
+		RetroInputDescriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() },
+	];
+	environment(RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_ptr() as *mut c_void);
+}
+
+fn geometry(frame_size: (usize, usize)) -> RetroGameGeometry {
+	let (width, height) = frame_size;
+	RetroGameGeometry {
+		base_width: width as c_uint,
+		base_height: height as c_uint,
+		max_width: SGB_WIDTH as c_uint,
+		max_height: SGB_HEIGHT as c_uint,
+		aspect_ratio: width as f32 / height as f32,
+	}
+}
+
+impl Core {
+	fn new(rom: Vec<u8>) -> Self {
+		let samples = Rc::new(RefCell::new(Vec::new()));
+		let callback_samples = Rc::clone(&samples);
+		let callback = Box::new(move |buffer: &[f32]| {
+			let mut samples = callback_samples.borrow_mut();
+			samples.extend(buffer.iter().map(|&sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16));
+		});
+
+		// The frontend keeps the cartridge RAM, so there is no save file
+		let mut gb = Emulator::new(None, None, callback);
+		gb.set_pixel_format(PixelFormat::BGRA8888);
+		gb.set_sample_rate(SAMPLE_RATE);
+		gb.set_audio_buffer_size(AUDIO_BUFFER_SIZE);
+		gb.set_audio_channels(2);
+		let mut core = Core {
+			gb,
+			samples,
+			model: None,
+			frame_size: (GB_WIDTH, GB_HEIGHT),
+			serialize_size: 0,
+			cheats: Vec::new(),
+			crashed: false,
+		};
+		core.update_variables();
+		core.gb.load(Some(rom), None);
+		core.frame_size = core.gb.framebuffer_size();
+		core.update_serialize_size();
+		core
+	}
+
+	// Applies the core options. Changing the model resets the game
+	fn update_variables(&mut self) {
+		if let Some(value) = get_variable(MODEL_KEY) {
+			let model = MODELS.into_iter().find(|model| model.name() == value);
+			if model != self.model {
+				self.model = model;
+				self.gb.set_model(model);
+				self.gb.reset();
+				self.update_serialize_size();
+			}
+		}
+		let palette = get_variable(PALETTE_KEY)
+			.and_then(|value| PALETTES.iter().find(|(name, _)| *name == value))
+			.unwrap_or(&PALETTES[0]);
+		self.gb.set_palette(palette.1.map(|color| {
+			let [_, r, g, b] = color.to_be_bytes();
+			[r, g, b]
+		}));
+	}
+
+	// Runs f, catching the panics of the emulator (e.g. on an illegal opcode),
+	// which must not unwind into the frontend, and returns None if it panicked
+	fn guard<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Option<T> {
+		let result = panic::catch_unwind(AssertUnwindSafe(|| f(self)));
+		if result.is_err() {
+			self.crashed = true;
+			let mut message = RetroMessage {
+				msg: c"The game crashed, reset it or load a state".as_ptr(),
+				frames: CRASH_MESSAGE_FRAMES,
+			};
+			environment(RETRO_ENVIRONMENT_SET_MESSAGE, &mut message as *mut RetroMessage as *mut c_void);
+		}
+		result.ok()
+	}
+
+	// A crashed game keeps showing its last frame
+	fn run(&mut self) {
+		let callbacks = callbacks();
+		let mut updated = false;
+		if environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+			self.guard(Core::update_variables);
+		}
+
+		if let Some(input_poll) = callbacks.input_poll {
+			input_poll();
+		}
+		let pressed = |id| callbacks.input_state.is_some_and(|input_state| input_state(0, RETRO_DEVICE_JOYPAD, 0, id) != 0);
+		let input = Input {
+			up: pressed(RETRO_DEVICE_ID_JOYPAD_UP),
+			down: pressed(RETRO_DEVICE_ID_JOYPAD_DOWN),
+			left: pressed(RETRO_DEVICE_ID_JOYPAD_LEFT),
+			right: pressed(RETRO_DEVICE_ID_JOYPAD_RIGHT),
+			a: pressed(RETRO_DEVICE_ID_JOYPAD_A),
+			b: pressed(RETRO_DEVICE_ID_JOYPAD_B),
+			start: pressed(RETRO_DEVICE_ID_JOYPAD_START),
+			select: pressed(RETRO_DEVICE_ID_JOYPAD_SELECT),
+		};
+		if !self.crashed {
+			self.guard(|core| {
+				core.gb.run(input, None);
+			});
+		}
+
+		// The Super Gameboy border changes the size of the frame
+		let frame_size = self.gb.framebuffer_size();
+		if frame_size != self.frame_size {
+			self.frame_size = frame_size;
+			let mut geometry = geometry(frame_size);
+			environment(RETRO_ENVIRONMENT_SET_GEOMETRY, &mut geometry as *mut RetroGameGeometry as *mut c_void);
+		}
+		if let Some(video_refresh) = callbacks.video_refresh {
+			let (width, height) = frame_size;
+			video_refresh(self.gb.framebuffer().as_ptr() as *const c_void, width as c_uint, height as c_uint, width * 4);
+		}
+
+		// The frontend may take the samples in more than one batch
+		let samples = std::mem::take(&mut *self.samples.borrow_mut());
+		if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+			let mut frames = samples.chunks_exact(2).len();
+			let mut sent = 0;
+			while frames > 0 {
+				let batch = audio_sample_batch(samples[sent * 2..].as_ptr(), frames);
+				if batch == 0 {
+					break;
+				}
+				sent += batch.min(frames);
+				frames -= batch.min(frames);
+			}
+		}
+	}
+
+	// The Super Gameboy makes snapshots larger, so the size depends on the model
+	fn update_serialize_size(&mut self) {
+		self.serialize_size = self.gb.snapshot().len() + SERIALIZE_MARGIN;
+	}
+
+	// The state is a binary snapshot, padded with zeros to the same size every time
+	fn serialize(&mut self, buffer: &mut [u8]) -> bool {
+		let snapshot = self.gb.snapshot();
+		if snapshot.len() > buffer.len() {
+			return false;
+		}
+		buffer[..snapshot.len()].copy_from_slice(&snapshot);
+		buffer[snapshot.len()..].fill(0);
+		true
+	}
+
+	// The padding is ignored, since the snapshot starts with its length
+	fn unserialize(&mut self, buffer: &[u8]) -> bool {
+		match self.guard(|core| core.gb.restore_snapshot(buffer)) {
+			Some(Ok(())) => {
+				self.crashed = false;
+				true
+			},
+			Some(Err(_)) | None => false,
+		}
+	}
+
+	fn reset(&mut self) {
+		if self.guard(|core| core.gb.reset()).is_some() {
+			self.crashed = false;
+		}
+	}
+}
+
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+	CORE.with(|core| core.borrow_mut().as_mut().map_or(default, f))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+	RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+	CALLBACKS.with(|callbacks| callbacks.set(Callbacks { environment: Some(callback), ..callbacks.get() }));
+	set_variables();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+	CALLBACKS.with(|callbacks| callbacks.set(Callbacks { video_refresh: Some(callback), ..callbacks.get() }));
+}
+
+// Samples are only sent in batches
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+	CALLBACKS.with(|callbacks| callbacks.set(Callbacks { audio_sample_batch: Some(callback), ..callbacks.get() }));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+	CALLBACKS.with(|callbacks| callbacks.set(Callbacks { input_poll: Some(callback), ..callbacks.get() }));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+	CALLBACKS.with(|callbacks| callbacks.set(Callbacks { input_state: Some(callback), ..callbacks.get() }));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+	CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+	*info = RetroSystemInfo {
+		library_name: c"Rugby".as_ptr(),
+		library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+		valid_extensions: c"gb|gbc|sgb".as_ptr(),
+		need_fullpath: false,
+		block_extract: false,
+	};
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+	let frame_size = with_core((GB_WIDTH, GB_HEIGHT), |core| core.frame_size);
+	*info = RetroSystemAvInfo {
+		geometry: geometry(frame_size),
+		timing: RetroSystemTiming {
+			fps: GB_FRAME_RATE,
+			sample_rate: SAMPLE_RATE as f64,
+		},
+	};
+}
+
+// Only the joypad is supported
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+	with_core((), |core| core.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+	with_core((), |core| core.run());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+	with_core(0, |core| core.serialize_size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+	let buffer = slice::from_raw_parts_mut(data as *mut u8, size);
+	with_core(false, |core| core.serialize(buffer))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+	let buffer = slice::from_raw_parts(data as *const u8, size);
+	with_core(false, |core| core.unserialize(buffer))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+	with_core((), |core| {
+		core.cheats.clear();
+		core.gb.set_cheats(&core.cheats);
+	});
+}
+
+// Codes are GameShark or Game Genie codes, joined with + like in the cheat files
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+	if code.is_null() {
+		return;
+	}
+	let code = CStr::from_ptr(code).to_string_lossy().into_owned();
+	with_core((), |core| {
+		let index = index as usize;
+		if core.cheats.len() <= index {
+			core.cheats.resize(index + 1, Cheat { name: String::new(), code: String::new(), enabled: false });
+		}
+		core.cheats[index] = Cheat { name: String::new(), code, enabled };
+		core.gb.set_cheats(&core.cheats);
+	});
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+	if game.is_null() || (*game).data.is_null() {
+		return false;
+	}
+	let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+	if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut pixel_format as *mut c_uint as *mut c_void) {
+		return false;
+	}
+	set_input_descriptors();
+
+	// Don't bring the frontend down with an unsupported cartridge
+	let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+	match panic::catch_unwind(AssertUnwindSafe(|| Core::new(rom))) {
+		Ok(core) => {
+			CORE.with(|c| *c.borrow_mut() = Some(core));
+			true
+		},
+		Err(_) => false,
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+	false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+	CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+	RETRO_REGION_NTSC
+}
+
+// The cartridge RAM, which the frontend saves and loads itself
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+	match id {
+		RETRO_MEMORY_SAVE_RAM => with_core(ptr::null_mut(), |core| {
+			let ram = core.gb.battery_ram();
+			match ram.is_empty() {
+				true => ptr::null_mut(),
+				false => ram.as_mut_ptr() as *mut c_void,
+			}
+		}),
+		_ => ptr::null_mut(),
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+	match id {
+		RETRO_MEMORY_SAVE_RAM => with_core(0, |core| core.gb.battery_ram().len()),
+		_ => 0,
+	}
+}
@@ -0,0 +1,188 @@
+// A Python module for training agents and research: the emulator runs
+// without audio or a window, as fast as it can, and its state is cloned
+// and restored in memory for tree search
+use std::panic::{self, AssertUnwindSafe};
+
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use rugby_core::color::PixelFormat;
+use rugby_core::emulator::Emulator as Core;
+use rugby_core::input::Input;
+use rugby_core::model::{Model, MODELS};
+
+// The buttons of the Game Boy, combined into a bitmask for step()
+const BUTTONS: [(&str, u8); 8] = [("UP", 1 << 0), ("DOWN", 1 << 1), ("LEFT", 1 << 2), ("RIGHT", 1 << 3),
+								  ("A", 1 << 4), ("B", 1 << 5), ("START", 1 << 6), ("SELECT", 1 << 7)];
+const WRAM_START: u16 = 0xC000;
+const WRAM_SIZE: usize = 0x2000;
+
+fn input(buttons: u8) -> Input {
+	let pressed = |name: &str| BUTTONS.iter().any(|(button, bit)| *button == name && buttons & bit != 0);
+	Input {
+		up: pressed("UP"),
+		down: pressed("DOWN"),
+		left: pressed("LEFT"),
+		right: pressed("RIGHT"),
+		a: pressed("A"),
+		b: pressed("B"),
+		start: pressed("START"),
+		select: pressed("SELECT"),
+	}
+}
+
+// Models are named like their variants, e.g. "DMG" or "CGB"
+fn model(name: &str) -> PyResult<Model> {
+	MODELS.into_iter()
+		.find(|model| format!("{:?}", model) == name)
+		.ok_or_else(|| PyValueError::new_err(format!("Unknown model {}", name)))
+}
+
+/// Emulator(rom, model=None)
+///
+/// A Game Boy running a ROM, given as bytes. The model is one of "DMG0", "DMG",
+/// "MGB", "SGB", "CGB" or "AGB", or None to pick it from the ROM's header.
+/// An emulator must stay in the thread that created it, so agents running in
+/// parallel should use one process per emulator.
+#[pyclass(unsendable)]
+struct Emulator {
+	gb: Core,
+}
+
+#[pymethods]
+impl Emulator {
+	#[new]
+	#[pyo3(signature = (rom, model=None))]
+	fn new(rom: &[u8], model: Option<&str>) -> PyResult<Self> {
+		let model = model.map(self::model).transpose()?;
+		if rom.len() < 0x150 {
+			return Err(PyValueError::new_err("The ROM is too small"));
+		}
+		let mut gb = Core::new(None, None, Box::new(|_| {}));
+		gb.set_audio_output(false);
+		gb.set_pixel_format(PixelFormat::RGBA8888);
+		gb.set_model(model);
+		let rom = rom.to_vec();
+		panic::catch_unwind(AssertUnwindSafe(|| gb.load(Some(rom), None)))
+			.map_err(|_| PyValueError::new_err("The ROM isn't supported"))?;
+		Ok(Emulator { gb })
+	}
+
+	/// step(buttons=0, frames=1)
+	///
+	/// Runs the given number of frames, about 1/60 of a second each, holding
+	/// the buttons, a bitmask of rugby.UP, rugby.A, etc.
+	#[pyo3(signature = (buttons=0, frames=1))]
+	fn step(&mut self, buttons: u8, frames: usize) {
+		let input = input(buttons);
+		for _ in 0..frames {
+			self.gb.run(input, None);
+		}
+	}
+
+	/// Resets the Game Boy, keeping the ROM.
+	fn reset(&mut self) {
+		self.gb.reset();
+	}
+
+	/// screen()
+	///
+	/// Returns the last frame as a NumPy array of RGB pixels, with a shape of
+	/// (144, 160, 3), or (224, 256, 3) with the border of the Super Game Boy.
+	fn screen<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray3<u8>> {
+		let (width, height) = self.gb.framebuffer_size();
+		let pixels: Vec<u8> = self.gb.framebuffer()
+			.chunks_exact(4)
+			.flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+			.collect();
+		Array3::from_shape_vec((height, width, 3), pixels).unwrap().into_pyarray(py)
+	}
+
+	/// ram()
+	///
+	/// Returns the 8 KiB of work RAM (0xC000 to 0xDFFF), where games keep most
+	/// of their variables, with the bank selected at the time on a CGB.
+	fn ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+		PyBytes::new_with(py, WRAM_SIZE, |bytes| {
+			for (offset, byte) in bytes.iter_mut().enumerate() {
+				*byte = self.gb.peek(WRAM_START + offset as u16);
+			}
+			Ok(())
+		}).unwrap()
+	}
+
+	/// read(address, size=1)
+	///
+	/// Reads any memory like a debugger, wrapping around after 0xFFFF.
+	#[pyo3(signature = (address, size=1))]
+	fn read<'py>(&self, py: Python<'py>, address: u16, size: usize) -> Bound<'py, PyBytes> {
+		let bytes: Vec<u8> = (0..size).map(|offset| self.gb.peek(address.wrapping_add(offset as u16))).collect();
+		PyBytes::new(py, &bytes)
+	}
+
+	/// write(address, data)
+	///
+	/// Writes bytes to memory like a debugger, e.g. to set up a scenario.
+	fn write(&mut self, address: u16, data: &[u8]) {
+		for (offset, value) in data.iter().enumerate() {
+			self.gb.poke(address.wrapping_add(offset as u16), *value);
+		}
+	}
+
+	/// registers()
+	///
+	/// Returns the CPU registers as a dict, from "a" to "l", "sp" and "pc".
+	fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+		let r = self.gb.registers();
+		let registers = PyDict::new(py);
+		for (name, value) in [("a", r.a as u16), ("f", r.f as u16), ("b", r.b as u16), ("c", r.c as u16),
+							  ("d", r.d as u16), ("e", r.e as u16), ("h", r.h as u16), ("l", r.l as u16),
+							  ("sp", r.sp), ("pc", r.pc)] {
+			registers.set_item(name, value)?;
+		}
+		Ok(registers)
+	}
+
+	/// snapshot()
+	///
+	/// Returns the whole state of the emulator, in a compact binary format that
+	/// is fast to create and restore, e.g. for tree search. It's only valid for
+	/// the same ROM and version of the module; use save_state() to keep it.
+	fn snapshot<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+		PyBytes::new(py, &self.gb.snapshot())
+	}
+
+	/// restore(snapshot)
+	///
+	/// Restores a state returned by snapshot(), and the screen at the time.
+	fn restore(&mut self, snapshot: &[u8]) -> PyResult<()> {
+		self.gb.restore_snapshot(snapshot).map_err(PyValueError::new_err)
+	}
+
+	/// save_state()
+	///
+	/// Returns a save state, in the same format as the other frontends.
+	fn save_state<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+		PyBytes::new(py, self.gb.get_save_string().as_bytes())
+	}
+
+	/// load_state(state)
+	///
+	/// Loads a state returned by save_state(), for the same ROM.
+	fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+		let state = std::str::from_utf8(state).map_err(|e| PyValueError::new_err(e.to_string()))?;
+		self.gb.load_state_string(state).map_err(PyValueError::new_err)
+	}
+}
+
+#[pymodule]
+fn rugby(module: &Bound<'_, PyModule>) -> PyResult<()> {
+	module.add_class::<Emulator>()?;
+	for (name, bit) in BUTTONS {
+		module.add(name, bit)?;
+	}
+	Ok(())
+}
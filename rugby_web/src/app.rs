@@ -9,14 +9,18 @@ use std::io::Read;
 use std::sync::mpsc::{Receiver, Sender};
 use std::path::PathBuf;
 
-use rugby_core::color::Color as OutputColor;
-use rugby_core::color::LogicalColor;
+use rugby_core::colorization::PALETTE_NAMES;
+use rugby_core::model::{Model, MODELS};
 use rugby_core::emulator::Emulator;
+use rugby_core::filter::{ColorCorrection, ScreenTint, FrameBlending};
+use rugby_core::filter::{COLOR_CORRECTIONS, SCREEN_TINTS, FRAME_BLENDINGS};
 use rugby_core::input::Input;
 use rugby_core::input::EmulatorInput;
 
 const GB_WIDTH: usize = 160;
 const GB_HEIGHT: usize = 144;
+const SGB_WIDTH: usize = 256; // With the Super Gameboy border
+const SGB_HEIGHT: usize = 224;
 const MENUBAR_HEIGHT: f32 = 20.0;
 
 #[derive(Clone, PartialEq)]
@@ -100,6 +104,8 @@ pub struct EguiApp {
 	palette_index: usize,
 
 	scale: f32,
+	display_size: (usize, usize), // The size of the screen, with the border if there is one
+	screen_texture: Option<egui::TextureHandle>, // Updated with every frame, instead of allocating a new one
 	emulator_playing: bool,
 	exit_program: bool,
 	active_layers: [bool; 3],
@@ -108,6 +114,14 @@ pub struct EguiApp {
 	audio_on: bool,
 	active_channels: [bool; 4],
 	toggle_channels: [bool; 4],
+	band_limited_audio: bool,
+	fifo_renderer: bool,
+	model: Option<Model>, // None picks the model from the game's header
+	colorization_palette: Option<usize>, // None picks the palette from the game's title
+	color_correction: ColorCorrection,
+	screen_tint: ScreenTint,
+	frame_blending: FrameBlending,
+	pixel_grid: bool,
 	show_palette_window: bool,
 	select_save_state: (bool, usize),
 	select_load_state: (bool, usize),
@@ -119,7 +133,7 @@ pub struct EguiApp {
 impl EguiApp {
 	pub fn new(cc: &eframe::CreationContext<'_>, palettes: Vec<Palette>, scale: Box<f32>,
 			   file_arg: Option<String>, callback: Box<dyn Fn(&[f32])>) -> Self {
-		let gb = match file_arg {
+		let mut gb = match file_arg {
 			Some(s) => {
 				let path_buf = PathBuf::from(s);
 				let mut rom = File::open(path_buf.clone()).expect("Unable to open file {path}");
@@ -132,6 +146,27 @@ impl EguiApp {
 		
 		let recent_roms = eframe::get_value(cc.storage.unwrap(), "recent_roms").unwrap_or_default();
 		let palette_index = 0;
+		let band_limited_audio = eframe::get_value(cc.storage.unwrap(), "band_limited_audio").unwrap_or(true);
+		gb.set_band_limited_audio(band_limited_audio);
+		let fifo_renderer = eframe::get_value(cc.storage.unwrap(), "fifo_renderer").unwrap_or(false);
+		gb.set_fifo_renderer(fifo_renderer);
+		let model = eframe::get_value(cc.storage.unwrap(), "model").unwrap_or(None);
+		let colorization_palette = eframe::get_value(cc.storage.unwrap(), "colorization_palette").unwrap_or(None);
+		let color_correction = eframe::get_value(cc.storage.unwrap(), "color_correction").unwrap_or(ColorCorrection::Off);
+		let screen_tint = eframe::get_value(cc.storage.unwrap(), "screen_tint").unwrap_or(ScreenTint::Off);
+		let frame_blending = eframe::get_value(cc.storage.unwrap(), "frame_blending").unwrap_or(FrameBlending::Off);
+		let pixel_grid = eframe::get_value(cc.storage.unwrap(), "pixel_grid").unwrap_or(false);
+		gb.set_palette(palettes[palette_index].colors.map(|(r, g, b)| [r, g, b]));
+		gb.set_color_correction(color_correction);
+		gb.set_screen_tint(screen_tint);
+		gb.set_frame_blending(frame_blending);
+		gb.set_pixel_grid(pixel_grid.then_some(*scale as usize));
+		gb.set_model(model);
+		gb.set_colorization_palette(colorization_palette);
+		if model.is_some() {
+			// The ROM was loaded before the model was selected
+			gb.reset();
+		}
 		
 		EguiApp {
 			gb,
@@ -139,6 +174,8 @@ impl EguiApp {
 			palette_index,
 
 			scale: *scale,
+			display_size: (GB_WIDTH, GB_HEIGHT),
+			screen_texture: None,
 			emulator_playing: true,
 			exit_program: false,
 			active_layers: [true; 3],
@@ -147,6 +184,14 @@ impl EguiApp {
 			audio_on: true,
 			active_channels: [true; 4],
 			toggle_channels: [false; 4],
+			band_limited_audio,
+			fifo_renderer,
+			model,
+			colorization_palette,
+			color_correction,
+			screen_tint,
+			frame_blending,
+			pixel_grid,
 			show_palette_window: false,
 			select_save_state: (false, 0),
 			select_load_state: (false, 0),
@@ -228,28 +273,24 @@ impl EguiApp {
 		(input, emulator_input)
 	}
 
-	// Returns a RGB color from the emulator's logical color
-	fn get_color(&self, color: &OutputColor) -> (u8, u8, u8) {
-		let i = self.palette_index;
-		match color {
-			OutputColor::Logical(logical) => match logical {
-				LogicalColor::White => self.palettes[i].colors[0],
-				LogicalColor::LightGray => self.palettes[i].colors[1],
-				LogicalColor::DarkGray => self.palettes[i].colors[2],
-				LogicalColor::Black => self.palettes[i].colors[3],
-			},
-			OutputColor::RGB(rgb) => {
-				let r_5bit = (rgb & 0x1F) as u8;
-				let r_8bit = (r_5bit << 3) | (r_5bit >> 2);
-				
-				let g_5bit = ((rgb & 0x3E0) >> 5) as u8;
-				let g_8bit = (g_5bit << 3) | (g_5bit >> 2);
-				
-				let b_5bit = ((rgb & 0x7C00) >> 10) as u8;
-				let b_8bit = (b_5bit << 3) | (b_5bit >> 2);
-				(r_8bit, g_8bit, b_8bit)
-			}
-		}
+	// Fits the window to the screen at the current scale
+	fn resize_window(&self, ctx: &egui::Context) {
+		let (width, height) = self.display_size;
+		ctx.send_viewport_cmd(ViewportCommand::InnerSize(
+			Vec2::new((width as f32 * self.scale) / ctx.pixels_per_point()
+					  , (height as f32 * self.scale) / ctx.pixels_per_point()
+					  + (MENUBAR_HEIGHT + 1.5 * self.scale))));
+	}
+
+	// Sends the colors of the selected palette to the emulator
+	fn apply_palette(&mut self) {
+		let colors = self.palettes[self.palette_index].colors;
+		self.gb.set_palette(colors.map(|(r, g, b)| [r, g, b]));
+	}
+
+	// The pixel grid is drawn at the scale of the window
+	fn update_pixel_grid(&mut self) {
+		self.gb.set_pixel_grid(self.pixel_grid.then_some(self.scale as usize));
 	}
 	
 }
@@ -277,22 +318,22 @@ impl eframe::App for EguiApp {
 		});
 
 		// Run the emulator for a frame
-		let screen = match self.emulator_playing {
-			true => self.gb.run(input, Some(emulator_input)).clone(),
-			false => self.gb.get_screen().clone(),
-		};
+		if self.emulator_playing {
+			self.gb.run(input, Some(emulator_input));
+		}
 
 
-		let mut buffer: Vec<u8> = Vec::with_capacity(GB_WIDTH * GB_HEIGHT * 4);
-		for y in 0..GB_HEIGHT {
-			for x in 0..GB_WIDTH {
-				let color = screen[y][x];
-				let (r, g, b) = self.get_color(&color);
-				buffer.push(r);
-				buffer.push(g);
-				buffer.push(b);
-			}
+		// The Super Gameboy draws the screen inside a border
+		let display_size = match self.gb.get_sgb_frame() {
+			Some(_) => (SGB_WIDTH, SGB_HEIGHT),
+			None => (GB_WIDTH, GB_HEIGHT),
+		};
+		if display_size != self.display_size {
+			self.display_size = display_size;
+			self.resize_window(ctx);
 		}
+		let (width, height) = display_size;
+		let palette_index = self.palette_index;
 
 		// Menu bar
 		egui::TopBottomPanel::top("Menu bar").show(ctx, |ui| {
@@ -336,6 +377,18 @@ impl eframe::App for EguiApp {
 				// Options
 				ui.menu_button("Options", |ui| {
 					ui.checkbox(&mut self.emulator_playing, "Pause/Resume");
+					ui.menu_button("Model", |ui| {
+						if ui.radio_value(&mut self.model, None, "Automatic").clicked() {
+							self.gb.set_model(None);
+							self.gb.reset();
+						}
+						for model in MODELS {
+							if ui.radio_value(&mut self.model, Some(model), model.name()).clicked() {
+								self.gb.set_model(Some(model));
+								self.gb.reset();
+							}
+						}
+					});
 				});
 				// Video
 				ui.menu_button("Video", |ui| {
@@ -344,10 +397,8 @@ impl eframe::App for EguiApp {
 							if ui.radio_value(&mut self.scale,
 											  i as f32, format!("{}x", i)).clicked() {
 								self.scale = i as f32;
-								ctx.send_viewport_cmd(ViewportCommand::InnerSize(
-									Vec2::new((GB_WIDTH as f32 * self.scale) / ctx.pixels_per_point()
-											  , (GB_HEIGHT as f32 * self.scale) / ctx.pixels_per_point()
-											  + (MENUBAR_HEIGHT + 1.5 * self.scale))));
+								self.resize_window(ctx);
+								self.update_pixel_grid();
 							}
 						}
 					});
@@ -359,6 +410,40 @@ impl eframe::App for EguiApp {
 							}
 						}
 					});
+					ui.menu_button("Colorization", |ui| {
+						if ui.radio_value(&mut self.colorization_palette, None, "Automatic").clicked() {
+							self.gb.set_colorization_palette(None);
+						}
+						for (i, name) in PALETTE_NAMES.iter().enumerate() {
+							if ui.radio_value(&mut self.colorization_palette, Some(i), *name).clicked() {
+								self.gb.set_colorization_palette(Some(i));
+							}
+						}
+					});
+					ui.menu_button("Color Correction", |ui| {
+						for color_correction in COLOR_CORRECTIONS {
+							if ui.radio_value(&mut self.color_correction, color_correction, color_correction.name()).clicked() {
+								self.gb.set_color_correction(color_correction);
+							}
+						}
+					});
+					ui.menu_button("Screen Tint", |ui| {
+						for screen_tint in SCREEN_TINTS {
+							if ui.radio_value(&mut self.screen_tint, screen_tint, screen_tint.name()).clicked() {
+								self.gb.set_screen_tint(screen_tint);
+							}
+						}
+					});
+					ui.menu_button("Frame Blending", |ui| {
+						for frame_blending in FRAME_BLENDINGS {
+							if ui.radio_value(&mut self.frame_blending, frame_blending, frame_blending.name()).clicked() {
+								self.gb.set_frame_blending(frame_blending);
+							}
+						}
+					});
+					if ui.checkbox(&mut self.pixel_grid, "Pixel Grid").clicked() {
+						self.update_pixel_grid();
+					}
 					if ui.button("Palette Picker").clicked() {
 						self.show_palette_window = !self.show_palette_window;
 					}
@@ -370,6 +455,9 @@ impl eframe::App for EguiApp {
 							}
 						}
 					});
+					if ui.checkbox(&mut self.fifo_renderer, "Pixel FIFO Renderer").clicked() {
+						self.gb.set_fifo_renderer(self.fifo_renderer);
+					}
 				});
 				// Audio
 				ui.menu_button("Audio", |ui| {
@@ -384,6 +472,9 @@ impl eframe::App for EguiApp {
 							}
 						}
 					});
+					if ui.checkbox(&mut self.band_limited_audio, "Band-limited Synthesis").clicked() {
+						self.gb.set_band_limited_audio(self.band_limited_audio);
+					}
 				});
 				// Save
 				ui.menu_button("Save", |ui| {
@@ -409,12 +500,17 @@ impl eframe::App for EguiApp {
 		});
 		
 		egui::CentralPanel::default().frame(Frame::none()).show(ctx, |ui| {
-			let size = [GB_WIDTH as _, GB_HEIGHT as _];
-			let image = egui::ColorImage::from_rgb(size, &buffer);
-		   
-			let texture_handle = ui.ctx().load_texture("Game screen", image, egui::TextureOptions::NEAREST);
-			let scaled_size = Vec2::new((GB_WIDTH as f32 * self.scale) / ctx.pixels_per_point(),
-										(GB_HEIGHT as f32 * self.scale) / ctx.pixels_per_point());
+			let (image_width, image_height) = self.gb.framebuffer_size();
+			let image = egui::ColorImage::from_rgba_unmultiplied([image_width, image_height], self.gb.framebuffer());
+			let texture_handle = match &mut self.screen_texture {
+				Some(texture_handle) => {
+					texture_handle.set(image, egui::TextureOptions::NEAREST);
+					texture_handle
+				},
+				None => self.screen_texture.insert(ui.ctx().load_texture("Game screen", image, egui::TextureOptions::NEAREST)),
+			};
+			let scaled_size = Vec2::new((width as f32 * self.scale) / ctx.pixels_per_point(),
+										(height as f32 * self.scale) / ctx.pixels_per_point());
 			ui.centered_and_justified(|ui| {
 				ui.image((texture_handle.id(), scaled_size));
 			});
@@ -445,12 +541,23 @@ impl eframe::App for EguiApp {
 					}
 				})
 			});
+		if self.palette_index != palette_index {
+			self.apply_palette();
+		}
 		ctx.request_repaint();
 	}
 	
 	fn save(&mut self, storage: &mut dyn Storage) {
 		eframe::set_value(storage, "recent_roms", &self.recent_roms);
 		eframe::set_value(storage, "palette", &self.palettes[self.palette_index].name);
+		eframe::set_value(storage, "band_limited_audio", &self.band_limited_audio);
+		eframe::set_value(storage, "fifo_renderer", &self.fifo_renderer);
+		eframe::set_value(storage, "model", &self.model);
+		eframe::set_value(storage, "colorization_palette", &self.colorization_palette);
+		eframe::set_value(storage, "color_correction", &self.color_correction);
+		eframe::set_value(storage, "screen_tint", &self.screen_tint);
+		eframe::set_value(storage, "frame_blending", &self.frame_blending);
+		eframe::set_value(storage, "pixel_grid", &self.pixel_grid);
 	}
 
 }